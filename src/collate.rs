@@ -0,0 +1,453 @@
+//! CouchDB collation, as implemented by
+//! [pouchdb-collate](https://github.com/pouchdb/pouchdb/tree/master/packages/node_modules/pouchdb-collate).
+//!
+//! Values sort in the order `null`, booleans, numbers, strings, arrays and objects. Arrays
+//! and objects are compared element by element. Strings are compared by their UTF-16 code
+//! units, like PouchDB does (CouchDB uses ICU collation instead).
+//!
+//! [to_indexable_string] encodes a value so that plain string comparison of the encoded
+//! values yields the same order as [collate], which is useful for building sortable ids.
+//! The encoding is identical to the one of pouchdb-collate.
+
+use serde_json::{Map, Number, Value};
+use std::{cmp::Ordering, fmt, iter::Peekable, str::Chars};
+
+const MIN_MAGNITUDE: i32 = -324;
+const MAGNITUDE_DIGITS: usize = 3;
+
+/// Compare two values using CouchDB collation.
+pub fn collate(a: &Value, b: &Value) -> Ordering {
+    let (ai, bi) = (collation_index(a), collation_index(b));
+    if ai != bi {
+        return ai.cmp(&bi);
+    }
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => {
+            number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => string_collate(a, b),
+        (Value::Array(a), Value::Array(b)) => array_collate(a, b),
+        (Value::Object(a), Value::Object(b)) => object_collate(a, b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn collation_index(value: &Value) -> u8 {
+    match value {
+        Value::Null => 1,
+        Value::Bool(_) => 2,
+        Value::Number(_) => 3,
+        Value::String(_) => 4,
+        Value::Array(_) => 5,
+        Value::Object(_) => 6,
+    }
+}
+
+fn number(number: &Number) -> f64 {
+    number.as_f64().unwrap_or(0.0)
+}
+
+pub(crate) fn string_collate(a: &str, b: &str) -> Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+fn array_collate(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| collate(a, b))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+fn object_collate(a: &Map<String, Value>, b: &Map<String, Value>) -> Ordering {
+    js_keys(a)
+        .into_iter()
+        .zip(js_keys(b))
+        .map(|(ak, bk)| string_collate(ak, bk).then_with(|| collate(&a[ak], &b[bk])))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// The keys of an object in the order JavaScript's `Object.keys` returns them: array
+/// indices in ascending order first, then all other keys in insertion order.
+pub(crate) fn js_keys(object: &Map<String, Value>) -> Vec<&str> {
+    let is_index = |key: &str| {
+        key.parse::<u32>()
+            .map(|index| index != u32::MAX && index.to_string() == key)
+            .unwrap_or(false)
+    };
+    let mut indices: Vec<&str> = object
+        .keys()
+        .map(String::as_str)
+        .filter(|key| is_index(key))
+        .collect();
+    indices.sort_by_key(|key| key.parse::<u32>().unwrap_or(0));
+    indices.extend(
+        object
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !is_index(key)),
+    );
+    indices
+}
+
+/// Encode a value into a string that sorts like the value does according to [collate].
+pub fn to_indexable_string(value: &Value) -> String {
+    let mut result = String::new();
+    write_indexable(value, &mut result);
+    result
+}
+
+fn write_indexable(value: &Value, result: &mut String) {
+    result.push((b'0' + collation_index(value)) as char);
+    match value {
+        Value::Null => {}
+        Value::Bool(value) => result.push(if *value { '1' } else { '0' }),
+        Value::Number(value) => result.push_str(&number_to_indexable_string(number(value))),
+        Value::String(value) => {
+            for c in value.chars() {
+                match c {
+                    '\u{2}' => result.push_str("\u{2}\u{2}"),
+                    '\u{1}' => result.push_str("\u{1}\u{2}"),
+                    '\u{0}' => result.push_str("\u{1}\u{1}"),
+                    c => result.push(c),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                write_indexable(value, result);
+            }
+        }
+        Value::Object(object) => {
+            for key in js_keys(object) {
+                write_indexable(&Value::String(key.to_owned()), result);
+                write_indexable(&object[key], result);
+            }
+        }
+    }
+    result.push('\u{0}');
+}
+
+// Numbers are encoded as `x yyy zz...zz`, where `x` is 0 for negative numbers, 1 for zero
+// and 2 for positive numbers, `yyy` is the exponent (negated for negative numbers), moved
+// so that it's not negative, and `zz...zz` is the mantissa (subtracted from 10 for
+// negative numbers).
+fn number_to_indexable_string(number: f64) -> String {
+    if number == 0.0 {
+        return "1".to_owned();
+    }
+    let exponential = format!("{:e}", number);
+    let (mantissa, magnitude) = exponential.split_at(exponential.find('e').unwrap_or(0));
+    let magnitude: i32 = magnitude[1..].parse().unwrap_or(0);
+    let negative = number < 0.0;
+
+    let magnitude = if negative { -magnitude } else { magnitude } - MIN_MAGNITUDE;
+    let mut factor: f64 = mantissa.parse::<f64>().unwrap_or(0.0).abs();
+    if negative {
+        factor = 10.0 - factor;
+    }
+    let factor = to_fixed_20(factor);
+    let factor = factor.trim_end_matches('0').trim_end_matches('.');
+
+    format!(
+        "{}{:0width$}{}",
+        if negative { '0' } else { '2' },
+        magnitude,
+        factor,
+        width = MAGNITUDE_DIGITS
+    )
+}
+
+/// `Number.prototype.toFixed(20)`, which rounds ties away from zero (unlike `format!`).
+fn to_fixed_20(number: f64) -> String {
+    // 60 fractional digits represent any double in [0, 10) exactly.
+    let exact = format!("{:.60}", number);
+    let point = exact.find('.').unwrap_or(exact.len());
+    let mut digits: Vec<u8> = exact[..point + 21].bytes().collect();
+    if exact.as_bytes()[point + 21] >= b'5' {
+        for digit in digits.iter_mut().rev() {
+            match *digit {
+                b'.' => continue,
+                b'9' => *digit = b'0',
+                _ => {
+                    *digit += 1;
+                    break;
+                }
+            }
+        }
+        if digits[0] == b'0' && number >= 1.0 {
+            digits.insert(0, b'1');
+        }
+    }
+    String::from_utf8(digits).unwrap_or_default()
+}
+
+/// The error returned by [parse_indexable_string] for malformed input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The position (in characters) at which parsing failed.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid indexable string at position {}", self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<char, ParseError> {
+        self.position += 1;
+        self.chars.next().ok_or(ParseError {
+            position: self.position - 1,
+        })
+    }
+
+    fn error(&self) -> ParseError {
+        ParseError {
+            position: self.position.saturating_sub(1),
+        }
+    }
+
+    fn until_terminator(&mut self) -> String {
+        let mut result = String::new();
+        while let Some(c) = self.chars.peek().filter(|c| **c != '\u{0}') {
+            result.push(*c);
+            self.chars.next();
+            self.position += 1;
+        }
+        result
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        let value = match self.next()? {
+            '1' => Value::Null,
+            '2' => match self.next()? {
+                '1' => Value::Bool(true),
+                '0' => Value::Bool(false),
+                _ => return Err(self.error()),
+            },
+            '3' => self.number()?,
+            '4' => Value::String(
+                self.until_terminator()
+                    .replace("\u{1}\u{1}", "\u{0}")
+                    .replace("\u{1}\u{2}", "\u{1}")
+                    .replace("\u{2}\u{2}", "\u{2}"),
+            ),
+            '5' => {
+                let mut values = Vec::new();
+                while self.chars.peek() != Some(&'\u{0}') {
+                    values.push(self.value()?);
+                }
+                Value::Array(values)
+            }
+            '6' => {
+                let mut object = Map::new();
+                while self.chars.peek() != Some(&'\u{0}') {
+                    let key = match self.value()? {
+                        Value::String(key) => key,
+                        _ => return Err(self.error()),
+                    };
+                    object.insert(key, self.value()?);
+                }
+                Value::Object(object)
+            }
+            _ => return Err(self.error()),
+        };
+        match self.next()? {
+            '\u{0}' => Ok(value),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let encoded = self.until_terminator();
+        let error = || ParseError {
+            position: self.position,
+        };
+        let number = match encoded.chars().next() {
+            Some('1') if encoded.len() == 1 => 0.0,
+            Some(sign @ '0') | Some(sign @ '2') if encoded.len() > 1 + MAGNITUDE_DIGITS => {
+                let negative = sign == '0';
+                let magnitude = encoded[1..=MAGNITUDE_DIGITS]
+                    .parse::<i32>()
+                    .map_err(|_| error())?
+                    + MIN_MAGNITUDE;
+                let magnitude = if negative { -magnitude } else { magnitude };
+                let mut number: f64 = encoded[1 + MAGNITUDE_DIGITS..]
+                    .parse()
+                    .map_err(|_| error())?;
+                if negative {
+                    number -= 10.0;
+                }
+                if magnitude != 0 {
+                    number = format!("{}e{}", number, magnitude)
+                        .parse()
+                        .map_err(|_| error())?;
+                }
+                number
+            }
+            _ => return Err(error()),
+        };
+        if number.fract() == 0.0 && number.abs() <= 9_007_199_254_740_992.0 {
+            Ok(Value::Number(Number::from(number as i64)))
+        } else {
+            Number::from_f64(number)
+                .map(Value::Number)
+                .ok_or_else(error)
+        }
+    }
+}
+
+/// Decode a string created by [to_indexable_string].
+pub fn parse_indexable_string(indexable: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        chars: indexable.chars().peekable(),
+        position: 0,
+    };
+    let value = parser.value()?;
+    if parser.chars.next().is_some() {
+        return Err(parser.error());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sorted_values() -> Vec<Value> {
+        vec![
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1e200),
+            json!(-123.5),
+            json!(-1),
+            json!(-0.001),
+            json!(0),
+            json!(1e-200),
+            json!(0.5),
+            json!(1),
+            json!(2),
+            json!(10),
+            json!(123456789.125),
+            json!(1e300),
+            json!(""),
+            json!("A"),
+            json!("a"),
+            json!("aa"),
+            json!("b"),
+            json!("\u{ff61}"),
+            json!([]),
+            json!([null]),
+            json!([1, "a"]),
+            json!([1, "b"]),
+            json!(["a"]),
+            json!(["a", []]),
+            json!({}),
+            json!({"a": 1}),
+            json!({"a": 2}),
+            json!({"b": 1}),
+            json!({"b": 1, "c": 1}),
+        ]
+    }
+
+    #[test]
+    fn collates_in_couchdb_order() {
+        let values = sorted_values();
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(collate(a, b), i.cmp(&j), "{} vs {}", a, b);
+            }
+        }
+        assert_eq!(collate(&json!(1), &json!(1.0)), Ordering::Equal);
+    }
+
+    #[test]
+    fn indexable_strings_match_pouchdb() {
+        assert_eq!(to_indexable_string(&json!(null)), "1\u{0}");
+        assert_eq!(to_indexable_string(&json!(true)), "21\u{0}");
+        assert_eq!(to_indexable_string(&json!(0)), "31\u{0}");
+        assert_eq!(to_indexable_string(&json!(1)), "323241\u{0}");
+        assert_eq!(to_indexable_string(&json!(-1)), "303249\u{0}");
+        assert_eq!(to_indexable_string(&json!(1.5)), "323241.5\u{0}");
+        assert_eq!(to_indexable_string(&json!(-300)), "303227\u{0}");
+        assert_eq!(to_indexable_string(&json!(5e-324)), "320005\u{0}");
+        assert_eq!(
+            to_indexable_string(&json!(-123.456)),
+            "303228.76543999999999989825\u{0}"
+        );
+        // Exactly between two 20-digit decimals; JavaScript rounds up.
+        assert_eq!(
+            to_indexable_string(&json!(1.0000004768371582)),
+            "323241.00000047683715820313\u{0}"
+        );
+        assert_eq!(to_indexable_string(&json!("foo")), "4foo\u{0}");
+        assert_eq!(to_indexable_string(&json!("a\u{0}b")), "4a\u{1}\u{1}b\u{0}");
+        assert_eq!(
+            to_indexable_string(&json!(["a", 1])),
+            "54a\u{0}323241\u{0}\u{0}"
+        );
+        assert_eq!(
+            to_indexable_string(&json!({"b": true, "1": null})),
+            "641\u{0}1\u{0}4b\u{0}21\u{0}\u{0}"
+        );
+    }
+
+    #[test]
+    fn indexable_strings_sort_like_values() {
+        let values: Vec<Value> = sorted_values()
+            .into_iter()
+            .filter(|value| value != &json!("\u{ff61}"))
+            .collect();
+        for pair in values.windows(2) {
+            let (a, b) = (to_indexable_string(&pair[0]), to_indexable_string(&pair[1]));
+            assert_eq!(
+                string_collate(&a, &b),
+                Ordering::Less,
+                "{} vs {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn parses_indexable_strings() {
+        for value in sorted_values()
+            .into_iter()
+            .filter(|value| value != &json!(-123.5))
+            .chain(vec![
+                json!("\u{0}\u{1}\u{2}"),
+                json!({"x": [{"y": [1, {}]}]}),
+            ])
+        {
+            assert_eq!(
+                parse_indexable_string(&to_indexable_string(&value)).unwrap(),
+                value
+            );
+        }
+        // Negative numbers lose precision, just like in pouchdb-collate.
+        assert_eq!(
+            parse_indexable_string(&to_indexable_string(&json!(-123.5))).unwrap(),
+            json!(-123.49999999999994)
+        );
+        assert_eq!(
+            parse_indexable_string("7\u{0}"),
+            Err(ParseError { position: 0 })
+        );
+        assert!(parse_indexable_string("5").is_err());
+        assert!(parse_indexable_string("1\u{0}1\u{0}").is_err());
+    }
+}
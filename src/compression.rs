@@ -0,0 +1,521 @@
+//! Transparent compression of large documents and attachments.
+//!
+//! [Compression] is a [DocumentTransform], so once it's registered with
+//! [PouchDB::add_transform](crate::PouchDB::add_transform), the fields of large documents
+//! are stored compressed and decompressed when they're read:
+//!
+//! ```json
+//! {"_id": "log:1", "_rev": "1-a",
+//!  "compressed": {"magic": "PDBZIP1", "algorithm": "deflate", "data": "..."}}
+//! ```
+//!
+//! Fields starting with `_` and the [uncompressed fields](Compression::uncompressed_fields)
+//! are kept as they are, so selectors and views can use them. Documents with a field
+//! `compressed` of their own are always compressed, so it can't be mistaken for the
+//! compressed fields. The data of attachments with
+//! a [compressible type](Compression::content_types) starts with a marker when it's
+//! compressed, which [Compression::decompress_attachment] checks.
+//!
+//! Documents are only read correctly with a [Compression] registered, but any one will do,
+//! since the algorithm is stored with the data. Replication copies the compressed
+//! documents, which keeps transfers small. To combine it with
+//! [Encryption](crate::encryption::Encryption), add the compression first, since encrypted
+//! data can't be compressed.
+
+use base64::prelude::*;
+use miniz_oxide::inflate::TINFLStatus;
+use serde_json::{json, Map, Value};
+use std::convert::TryInto;
+
+use crate::{
+    database::JsonDocument, design::DESIGN_PREFIX, error::Error, transform::DocumentTransform,
+};
+
+/// The field holding the compressed fields of a document.
+pub const COMPRESSED_FIELD: &str = "compressed";
+
+/// The value of `magic` in [COMPRESSED_FIELD], which marks it as compressed fields.
+const BODY_MAGIC: &str = "PDBZIP1";
+
+/// The start of compressed attachment data, followed by the algorithm and the compressed
+/// data.
+const ATTACHMENT_MAGIC: &[u8] = b"\0PDBZIP1";
+
+/// The default of [Compression::max_size].
+const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// A compression algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Deflate (RFC 1951) with the default level, which compresses best.
+    Deflate,
+    /// LZ4, which is the fastest.
+    Lz4,
+}
+
+impl Algorithm {
+    /// The name stored with compressed documents.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Deflate => "deflate",
+            Self::Lz4 => "lz4",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "deflate" => Ok(Self::Deflate),
+            "lz4" => Ok(Self::Lz4),
+            name => Err(Error::Compression(format!("Unknown algorithm {}.", name))),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Deflate => miniz_oxide::deflate::compress_to_vec(data, 6),
+            Self::Lz4 => lz4_flex::compress_prepend_size(data),
+        }
+    }
+
+    /// Decompress `data`, failing if it would be larger than `max_size` bytes.
+    fn decompress(self, data: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+        let too_large = || {
+            Error::Compression(format!(
+                "The decompressed data is larger than {} bytes.",
+                max_size
+            ))
+        };
+        match self {
+            Self::Deflate => miniz_oxide::inflate::decompress_to_vec_with_limit(data, max_size)
+                .map_err(|err| match err.status {
+                    TINFLStatus::HasMoreOutput => too_large(),
+                    _ => Error::Compression(err.to_string()),
+                }),
+            Self::Lz4 => {
+                // The size is prepended by `compress_prepend_size`.
+                if data.len() < 4 {
+                    return Err(Error::Compression("Missing size.".to_owned()));
+                }
+                let (size, data) = data.split_at(4);
+                let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+                if size > max_size {
+                    return Err(too_large());
+                }
+                lz4_flex::decompress(data, size).map_err(|err| Error::Compression(err.to_string()))
+            }
+        }
+    }
+}
+
+/// Compresses large documents and attachments when they're written and decompresses them
+/// when they're read.
+#[derive(Clone, Debug)]
+pub struct Compression {
+    algorithm: Algorithm,
+    collections: Vec<String>,
+    body_threshold: usize,
+    attachment_threshold: usize,
+    content_types: Vec<String>,
+    uncompressed_fields: Vec<String>,
+    max_size: usize,
+}
+
+impl Compression {
+    /// Compress all documents of at least 1 KiB with `algorithm`, and text attachments of
+    /// the same size.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            collections: Vec::new(),
+            body_threshold: 1024,
+            attachment_threshold: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|content_type| content_type.to_string())
+            .collect(),
+            uncompressed_fields: Vec::new(),
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+    /// Only compress the documents whose ids start with one of the prefixes, e.g.
+    /// `"log:"`.
+    pub fn collections<T: Into<String>, I: IntoIterator<Item = T>>(self, prefixes: I) -> Self {
+        Self {
+            collections: prefixes.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+    /// The minimum size of the fields as JSON, in bytes.
+    pub fn body_threshold(self, body_threshold: usize) -> Self {
+        Self {
+            body_threshold,
+            ..self
+        }
+    }
+    /// The minimum size of attachments, in bytes.
+    pub fn attachment_threshold(self, attachment_threshold: usize) -> Self {
+        Self {
+            attachment_threshold,
+            ..self
+        }
+    }
+    /// The content types of the attachments to compress. A type ending with `/` matches
+    /// all of its subtypes.
+    pub fn content_types<T: Into<String>, I: IntoIterator<Item = T>>(
+        self,
+        content_types: I,
+    ) -> Self {
+        Self {
+            content_types: content_types.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+    /// Fields which aren't compressed, e.g. for selectors and views.
+    pub fn uncompressed_fields<T: Into<String>, I: IntoIterator<Item = T>>(
+        self,
+        fields: I,
+    ) -> Self {
+        Self {
+            uncompressed_fields: fields.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// The maximum size of decompressed fields or attachments, in bytes, so that corrupt
+    /// or malicious data can't exhaust the memory. Default: 64 MiB.
+    pub fn max_size(self, max_size: usize) -> Self {
+        Self { max_size, ..self }
+    }
+
+    fn is_compressible_type(&self, content_type: &str) -> bool {
+        self.content_types.iter().any(|compressible| {
+            if compressible.ends_with('/') {
+                content_type.starts_with(compressible.as_str())
+            } else {
+                content_type == compressible
+                    || content_type.starts_with(&format!("{};", compressible))
+            }
+        })
+    }
+
+    /// Compress the fields and the attachment data of a document, if they're large enough
+    /// and the document belongs to one of the [collections](Self::collections).
+    ///
+    /// Documents with a field [COMPRESSED_FIELD] are always compressed, and that field
+    /// must not be one of the [uncompressed fields](Self::uncompressed_fields).
+    pub fn compress(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        // The field is hidden in the compressed fields, so it can't be taken for them.
+        let escape = doc.data.get(COMPRESSED_FIELD).is_some();
+        let in_collection = escape
+            || self.collections.is_empty()
+            || self
+                .collections
+                .iter()
+                .any(|prefix| doc.id.starts_with(prefix.as_str()));
+        let data = match doc.data {
+            Value::Object(data) if in_collection && !doc.id.starts_with(DESIGN_PREFIX) => data,
+            _ => return Ok(doc),
+        };
+        let (mut kept, fields): (Map<String, Value>, Map<String, Value>) =
+            data.into_iter().partition(|(name, _)| {
+                name.starts_with('_') || self.uncompressed_fields.contains(name)
+            });
+        if kept.contains_key(COMPRESSED_FIELD) {
+            return Err(Error::BadRequest(format!(
+                "The field {} can't be uncompressed.",
+                COMPRESSED_FIELD
+            )));
+        }
+        if let Some(Value::Object(attachments)) = kept.get_mut("_attachments") {
+            for attachment in attachments.values_mut() {
+                self.compress_attachment(attachment)?;
+            }
+        }
+
+        let json = serde_json::to_vec(&fields)?;
+        let compressed = Some(json.len())
+            .filter(|length| escape || *length >= self.body_threshold)
+            .map(|_| self.algorithm.compress(&json))
+            .filter(|compressed| escape || compressed.len() < json.len());
+        match compressed {
+            Some(compressed) => {
+                kept.insert(
+                    COMPRESSED_FIELD.to_owned(),
+                    json!({
+                        "magic": BODY_MAGIC,
+                        "algorithm": self.algorithm.name(),
+                        "data": BASE64_STANDARD.encode(compressed),
+                    }),
+                );
+            }
+            None => kept.extend(fields),
+        }
+        Ok(JsonDocument {
+            data: Value::Object(kept),
+            ..doc
+        })
+    }
+
+    /// Decompress a document compressed by [compress](Self::compress). Other documents are
+    /// returned as they are.
+    pub fn decompress(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        let mut data = match doc.data {
+            Value::Object(data) => data,
+            _ => return Ok(doc),
+        };
+        if let Some(Value::Object(attachments)) = data.get_mut("_attachments") {
+            for attachment in attachments.values_mut() {
+                if let Some(compressed) = attachment_data(attachment)? {
+                    let decompressed = self.decompress_attachment(&compressed)?;
+                    attachment["data"] = json!(BASE64_STANDARD.encode(decompressed));
+                }
+            }
+        }
+        let is_compressed = data.get(COMPRESSED_FIELD).is_some_and(|compressed| {
+            compressed["magic"] == BODY_MAGIC
+                && compressed["algorithm"].is_string()
+                && compressed["data"].is_string()
+        });
+        if is_compressed {
+            let compressed = data.remove(COMPRESSED_FIELD).unwrap_or_default();
+            let algorithm = Algorithm::from_name(compressed["algorithm"].as_str().unwrap())?;
+            let json = BASE64_STANDARD
+                .decode(compressed["data"].as_str().unwrap())
+                .map_err(|err| Error::Compression(err.to_string()))?;
+            let fields: Map<String, Value> =
+                serde_json::from_slice(&algorithm.decompress(&json, self.max_size)?)?;
+            data.extend(fields);
+        }
+        Ok(JsonDocument {
+            data: Value::Object(data),
+            ..doc
+        })
+    }
+
+    /// Compress the base64 data of an attachment, if it's included and compressible.
+    fn compress_attachment(&self, attachment: &mut Value) -> Result<(), Error> {
+        let content_type = attachment["content_type"].as_str().unwrap_or_default();
+        if !self.is_compressible_type(content_type) {
+            return Ok(());
+        }
+        let Some(data) = attachment_data(attachment)? else {
+            return Ok(());
+        };
+        if data.len() < self.attachment_threshold || data.starts_with(ATTACHMENT_MAGIC) {
+            return Ok(());
+        }
+        let compressed = self.algorithm.compress(&data);
+        if compressed.len() + ATTACHMENT_MAGIC.len() + 1 < data.len() {
+            let mut marked = ATTACHMENT_MAGIC.to_vec();
+            marked.push(self.algorithm as u8);
+            marked.extend(compressed);
+            attachment["data"] = json!(BASE64_STANDARD.encode(marked));
+        }
+        Ok(())
+    }
+
+    /// Decompress the data of an attachment, e.g. as returned by
+    /// [PouchDB::get_attachment](crate::PouchDB::get_attachment). Data that isn't
+    /// compressed is returned as it is.
+    pub fn decompress_attachment(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let Some(compressed) = data.strip_prefix(ATTACHMENT_MAGIC) else {
+            return Ok(data.to_vec());
+        };
+        match compressed.split_first() {
+            Some((&algorithm, compressed)) if algorithm == Algorithm::Deflate as u8 => {
+                Algorithm::Deflate.decompress(compressed, self.max_size)
+            }
+            Some((&algorithm, compressed)) if algorithm == Algorithm::Lz4 as u8 => {
+                Algorithm::Lz4.decompress(compressed, self.max_size)
+            }
+            _ => Err(Error::Compression(
+                "Unknown algorithm of attachment.".to_owned(),
+            )),
+        }
+    }
+}
+
+impl DocumentTransform for Compression {
+    fn incoming(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        self.compress(doc)
+    }
+    fn outgoing(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        self.decompress(doc)
+    }
+}
+
+/// The decoded data of an attachment, unless it's a stub.
+fn attachment_data(attachment: &Value) -> Result<Option<Vec<u8>>, Error> {
+    attachment["data"]
+        .as_str()
+        .map(|data| {
+            BASE64_STANDARD
+                .decode(data)
+                .map_err(|err| Error::BadRequest(err.to_string()))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::Database,
+        encryption::{Encryption, EncryptionKey},
+        memory::MemoryDatabase,
+        options::{all_docs::AllDocsOptions, changes::Changes, fetch::FetchOptions},
+        responses::AllDocsValue,
+        transform::Transformed,
+    };
+    use futures::executor::block_on;
+
+    fn large() -> Value {
+        json!({ "type": "log", "lines": vec!["the same line again"; 200] })
+    }
+
+    #[test]
+    fn large_documents_are_compressed() {
+        for algorithm in [Algorithm::Deflate, Algorithm::Lz4].iter() {
+            let compression = Compression::new(*algorithm).uncompressed_fields(["type"]);
+            let doc = JsonDocument::new("log:1", large());
+            let stored = compression.compress(doc.clone()).unwrap();
+            assert_eq!(stored.data["type"], "log");
+            assert_eq!(stored.data[COMPRESSED_FIELD]["algorithm"], algorithm.name());
+            assert!(stored.data.to_string().len() < doc.data.to_string().len() / 4);
+            // Any configuration decompresses it.
+            let other = Compression::new(Algorithm::Deflate);
+            assert_eq!(other.decompress(stored).unwrap(), doc);
+        }
+
+        let compression = Compression::new(Algorithm::Deflate).collections(["log:"]);
+        let small = JsonDocument::new("log:2", json!({ "line": "short" }));
+        assert_eq!(compression.compress(small.clone()).unwrap(), small);
+        let other = JsonDocument::new("user:1", large());
+        assert_eq!(compression.compress(other.clone()).unwrap(), other);
+    }
+
+    #[test]
+    fn own_compressed_fields_are_kept() {
+        let compression = Compression::new(Algorithm::Lz4).collections(["log:"]);
+        let own = json!({ "compressed": { "algorithm": "zip", "data": "abc" } });
+        // Written before compression was enabled.
+        let old = JsonDocument::new("log:1", own.clone());
+        assert_eq!(compression.decompress(old.clone()).unwrap(), old);
+
+        for id in ["log:2", "user:1"] {
+            let doc = JsonDocument::new(id, own.clone());
+            let stored = compression.compress(doc.clone()).unwrap();
+            assert_eq!(stored.data[COMPRESSED_FIELD]["magic"], BODY_MAGIC);
+            assert_eq!(compression.decompress(stored).unwrap(), doc);
+        }
+
+        let compression = compression.uncompressed_fields([COMPRESSED_FIELD]);
+        assert!(matches!(
+            compression.compress(JsonDocument::new("log:3", own)),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn text_attachments_are_compressed() {
+        let compression = Compression::new(Algorithm::Lz4).attachment_threshold(100);
+        let text = BASE64_STANDARD.encode("line\n".repeat(100));
+        let doc = JsonDocument::new(
+            "a",
+            json!({
+                "_attachments": {
+                    "log.txt": {"content_type": "text/plain; charset=utf-8", "data": text},
+                    "image.png": {"content_type": "image/png", "data": text},
+                    "old.txt": {"content_type": "text/plain", "stub": true, "digest": "md5-x"},
+                },
+            }),
+        );
+        let stored = compression.compress(doc.clone()).unwrap();
+        let attachments = &stored.data["_attachments"];
+        assert_ne!(attachments["log.txt"]["data"], text);
+        assert_eq!(
+            attachments["image.png"],
+            doc.data["_attachments"]["image.png"]
+        );
+        assert_eq!(attachments["old.txt"], doc.data["_attachments"]["old.txt"]);
+        assert!(stored.data.get(COMPRESSED_FIELD).is_none());
+
+        let data = BASE64_STANDARD
+            .decode(attachments["log.txt"]["data"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            compression.decompress_attachment(&data).unwrap(),
+            "line\n".repeat(100).as_bytes()
+        );
+        assert_eq!(compression.decompress(stored).unwrap(), doc);
+    }
+
+    #[test]
+    fn decompression_is_limited() {
+        for algorithm in [Algorithm::Deflate, Algorithm::Lz4].iter() {
+            let compression = Compression::new(*algorithm).max_size(1000);
+            let mut bomb = ATTACHMENT_MAGIC.to_vec();
+            bomb.push(*algorithm as u8);
+            bomb.extend(algorithm.compress(&[0; 1001]));
+            assert!(matches!(
+                compression.decompress_attachment(&bomb),
+                Err(Error::Compression(_))
+            ));
+            let fits = compression.max_size(1001);
+            assert_eq!(fits.decompress_attachment(&bomb).unwrap(), vec![0; 1001]);
+        }
+        // The prepended size of LZ4 is checked before allocating.
+        let mut lied = vec![0xff; 4];
+        lied.extend(&lz4_flex::compress(b"small")[..]);
+        assert!(matches!(
+            Algorithm::Lz4.decompress(&lied, DEFAULT_MAX_SIZE),
+            Err(Error::Compression(_))
+        ));
+    }
+
+    #[test]
+    fn reads_are_decompressed() {
+        let mut db = Transformed::new(MemoryDatabase::new("db"));
+        db.add_transform(Compression::new(Algorithm::Deflate));
+        db.add_transform(Encryption::new(EncryptionKey::new("k", [7; 32])));
+        block_on(async {
+            db.put(&JsonDocument::new("a", large())).await.unwrap();
+
+            let fetched = db.fetch("a", &FetchOptions::default()).await.unwrap();
+            assert_eq!(fetched.data, large());
+
+            let all_docs = db
+                .all_docs(&AllDocsOptions {
+                    include_docs: true,
+                    ..AllDocsOptions::default()
+                })
+                .await
+                .unwrap();
+            match &all_docs.rows[0].value {
+                AllDocsValue::Found { doc, .. } => {
+                    assert_eq!(doc.as_ref().unwrap().data, large())
+                }
+                value => panic!("unexpected row {:?}", value),
+            }
+
+            let (changes, _) = db
+                .changes(&Changes {
+                    include_docs: true,
+                    ..Changes::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(changes[0].doc.as_ref().unwrap().data, large());
+
+            // Compressed before encrypted, so the ciphertext is small.
+            let stored = db.inner().fetch("a", &FetchOptions::default()).await;
+            assert!(stored.unwrap().data.to_string().len() < large().to_string().len() / 4);
+        });
+    }
+}
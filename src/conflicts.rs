@@ -0,0 +1,434 @@
+use futures::{channel::mpsc, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+use wasm_bindgen_futures::spawn_local;
+
+use crate::{
+    document::{Revision, SerializedDocument},
+    error::Error,
+    events::{
+        changes_event_emitter::{ChangeEvent, ChangesEventEmitter},
+        EventListener, SequenceID,
+    },
+    options::changes::Changes,
+    PouchDB,
+};
+
+/// Merges the leaf revisions of a conflicted document into a single body.
+///
+/// `winner` is the revision PouchDB picked as the winner, `losers` are the remaining
+/// (non-deleted) leaf revisions. All of them are passed as raw JSON, including the
+/// `_id` and `_rev` fields.
+///
+/// The returned body is written on top of the winning revision by
+/// [PouchDB::resolve_conflicts](crate::PouchDB::resolve_conflicts). Reserved fields
+/// (everything starting with `_`) are taken from the winner, except for `_attachments`,
+/// which is only taken from the winner if the merged body doesn't contain it.
+pub trait ConflictResolver {
+    fn resolve(&self, winner: &Value, losers: &[Value]) -> Value;
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(&Value, &[Value]) -> Value,
+{
+    fn resolve(&self, winner: &Value, losers: &[Value]) -> Value {
+        self(winner, losers)
+    }
+}
+
+/// Keeps the winning revision as-is and discards all losing revisions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeepWinner;
+
+impl ConflictResolver for KeepWinner {
+    fn resolve(&self, winner: &Value, _losers: &[Value]) -> Value {
+        winner.clone()
+    }
+}
+
+/// Keeps the revision with the greatest value in the given field, e.g. a modification
+/// timestamp. Numbers are compared numerically, strings lexicographically. Revisions
+/// missing the field lose against all others; on a tie, PouchDB's winner is kept.
+#[derive(Debug, Clone)]
+pub struct LastWriteWins {
+    field: String,
+}
+
+impl LastWriteWins {
+    pub fn new<T: Into<String>>(field: T) -> Self {
+        Self {
+            field: field.into(),
+        }
+    }
+}
+
+impl ConflictResolver for LastWriteWins {
+    fn resolve(&self, winner: &Value, losers: &[Value]) -> Value {
+        let mut latest = winner;
+        for loser in losers {
+            if compare_field(loser.get(&self.field), latest.get(&self.field)) == Ordering::Greater {
+                latest = loser;
+            }
+        }
+        latest.clone()
+    }
+}
+
+fn compare_field(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(a), None) if !a.is_null() => Ordering::Greater,
+        (None, Some(b)) if !b.is_null() => Ordering::Less,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Merges all revisions field by field. Fields are taken from the winner first; fields
+/// only present in losing revisions are added in the order the losers are given.
+/// Nested objects are merged recursively, all other values are never overwritten.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldMerge;
+
+impl ConflictResolver for FieldMerge {
+    fn resolve(&self, winner: &Value, losers: &[Value]) -> Value {
+        let mut merged = winner.clone();
+        for loser in losers {
+            merge_into(&mut merged, loser);
+        }
+        merged
+    }
+}
+
+fn merge_into(target: &mut Value, source: &Value) {
+    if let (Value::Object(target), Value::Object(source)) = (target, source) {
+        for (key, value) in source {
+            if key.starts_with('_') {
+                continue;
+            }
+            match target.get_mut(key) {
+                Some(existing) => merge_into(existing, value),
+                None => {
+                    target.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Builds the document that's written on top of the winning revision.
+pub(crate) fn merged_document(merged: Value, winner: &Value) -> Value {
+    let mut document = Map::new();
+    let mut attachments = None;
+    if let Value::Object(merged) = merged {
+        for (key, value) in merged {
+            if key == "_attachments" {
+                attachments = Some(value);
+            } else if !key.starts_with('_') {
+                document.insert(key, value);
+            }
+        }
+    }
+    for field in &["_id", "_rev"] {
+        if let Some(value) = winner.get(field) {
+            document.insert((*field).to_owned(), value.clone());
+        }
+    }
+    if let Some(attachments) = attachments.or_else(|| winner.get("_attachments").cloned()) {
+        document.insert("_attachments".to_owned(), attachments);
+    }
+    Value::Object(document)
+}
+
+/// Builds the deletion for a losing revision.
+pub(crate) fn tombstone(loser: &Value) -> Value {
+    let mut document = Map::new();
+    for field in &["_id", "_rev"] {
+        if let Some(value) = loser.get(field) {
+            document.insert((*field).to_owned(), value.clone());
+        }
+    }
+    document.insert("_deleted".to_owned(), Value::Bool(true));
+    Value::Object(document)
+}
+
+type ErrorHandler = Box<dyn Fn(Error)>;
+
+enum Handler {
+    Resolver(Box<dyn ConflictResolver>),
+    Callback(Box<dyn Fn(SerializedDocument)>),
+}
+
+#[derive(Default)]
+struct WatcherState {
+    last_seq: RefCell<Option<SequenceID>>,
+    on_error: RefCell<Option<ErrorHandler>>,
+}
+
+impl WatcherState {
+    fn report(&self, err: Error) {
+        if let Some(on_error) = &*self.on_error.borrow() {
+            on_error(err);
+        }
+    }
+}
+
+/// Watches the changes feed of a database for conflicted documents, e.g. ones produced
+/// by replication, and hands them to a [ConflictResolver] or a callback.
+///
+/// Changes are processed one at a time. The sequence of the last processed change is
+/// checkpointed in the `_local/<checkpoint_id>` document, so a watcher created with the
+/// same checkpoint id continues where the previous one stopped. If reading or handling a
+/// change fails, the checkpoint isn't advanced past it any more, so it's retried by the
+/// next watcher.
+///
+/// Dropping the watcher cancels the changes feed, like [cancel](Self::cancel).
+pub struct ConflictWatcher {
+    changes: ChangesEventEmitter,
+    _listener: EventListener,
+    state: Rc<WatcherState>,
+}
+
+impl ConflictWatcher {
+    /// Start watching `db`, resolving every conflicted document with
+    /// [PouchDB::resolve_conflicts].
+    pub async fn with_resolver<R>(
+        db: &PouchDB,
+        checkpoint_id: &str,
+        resolver: R,
+    ) -> Result<Self, Error>
+    where
+        R: ConflictResolver + 'static,
+    {
+        Self::start(db, checkpoint_id, Handler::Resolver(Box::new(resolver))).await
+    }
+
+    /// Start watching `db`, passing every conflicted document to `callback`. The document
+    /// contains the revisions of the conflicts in [SerializedDocument::conflicts].
+    pub async fn with_callback<F>(
+        db: &PouchDB,
+        checkpoint_id: &str,
+        callback: F,
+    ) -> Result<Self, Error>
+    where
+        F: Fn(SerializedDocument) + 'static,
+    {
+        Self::start(db, checkpoint_id, Handler::Callback(Box::new(callback))).await
+    }
+
+    async fn start(db: &PouchDB, checkpoint_id: &str, handler: Handler) -> Result<Self, Error> {
+        let checkpoint_id = checkpoint_id.to_owned();
+        let (checkpoint_rev, since) = read_checkpoint(db, &checkpoint_id).await?;
+
+        let state = Rc::new(WatcherState::default());
+        *state.last_seq.borrow_mut() = since.clone();
+
+        let changes = db.changes(&Changes {
+            include_docs: true,
+            conflicts: true,
+            since,
+            ..Changes::default()
+        })?;
+        let (sender, receiver) = mpsc::unbounded();
+        let listener = changes.add_change_listener(move |event| {
+            sender.unbounded_send(event).ok();
+        })?;
+
+        spawn_local(watch(
+            PouchDB(db.0.clone(), db.1.clone(), db.2.clone()),
+            checkpoint_id,
+            checkpoint_rev,
+            handler,
+            receiver,
+            state.clone(),
+        ));
+
+        Ok(Self {
+            changes,
+            _listener: listener,
+            state,
+        })
+    }
+
+    /// The sequence of the last change that was processed and checkpointed.
+    pub fn last_seq(&self) -> Option<SequenceID> {
+        self.state.last_seq.borrow().clone()
+    }
+
+    /// Set a function receiving errors that occur while resolving conflicts or writing
+    /// checkpoints. Errors are ignored otherwise.
+    pub fn set_error_handler(&self, on_error: impl Fn(Error) + 'static) {
+        *self.state.on_error.borrow_mut() = Some(Box::new(on_error));
+    }
+
+    /// Stop watching for conflicts.
+    pub fn cancel(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ConflictWatcher {
+    fn drop(&mut self) {
+        self.changes.stop();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    last_seq: SequenceID,
+}
+
+async fn read_checkpoint(
+    db: &PouchDB,
+    checkpoint_id: &str,
+) -> Result<(Option<Revision>, Option<SequenceID>), Error> {
+    match db.get_local::<Checkpoint>(checkpoint_id).await {
+        Ok(checkpoint) => Ok((Some(checkpoint.rev), Some(checkpoint.data.last_seq))),
+        Err(Error::NotFound(_)) => Ok((None, None)),
+        Err(err) => Err(err),
+    }
+}
+
+async fn write_checkpoint(
+    db: &PouchDB,
+    checkpoint_id: &str,
+    rev: &Option<Revision>,
+    seq: &SequenceID,
+) -> Result<Revision, Error> {
+    let checkpoint = Checkpoint {
+        last_seq: seq.clone(),
+    };
+    Ok(db
+        .put_local(checkpoint_id, rev.as_ref(), &checkpoint)
+        .await?
+        .rev)
+}
+
+async fn handle(db: &PouchDB, handler: &Handler, event: ChangeEvent) -> Result<(), Error> {
+    if let Some(doc) = event.doc.filter(|doc| !doc.conflicts.is_empty()) {
+        match handler {
+            Handler::Resolver(resolver) => {
+                db.resolve_conflicts(&doc.id, resolver.as_ref()).await?;
+            }
+            Handler::Callback(callback) => callback(doc),
+        }
+    }
+    Ok(())
+}
+
+async fn watch(
+    db: PouchDB,
+    checkpoint_id: String,
+    mut checkpoint_rev: Option<Revision>,
+    handler: Handler,
+    mut receiver: mpsc::UnboundedReceiver<Result<ChangeEvent, Error>>,
+    state: Rc<WatcherState>,
+) {
+    // Once handling a change failed, the checkpoint stays before it, so the change is
+    // handled again by the next watcher with this checkpoint id.
+    let mut failed = false;
+    while let Some(event) = receiver.next().await {
+        let mut seq = None;
+        let mut next = Some(event);
+        while let Some(event) = next {
+            let handled = match event {
+                Ok(event) => {
+                    let event_seq = event.seq.clone();
+                    handle(&db, &handler, event).await.map(|()| event_seq)
+                }
+                Err(err) => Err(err),
+            };
+            match handled {
+                Ok(event_seq) if !failed => seq = Some(event_seq),
+                Ok(_) => {}
+                Err(err) => {
+                    failed = true;
+                    state.report(err);
+                }
+            }
+            // Only checkpoint once all queued changes are processed.
+            next = receiver.try_recv().ok();
+        }
+        let Some(seq) = seq else {
+            continue;
+        };
+        match write_checkpoint(&db, &checkpoint_id, &checkpoint_rev, &seq).await {
+            Ok(rev) => {
+                checkpoint_rev = Some(rev);
+                *state.last_seq.borrow_mut() = Some(seq);
+            }
+            Err(err) => state.report(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn revisions() -> (Value, Vec<Value>) {
+        (
+            json!({"_id": "a", "_rev": "2-b", "name": "winner", "updated": 10, "nested": {"x": 1}}),
+            vec![
+                json!({"_id": "a", "_rev": "2-a", "name": "loser", "updated": 20, "extra": true}),
+                json!({"_id": "a", "_rev": "2-c", "nested": {"x": 2, "y": 3}}),
+            ],
+        )
+    }
+
+    #[test]
+    fn keep_winner_returns_winner() {
+        let (winner, losers) = revisions();
+        assert_eq!(KeepWinner.resolve(&winner, &losers), winner);
+    }
+
+    #[test]
+    fn last_write_wins_picks_greatest_field() {
+        let (winner, losers) = revisions();
+        assert_eq!(
+            LastWriteWins::new("updated").resolve(&winner, &losers),
+            losers[0]
+        );
+        assert_eq!(
+            LastWriteWins::new("missing").resolve(&winner, &losers),
+            winner
+        );
+    }
+
+    #[test]
+    fn field_merge_prefers_winner() {
+        let (winner, losers) = revisions();
+        let merged = merged_document(FieldMerge.resolve(&winner, &losers), &winner);
+        assert_eq!(
+            merged,
+            json!({
+                "_id": "a",
+                "_rev": "2-b",
+                "name": "winner",
+                "updated": 10,
+                "extra": true,
+                "nested": {"x": 1, "y": 3},
+            })
+        );
+    }
+
+    #[test]
+    fn merged_document_keeps_winner_metadata() {
+        let winner = json!({"_id": "a", "_rev": "3-x", "_conflicts": ["3-y"], "_attachments": {"f": {"stub": true}}});
+        let merged = merged_document(json!({"_rev": "3-y", "value": 1}), &winner);
+        assert_eq!(
+            merged,
+            json!({"_id": "a", "_rev": "3-x", "value": 1, "_attachments": {"f": {"stub": true}}})
+        );
+        assert_eq!(
+            tombstone(&json!({"_id": "a", "_rev": "3-y", "value": 2})),
+            json!({"_id": "a", "_rev": "3-y", "_deleted": true})
+        );
+    }
+}
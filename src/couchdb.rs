@@ -0,0 +1,931 @@
+//! A client for the CouchDB HTTP API, for native (non-wasm) targets with the `couchdb`
+//! feature.
+//!
+//! [CouchDB] implements [Database] and the methods of [PouchDB](crate::PouchDB) that make
+//! sense for a remote database, using the same options and responses, so server and client
+//! code can share them. Requests are made with `reqwest`, so they must run within a Tokio
+//! runtime.
+
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client, Method, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use crate::{
+    database::{self, Database, JsonDocument},
+    document::{self, LocalDocument, Revision},
+    error::Error,
+    events::{changes_event_emitter::ChangeEvent, SequenceID},
+    options::{
+        all_docs::AllDocsOptions,
+        changes::Changes,
+        create::{Auth, CreateOptions},
+        fetch::FetchOptions,
+        find::{FindOptions, IndexOptions},
+    },
+    responses::{
+        AllDocsResponse, AllDocsRow, AllDocsValue, ChangeResponse, CreateIndexResponse,
+        DatabaseInfo, DestroyResponse, FindResponse, RevsDiffEntry, Session,
+    },
+    rev_tree::js_stringify,
+    session::{self, User},
+};
+
+/// Query parameters whose string values CouchDB expects as JSON.
+const JSON_PARAMS: &[&str] = &["key", "keys", "startkey", "endkey", "start_key", "end_key"];
+
+/// Turns serialized options into query parameters. Non-string values are sent as JSON.
+fn query_params(options: &Value) -> Vec<(String, String)> {
+    options
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(value) if !JSON_PARAMS.contains(&name.as_str()) => value.clone(),
+                value => js_stringify(value),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// The path segments of a document, keeping the slash of design and local documents.
+fn doc_segments(id: &str) -> Vec<&str> {
+    match id.split_once('/') {
+        Some((prefix, name)) if prefix == "_design" || prefix == "_local" => vec![prefix, name],
+        _ => vec![id],
+    }
+}
+
+async fn error_from_response(response: Response) -> Error {
+    let status = response.status().as_u16();
+    let body: Value = response.json().await.unwrap_or_default();
+    Error::from_response(status, &body)
+}
+
+#[derive(Deserialize)]
+struct RowValue {
+    rev: Revision,
+    #[serde(default)]
+    deleted: bool,
+}
+
+#[derive(Deserialize)]
+struct Row {
+    key: String,
+    id: Option<String>,
+    value: Option<RowValue>,
+    doc: Option<Value>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AllDocs {
+    #[serde(default)]
+    total_rows: u64,
+    #[serde(default)]
+    offset: u64,
+    update_seq: Option<SequenceID>,
+    rows: Vec<Row>,
+}
+
+#[derive(Deserialize)]
+struct Change {
+    rev: Revision,
+}
+
+#[derive(Deserialize)]
+struct ChangeInfo {
+    id: String,
+    seq: SequenceID,
+    changes: Vec<Change>,
+    #[serde(default)]
+    deleted: bool,
+    doc: Option<Value>,
+}
+
+impl ChangeInfo {
+    fn into_event(self) -> Result<ChangeEvent<JsonDocument>, Error> {
+        let deleted = self.deleted;
+        Ok(ChangeEvent {
+            id: self.id,
+            changes: self.changes.into_iter().map(|change| change.rev).collect(),
+            seq: self.seq,
+            deleted: self.deleted,
+            doc: self
+                .doc
+                .filter(|doc| doc.is_object() && !deleted)
+                .map(JsonDocument::from_json)
+                .transpose()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangesInfo {
+    results: Vec<ChangeInfo>,
+    last_seq: SequenceID,
+}
+
+#[derive(Deserialize)]
+struct Find {
+    docs: Vec<Value>,
+    warning: Option<String>,
+    bookmark: Option<String>,
+}
+
+/// Parses the lines of a continuous changes feed.
+struct ContinuousChanges {
+    bytes: BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl ContinuousChanges {
+    async fn next_event(mut self) -> Option<(Result<ChangeEvent<JsonDocument>, Error>, Self)> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    // heartbeat
+                    continue;
+                }
+                let line: Value = match serde_json::from_slice(&line) {
+                    Ok(line) => line,
+                    Err(err) => return Some((Err(err.into()), self)),
+                };
+                if line.get("id").is_none() && line.get("last_seq").is_some() {
+                    return None;
+                }
+                let event = serde_json::from_value::<ChangeInfo>(line)
+                    .map_err(Error::from)
+                    .and_then(ChangeInfo::into_event);
+                return Some((event, self));
+            }
+            if self.done {
+                return None;
+            }
+            match self.bytes.next().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(err)) => {
+                    self.done = true;
+                    self.buffer.clear();
+                    return Some((Err(err.into()), self));
+                }
+                None => {
+                    self.done = true;
+                    self.buffer.push(b'\n');
+                }
+            }
+        }
+    }
+}
+
+/// A remote CouchDB database, accessed via HTTP.
+///
+/// Authenticate either with the credentials in [CreateOptions::auth] (sent with every
+/// request and replaceable with [set_auth](CouchDB::set_auth)), or with a cookie session
+/// started by [log_in](CouchDB::log_in).
+///
+/// Unless [CreateOptions::skip_setup] is set, the database is created if it doesn't exist
+/// on the first request, like PouchDB does.
+pub struct CouchDB {
+    client: Client,
+    url: Url,
+    auth: RwLock<Option<Auth>>,
+    skip_setup: bool,
+    set_up: AtomicBool,
+}
+
+impl CouchDB {
+    /// Connect to the database at `url`, e.g. `http://localhost:5984/dbname`.
+    pub fn new<T: Into<String>>(url: T) -> Result<Self, Error> {
+        Self::new_with_options(CreateOptions::default().name(url))
+    }
+
+    /// Connect to the database at the URL given as [CreateOptions::name], which is
+    /// appended to [CreateOptions::prefix] if set.
+    pub fn new_with_options(options: CreateOptions) -> Result<Self, Error> {
+        options.validate()?;
+        let name = options
+            .name
+            .ok_or_else(|| Error::BadRequest("The URL of the database is missing.".to_owned()))?;
+        let name = format!("{}{}", options.prefix.unwrap_or_default(), name);
+        let url = Url::parse(&name)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base())
+            .ok_or_else(|| Error::BadRequest(format!("Invalid database URL: {}", name)))?;
+        Ok(Self {
+            client: Client::builder().cookie_store(true).build()?,
+            url,
+            auth: RwLock::new(options.auth),
+            skip_setup: options.skip_setup,
+            set_up: AtomicBool::new(false),
+        })
+    }
+
+    /// The URL of the database.
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    /// Replace the credentials, e.g. with a refreshed token
+    ///
+    /// They're used from the next request on, including the requests of running
+    /// replications.
+    pub fn set_auth(&self, auth: Auth) {
+        *self.auth.write().expect("not poisoned") = Some(auth);
+    }
+
+    fn db_url(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in new")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn doc_url(&self, id: &str, segments: &[&str]) -> Url {
+        let mut path = doc_segments(id);
+        path.extend(segments);
+        self.db_url(&path)
+    }
+
+    fn server_url(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in new")
+            .pop_if_empty()
+            .pop()
+            .extend(segments);
+        url
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, url)
+            .header(ACCEPT, "application/json");
+        let auth = self.auth.read().expect("not poisoned");
+        auth.iter()
+            .flat_map(Auth::headers)
+            .fold(request, |request, (name, value)| {
+                request.header(name, value)
+            })
+    }
+
+    /// Send a request to the server, without setting up the database.
+    async fn send_to_server(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(error_from_response(response).await)
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        self.setup().await?;
+        self.send_to_server(request).await
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        let response = self.send(request).await?;
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    async fn setup(&self) -> Result<(), Error> {
+        if self.skip_setup || self.set_up.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let response = self.request(Method::GET, self.url.clone()).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let response = self.request(Method::PUT, self.url.clone()).send().await?;
+            // Somebody else may have created it in the meantime.
+            if !response.status().is_success()
+                && response.status() != StatusCode::PRECONDITION_FAILED
+            {
+                return Err(error_from_response(response).await);
+            }
+        }
+        self.set_up.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Get information about the database
+    pub async fn info(&self) -> Result<DatabaseInfo, Error> {
+        self.send_json(self.request(Method::GET, self.db_url(&[])))
+            .await
+    }
+
+    /// Delete the database
+    pub async fn destroy(self) -> Result<DestroyResponse, Error> {
+        let request = self.request(Method::DELETE, self.db_url(&[]));
+        self.send_to_server(request)
+            .await?
+            .json()
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Create/update a document
+    ///
+    /// If the document already exists, its revision must be set, otherwise
+    /// [Error::Conflict] is returned.
+    pub async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let request = self
+            .request(Method::PUT, self.doc_url(&doc.id, &[]))
+            .json(&doc.to_json());
+        self.send_json(request).await
+    }
+
+    /// Create a document with an id generated by the server
+    pub async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let mut json = doc.to_json();
+        if let Some(json) = json.as_object_mut() {
+            json.remove("_id");
+        }
+        self.send_json(self.request(Method::POST, self.db_url(&[])).json(&json))
+            .await
+    }
+
+    /// Fetch a document
+    ///
+    /// If [FetchOptions::open_revs] is set, the first of the requested revisions is
+    /// returned. Attachments are included as base64 if [FetchOptions::attachments] is set.
+    pub async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        let mut params = serde_json::to_value(options)?;
+        if let Some(conflicts) = params.get_mut("conflicts") {
+            *conflicts = Value::Bool(true);
+        }
+        let request = self
+            .request(Method::GET, self.doc_url(doc_id, &[]))
+            .query(&query_params(&params));
+        let doc: Value = self.send_json(request).await?;
+        match doc {
+            Value::Array(docs) => docs
+                .into_iter()
+                .find_map(|mut doc| doc.get_mut("ok").map(Value::take))
+                .ok_or_else(|| Error::NotFound("missing".to_owned()))
+                .and_then(JsonDocument::from_json),
+            doc => JsonDocument::from_json(doc),
+        }
+    }
+
+    /// Delete revision `rev` of a document
+    pub async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        let request = self
+            .request(Method::DELETE, self.doc_url(doc_id, &[]))
+            .query(&[("rev", rev.as_str())]);
+        self.send_json(request).await
+    }
+
+    /// Create/update a batch of documents
+    ///
+    /// Returns the result for each document. Documents with an empty id get an id
+    /// generated by the server.
+    pub async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        let docs: Vec<Value> = docs
+            .iter()
+            .map(|doc| {
+                let mut json = doc.to_json();
+                if doc.id.is_empty() {
+                    if let Some(json) = json.as_object_mut() {
+                        json.remove("_id");
+                    }
+                }
+                json
+            })
+            .collect();
+        let request = self
+            .request(Method::POST, self.db_url(&["_bulk_docs"]))
+            .json(&json!({ "docs": docs }));
+        let results: Vec<Value> = self.send_json(request).await?;
+
+        Ok(results
+            .into_iter()
+            .map(|result| {
+                if result.get("error").is_some() {
+                    Err(Error::from_json(&result))
+                } else {
+                    Ok(serde_json::from_value(result)?)
+                }
+            })
+            .collect())
+    }
+
+    /// Fetch multiple documents, indexed and sorted by the id. See
+    /// [PouchDB::all_docs](crate::PouchDB::all_docs).
+    pub async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        let mut params = serde_json::to_value(options)?;
+        let keys = params
+            .as_object_mut()
+            .and_then(|params| params.remove("keys"));
+        let url = self.db_url(&["_all_docs"]);
+        let request = match keys {
+            Some(keys) => self
+                .request(Method::POST, url)
+                .json(&json!({ "keys": keys })),
+            None => self.request(Method::GET, url),
+        }
+        .query(&query_params(&params));
+        let response: AllDocs = self.send_json(request).await?;
+
+        let rows = response
+            .rows
+            .into_iter()
+            .map(|row| {
+                let value = match (row.id, row.value, row.error) {
+                    (_, _, Some(error)) if error == "not_found" => AllDocsValue::NotFound,
+                    (_, _, Some(error)) => AllDocsValue::Error(error),
+                    (Some(id), Some(value), None) if value.deleted => {
+                        AllDocsValue::Deleted { id, rev: value.rev }
+                    }
+                    (Some(id), Some(value), None) => AllDocsValue::Found {
+                        id,
+                        rev: value.rev,
+                        doc: row
+                            .doc
+                            .filter(Value::is_object)
+                            .map(JsonDocument::from_json)
+                            .transpose()?,
+                    },
+                    _ => AllDocsValue::Error("invalid row".to_owned()),
+                };
+                Ok(AllDocsRow {
+                    key: row.key,
+                    value,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(AllDocsResponse {
+            total_rows: response.total_rows,
+            offset: response.offset,
+            update_seq: response.update_seq,
+            rows,
+        })
+    }
+
+    fn changes_request(&self, options: &Changes, feed: &str) -> Result<RequestBuilder, Error> {
+        if options.filter.is_some() && (!options.doc_ids.is_empty() || options.selector.is_some()) {
+            return Err(Error::BadRequest(
+                "filter can't be combined with doc_ids or selector.".to_owned(),
+            ));
+        }
+        let mut params = query_params(&serde_json::to_value(options)?);
+        params.retain(|(name, value)| {
+            !["doc_ids", "selector", "batch_size"].contains(&name.as_str()) && value != "false"
+        });
+        if let Some(since) = &options.since {
+            let since = match &since.0 {
+                Value::String(since) => since.clone(),
+                since => since.to_string(),
+            };
+            params.push(("since".to_owned(), since));
+        }
+        params.push(("feed".to_owned(), feed.to_owned()));
+
+        let body = if !options.doc_ids.is_empty() {
+            params.push(("filter".to_owned(), "_doc_ids".to_owned()));
+            Some(json!({ "doc_ids": options.doc_ids }))
+        } else if let Some(selector) = &options.selector {
+            params.push(("filter".to_owned(), "_selector".to_owned()));
+            Some(json!({ "selector": selector }))
+        } else {
+            None
+        };
+        let url = self.db_url(&["_changes"]);
+        Ok(match body {
+            Some(body) => self.request(Method::POST, url).json(&body),
+            None => self.request(Method::GET, url),
+        }
+        .query(&params))
+    }
+
+    async fn changes_feed(
+        &self,
+        options: &Changes,
+        feed: &str,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        let changes: ChangesInfo = self.send_json(self.changes_request(options, feed)?).await?;
+        let results = changes
+            .results
+            .into_iter()
+            .map(ChangeInfo::into_event)
+            .collect::<Result<_, _>>()?;
+        Ok((results, changes.last_seq))
+    }
+
+    /// The changes made since [Changes::since], and the `last_seq`.
+    ///
+    /// [Changes::query_params] is ignored.
+    pub async fn changes_oneshot(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        self.changes_feed(options, "normal").await
+    }
+
+    /// Like [changes_oneshot](CouchDB::changes_oneshot), but waits until there's at least
+    /// one change (or until [Changes::timeout] passes).
+    pub async fn changes_longpoll(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        self.changes_feed(options, "longpoll").await
+    }
+
+    /// Follow the changes of the database as they happen (the continuous feed).
+    ///
+    /// The stream ends when the server closes the connection, e.g. after
+    /// [Changes::timeout] passed without changes. Set [Changes::heartbeat] to keep the
+    /// connection open.
+    pub async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<impl Stream<Item = Result<ChangeEvent<JsonDocument>, Error>>, Error> {
+        let response = self
+            .send(self.changes_request(options, "continuous")?)
+            .await?;
+        let changes = ContinuousChanges {
+            bytes: response
+                .bytes_stream()
+                .map(|chunk| chunk.map(|chunk| chunk.to_vec()))
+                .boxed(),
+            buffer: Vec::new(),
+            done: false,
+        };
+        Ok(stream::unfold(changes, ContinuousChanges::next_event))
+    }
+
+    /// Fetch a local document. See [PouchDB::get_local](crate::PouchDB::get_local).
+    pub async fn get_local<T>(&self, id: &str) -> Result<LocalDocument<T>, Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let id = document::local_id(id);
+        let doc = self.fetch(&id, &FetchOptions::default()).await?;
+
+        Ok(LocalDocument {
+            id: id[document::LOCAL_PREFIX.len()..].to_owned(),
+            rev: doc
+                .rev
+                .ok_or_else(|| Error::BadRequest("Local document has no revision.".to_owned()))?,
+            data: serde_json::from_value(doc.data)?,
+        })
+    }
+
+    /// Create/update a local document. See [PouchDB::put_local](crate::PouchDB::put_local).
+    pub async fn put_local<T>(
+        &self,
+        id: &str,
+        rev: Option<&Revision>,
+        data: &T,
+    ) -> Result<ChangeResponse, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let doc = JsonDocument {
+            id: document::local_id(id),
+            rev: rev.cloned(),
+            data: serde_json::to_value(data)?,
+            ..JsonDocument::default()
+        };
+        if !doc.data.is_object() {
+            return Err(Error::BadRequest(
+                "Local documents must be JSON objects.".to_owned(),
+            ));
+        }
+        self.put(&doc).await
+    }
+
+    /// Delete a local document
+    pub async fn remove_local(&self, id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        self.remove(&document::local_id(id), rev).await
+    }
+
+    /// Compare revisions with the database. See [PouchDB::revs_diff](crate::PouchDB::revs_diff).
+    pub async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        let request = self
+            .request(Method::POST, self.db_url(&["_revs_diff"]))
+            .json(&revs);
+        self.send_json(request).await
+    }
+
+    /// Fetch the given revisions of documents, with their history in `_revisions` and the
+    /// attachment data inline
+    pub async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        let docs: Vec<Value> = docs
+            .iter()
+            .map(|(id, rev)| json!({ "id": id, "rev": rev }))
+            .collect();
+        let request = self
+            .request(Method::POST, self.db_url(&["_bulk_get"]))
+            .query(&[("revs", "true"), ("attachments", "true")])
+            .json(&json!({ "docs": docs }));
+        Ok(database::bulk_get_results(self.send_json(request).await?))
+    }
+
+    /// Store revisions made in another database, like `_bulk_docs` with
+    /// `new_edits: false`. See [Database::bulk_docs_replicated].
+    pub async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let json: Vec<Value> = docs.iter().map(JsonDocument::to_json).collect();
+        let request = self
+            .request(Method::POST, self.db_url(&["_bulk_docs"]))
+            .json(&json!({ "docs": json, "new_edits": false }));
+        let results: Vec<Value> = self.send_json(request).await?;
+
+        Ok(docs
+            .iter()
+            .map(|doc| {
+                match results
+                    .iter()
+                    .find(|result| result["id"] == doc.id.as_str() && result.get("error").is_some())
+                {
+                    Some(result) => Err(Error::from_json(result)),
+                    None => Ok(()),
+                }
+            })
+            .collect())
+    }
+
+    /// Get the data and content type of an attachment
+    pub async fn get_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: Option<&Revision>,
+    ) -> Result<(String, Vec<u8>), Error> {
+        let mut request = self
+            .request(Method::GET, self.doc_url(doc_id, &[attachment_id]))
+            .header(ACCEPT, "*/*");
+        if let Some(rev) = rev {
+            request = request.query(&[("rev", rev.as_str())]);
+        }
+        let response = self.send(request).await?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_owned();
+        Ok((content_type, response.bytes().await?.to_vec()))
+    }
+
+    /// Add or replace an attachment
+    ///
+    /// `rev` must be the current revision of the document, or `None` to create a new
+    /// document with only this attachment.
+    pub async fn put_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: Option<&Revision>,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> Result<ChangeResponse, Error> {
+        let mut request = self
+            .request(Method::PUT, self.doc_url(doc_id, &[attachment_id]))
+            .header(CONTENT_TYPE, content_type)
+            .body(data);
+        if let Some(rev) = rev {
+            request = request.query(&[("rev", rev.as_str())]);
+        }
+        self.send_json(request).await
+    }
+
+    /// Delete an attachment
+    pub async fn remove_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: &Revision,
+    ) -> Result<ChangeResponse, Error> {
+        let request = self
+            .request(Method::DELETE, self.doc_url(doc_id, &[attachment_id]))
+            .query(&[("rev", rev.as_str())]);
+        self.send_json(request).await
+    }
+
+    /// Query documents with a Mango selector
+    pub async fn find(&self, options: &FindOptions) -> Result<FindResponse<JsonDocument>, Error> {
+        let request = self
+            .request(Method::POST, self.db_url(&["_find"]))
+            .json(options);
+        let response: Find = self.send_json(request).await?;
+
+        Ok(FindResponse {
+            docs: response
+                .docs
+                .into_iter()
+                .map(JsonDocument::from_json)
+                .collect::<Result<_, _>>()?,
+            warning: response.warning,
+            bookmark: response.bookmark,
+        })
+    }
+
+    /// Create a Mango index
+    pub async fn create_index(&self, options: &IndexOptions) -> Result<CreateIndexResponse, Error> {
+        let mut index = json!({ "fields": options.fields });
+        if let Some(selector) = &options.partial_filter_selector {
+            index["partial_filter_selector"] = serde_json::to_value(selector)?;
+        }
+        let mut body = json!({ "index": index, "type": "json" });
+        if let Some(name) = &options.name {
+            body["name"] = json!(name);
+        }
+        if let Some(ddoc) = &options.ddoc {
+            body["ddoc"] = json!(ddoc);
+        }
+        let request = self
+            .request(Method::POST, self.db_url(&["_index"]))
+            .json(&body);
+        self.send_json(request).await
+    }
+
+    /// Start a cookie session
+    ///
+    /// The session cookie is sent with all further requests of this instance.
+    pub async fn log_in(&self, username: &str, password: &str) -> Result<Session, Error> {
+        let request = self
+            .request(Method::POST, self.server_url(&["_session"]))
+            .json(&json!({ "name": username, "password": password }));
+        let response: Value = self.send_to_server(request).await?.json().await?;
+        session::logged_in(&response)
+    }
+
+    /// End the cookie session
+    pub async fn log_out(&self) -> Result<(), Error> {
+        let request = self.request(Method::DELETE, self.server_url(&["_session"]));
+        self.send_to_server(request).await?;
+        Ok(())
+    }
+
+    /// Get the current session
+    pub async fn get_session(&self) -> Result<Session, Error> {
+        let request = self.request(Method::GET, self.server_url(&["_session"]));
+        Ok(self.send_to_server(request).await?.json().await?)
+    }
+
+    /// Create a user with `password`
+    pub async fn sign_up(&self, user: &User, password: &str) -> Result<ChangeResponse, Error> {
+        let user = User {
+            password: Some(password.to_owned()),
+            ..user.clone()
+        };
+        self.put_user(&user).await
+    }
+
+    /// Set a new password for the user `name`
+    ///
+    /// The session of the user ends if it is the current one, so log in again.
+    pub async fn change_password(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<ChangeResponse, Error> {
+        let user = User {
+            password: Some(password.to_owned()),
+            ..self.get_user(name).await?
+        };
+        self.put_user(&user).await
+    }
+
+    /// Fetch the user `name`
+    pub async fn get_user(&self, name: &str) -> Result<User, Error> {
+        let id = session::user_id(name);
+        let request = self.request(Method::GET, self.server_url(&["_users", &id]));
+        User::from_json(self.send_to_server(request).await?.json().await?)
+    }
+
+    /// Create or update a user
+    ///
+    /// To update an existing user, [User::rev] must be set, e.g. by fetching it with
+    /// [get_user](Self::get_user) first.
+    pub async fn put_user(&self, user: &User) -> Result<ChangeResponse, Error> {
+        let request = self
+            .request(Method::PUT, self.server_url(&["_users", &user.id()]))
+            .json(&user.to_json()?);
+        Ok(self.send_to_server(request).await?.json().await?)
+    }
+}
+
+impl std::fmt::Debug for CouchDB {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "CouchDB {}", self.url)
+    }
+}
+
+impl Database for CouchDB {
+    async fn info(&self) -> Result<DatabaseInfo, Error> {
+        CouchDB::info(self).await
+    }
+
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        CouchDB::put(self, doc).await
+    }
+
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        CouchDB::post(self, doc).await
+    }
+
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        let options = FetchOptions {
+            attachments: false,
+            ..options.clone()
+        };
+        CouchDB::fetch(self, doc_id, &options).await
+    }
+
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        CouchDB::remove(self, doc_id, rev).await
+    }
+
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        CouchDB::bulk_docs(self, docs).await
+    }
+
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        let options = AllDocsOptions {
+            attachments: false,
+            ..options.clone()
+        };
+        CouchDB::all_docs(self, &options).await
+    }
+
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        self.changes_oneshot(options).await
+    }
+    /// The URL of the database without credentials, so that they don't change the id and
+    /// don't end up in replication checkpoints.
+    async fn id(&self) -> Result<String, Error> {
+        let mut url = self.db_url(&[""]);
+        url.set_username("").expect("checked in new");
+        url.set_password(None).expect("checked in new");
+        Ok(url.into())
+    }
+
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        CouchDB::revs_diff(self, revs).await
+    }
+
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        CouchDB::bulk_get(self, docs).await
+    }
+
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        CouchDB::bulk_docs_replicated(self, docs).await
+    }
+}
+
+#[cfg(test)]
+mod tests;
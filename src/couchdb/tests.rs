@@ -0,0 +1,562 @@
+use super::*;
+use futures::TryStreamExt;
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+use tiny_http::{Header, Server};
+
+use crate::{
+    options::{find::Sort, selector::Selector},
+    responses::AllDocsValue,
+};
+
+#[derive(Debug, Clone)]
+struct Request {
+    method: String,
+    url: String,
+    body: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "application/json".to_owned())],
+            body: body.to_string().into_bytes(),
+        }
+    }
+}
+
+/// A fake CouchDB, answering with `handler` and recording the requests.
+struct Mock {
+    server: Arc<Server>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Mock {
+    fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> Reply + Send + 'static,
+    {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (incoming, recorded) = (server.clone(), requests.clone());
+        thread::spawn(move || {
+            for mut request in incoming.incoming_requests() {
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+                let recorded_request = Request {
+                    method: request.method().to_string(),
+                    url: request.url().to_owned(),
+                    body,
+                    headers: request
+                        .headers()
+                        .iter()
+                        .map(|header| (header.field.to_string(), header.value.to_string()))
+                        .collect(),
+                };
+                let reply = handler(&recorded_request);
+                recorded.lock().unwrap().push(recorded_request);
+                let mut response =
+                    tiny_http::Response::from_data(reply.body).with_status_code(reply.status);
+                for (name, value) in reply.headers {
+                    response.add_header(Header::from_bytes(name, value).unwrap());
+                }
+                let _ = request.respond(response);
+            }
+        });
+        Self { server, requests }
+    }
+
+    fn db(&self) -> CouchDB {
+        CouchDB::new_with_options(
+            CreateOptions::default()
+                .name(self.url("db"))
+                .skip_setup(true),
+        )
+        .unwrap()
+    }
+
+    fn url(&self, path: &str) -> String {
+        let port = self.server.server_addr().to_ip().unwrap().port();
+        format!("http://127.0.0.1:{}/{}", port, path)
+    }
+
+    fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+#[tokio::test]
+async fn documents_are_sent_and_parsed() {
+    let mock = Mock::new(|request| match request.method.as_str() {
+        "PUT" => Reply::json(201, json!({"ok": true, "id": "a/b", "rev": "1-x"})),
+        "GET" => Reply::json(
+            200,
+            json!({"_id": "_design/app", "_rev": "2-y", "_conflicts": ["2-x"], "views": {}}),
+        ),
+        _ => Reply::json(200, json!({"ok": true, "id": "a/b", "rev": "2-z"})),
+    });
+    let db = mock.db();
+
+    let response = db
+        .put(&JsonDocument::new("a/b", json!({"n": 1})))
+        .await
+        .unwrap();
+    assert_eq!(response.rev, Revision::from("1-x"));
+    let doc = db
+        .fetch(
+            "_design/app",
+            &FetchOptions::default().conflicts(Vec::new()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(doc.rev, Some(Revision::from("2-y")));
+    assert_eq!(doc.conflicts, vec![Revision::from("2-x")]);
+    assert_eq!(doc.data, json!({"views": {}}));
+    db.remove("a/b", &Revision::from("1-x")).await.unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].url, "/db/a%2Fb");
+    assert_eq!(requests[0].json(), json!({"n": 1, "_id": "a/b"}));
+    assert_eq!(requests[1].url, "/db/_design/app?conflicts=true");
+    assert_eq!(requests[2].method, "DELETE");
+    assert_eq!(requests[2].url, "/db/a%2Fb?rev=1-x");
+}
+
+#[tokio::test]
+async fn errors_are_mapped_to_variants() {
+    let mock = Mock::new(|request| match request.method.as_str() {
+        "PUT" => Reply::json(
+            409,
+            json!({"error": "conflict", "reason": "Document update conflict."}),
+        ),
+        "POST" => Reply::json(
+            201,
+            json!([
+                {"ok": true, "id": "a", "rev": "1-a"},
+                {"id": "b", "error": "conflict", "reason": "Document update conflict."},
+                {"id": "c", "error": "forbidden", "reason": "Nope."},
+            ]),
+        ),
+        _ => Reply::json(404, json!({"error": "not_found", "reason": "missing"})),
+    });
+    let db = mock.db();
+
+    match db.put(&JsonDocument::new("a", json!({}))).await {
+        Err(Error::Conflict(reason)) => assert_eq!(reason, "Document update conflict."),
+        other => panic!("unexpected {:?}", other),
+    }
+    match db.fetch("a", &FetchOptions::default()).await {
+        Err(Error::NotFound(reason)) => assert_eq!(reason, "missing"),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let results = db
+        .bulk_docs(&[
+            JsonDocument::new("a", json!({})),
+            JsonDocument::new("b", json!({})),
+            JsonDocument::new("", json!({})),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(results[0].as_ref().unwrap().rev, Revision::from("1-a"));
+    assert!(matches!(results[1], Err(Error::Conflict(_))));
+    assert!(matches!(results[2], Err(Error::Unauthorized(_))));
+    assert_eq!(
+        mock.requests()[2].json(),
+        json!({"docs": [{"_id": "a"}, {"_id": "b"}, {}]})
+    );
+}
+
+#[tokio::test]
+async fn all_docs_posts_keys() {
+    let mock = Mock::new(|_| {
+        Reply::json(
+            200,
+            json!({"total_rows": 2, "offset": 0, "rows": [
+                {"id": "a", "key": "a", "value": {"rev": "1-a"}, "doc": {"_id": "a", "_rev": "1-a", "n": 1}},
+                {"id": "b", "key": "b", "value": {"rev": "2-b", "deleted": true}, "doc": null},
+                {"key": "c", "error": "not_found"},
+            ]}),
+        )
+    });
+    let db = mock.db();
+
+    let options = AllDocsOptions {
+        include_docs: true,
+        keys: vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+        ..AllDocsOptions::default()
+    };
+    let response = db.all_docs(&options).await.unwrap();
+    assert_eq!(response.total_rows, 2);
+    match &response.rows[0].value {
+        AllDocsValue::Found { doc: Some(doc), .. } => assert_eq!(doc.data, json!({"n": 1})),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(
+        response.rows[1].value,
+        AllDocsValue::Deleted { .. }
+    ));
+    assert!(matches!(response.rows[2].value, AllDocsValue::NotFound));
+
+    let options = AllDocsOptions {
+        startkey: Some("a".to_owned()),
+        limit: Some(2),
+        ..AllDocsOptions::default()
+    };
+    db.all_docs(&options).await.unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].url, "/db/_all_docs?include_docs=true");
+    assert_eq!(requests[0].json(), json!({"keys": ["a", "b", "c"]}));
+    assert_eq!(requests[1].url, "/db/_all_docs?startkey=%22a%22&limit=2");
+}
+
+#[tokio::test]
+async fn changes_feeds_are_parsed() {
+    let mock = Mock::new(|request| {
+        if request.url.contains("feed=continuous") {
+            let body = concat!(
+                "{\"seq\":\"1-a\",\"id\":\"a\",\"changes\":[{\"rev\":\"1-a\"}]}\n",
+                "\n",
+                "{\"seq\":\"2-b\",\"id\":\"b\",\"changes\":[{\"rev\":\"2-b\"}],\"deleted\":true}\n",
+                "{\"last_seq\":\"2-b\",\"pending\":0}\n",
+            );
+            Reply {
+                status: 200,
+                headers: Vec::new(),
+                body: body.as_bytes().to_vec(),
+            }
+        } else {
+            Reply::json(
+                200,
+                json!({"results": [
+                    {"seq": 3, "id": "a", "changes": [{"rev": "2-a"}], "doc": {"_id": "a", "_rev": "2-a"}},
+                ], "last_seq": 3}),
+            )
+        }
+    });
+    let db = mock.db();
+
+    let options = Changes {
+        since: Some(SequenceID::from(2)),
+        include_docs: true,
+        ..Changes::default()
+    };
+    let (results, last_seq) = db.changes_oneshot(&options).await.unwrap();
+    assert_eq!(last_seq.number(), Some(3));
+    assert_eq!(results[0].doc.as_ref().unwrap().id, "a");
+
+    let options = Changes {
+        doc_ids: vec!["a".to_owned()],
+        ..Changes::default()
+    };
+    db.changes_longpoll(&options).await.unwrap();
+
+    let events: Vec<_> = db
+        .changes(&Changes::default())
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].id, "b");
+    assert!(events[1].deleted);
+    assert_eq!(events[1].seq, SequenceID::from("2-b"));
+
+    let options = Changes {
+        doc_ids: vec!["a".to_owned()],
+        filter: Some("app/important".to_owned()),
+        ..Changes::default()
+    };
+    assert!(matches!(
+        db.changes_oneshot(&options).await,
+        Err(Error::BadRequest(_))
+    ));
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(
+        requests[0].url,
+        "/db/_changes?include_docs=true&since=2&feed=normal"
+    );
+    assert_eq!(requests[1].method, "POST");
+    assert_eq!(
+        requests[1].url,
+        "/db/_changes?feed=longpoll&filter=_doc_ids"
+    );
+    assert_eq!(requests[1].json(), json!({"doc_ids": ["a"]}));
+    assert_eq!(requests[2].url, "/db/_changes?feed=continuous");
+}
+
+#[tokio::test]
+async fn attachments_keep_their_bytes() {
+    let mock = Mock::new(|request| match request.method.as_str() {
+        "PUT" => Reply::json(201, json!({"ok": true, "id": "a", "rev": "2-a"})),
+        _ => Reply {
+            status: 200,
+            headers: vec![("Content-Type", "image/png".to_owned())],
+            body: vec![0, 159, 146, 150],
+        },
+    });
+    let db = mock.db();
+
+    db.put_attachment(
+        "a",
+        "pic.png",
+        Some(&Revision::from("1-a")),
+        vec![0, 159, 146, 150],
+        "image/png",
+    )
+    .await
+    .unwrap();
+    let (content_type, data) = db.get_attachment("a", "pic.png", None).await.unwrap();
+    assert_eq!(content_type, "image/png");
+    assert_eq!(data, vec![0, 159, 146, 150]);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].url, "/db/a/pic.png?rev=1-a");
+    assert_eq!(requests[0].header("Content-Type"), Some("image/png"));
+    assert_eq!(requests[0].body, vec![0, 159, 146, 150]);
+}
+
+#[tokio::test]
+async fn find_and_create_index() {
+    let mock = Mock::new(|request| {
+        if request.url.ends_with("_index") {
+            Reply::json(
+                200,
+                json!({"result": "created", "id": "_design/idx", "name": "by-name"}),
+            )
+        } else {
+            Reply::json(
+                200,
+                json!({"docs": [{"_id": "a", "_rev": "1-a", "name": "Alice"}], "bookmark": "g1"}),
+            )
+        }
+    });
+    let db = mock.db();
+
+    let response = db
+        .create_index(&IndexOptions::new(vec![Sort::from("name")]).name("by-name"))
+        .await
+        .unwrap();
+    assert_eq!(response.result, "created");
+    let response = db
+        .find(&FindOptions::new(Selector::default().field("name", "Alice")).limit(1))
+        .await
+        .unwrap();
+    assert_eq!(response.docs[0].data, json!({"name": "Alice"}));
+    assert_eq!(response.bookmark.as_deref(), Some("g1"));
+
+    let requests = mock.requests();
+    assert_eq!(
+        requests[0].json(),
+        json!({"index": {"fields": [{"name": "asc"}]}, "type": "json", "name": "by-name"})
+    );
+    assert_eq!(requests[1].url, "/db/_find");
+    assert_eq!(
+        requests[1].json(),
+        json!({"selector": {"name": "Alice"}, "limit": 1})
+    );
+}
+
+#[tokio::test]
+async fn sessions_and_setup() {
+    let created = AtomicBool::new(false);
+    let mock = Mock::new(
+        move |request| match (request.method.as_str(), request.url.as_str()) {
+            ("POST", "/_session") => {
+                let mut reply = Reply::json(200, json!({"ok": true, "name": "bob", "roles": []}));
+                reply
+                    .headers
+                    .push(("Set-Cookie", "AuthSession=abc; Path=/; HttpOnly".to_owned()));
+                reply
+            }
+            ("GET", "/_session") => Reply::json(
+                200,
+                json!({"ok": true, "userCtx": {"name": "bob", "roles": ["admin"]}}),
+            ),
+            ("GET", "/db") if !created.load(Ordering::SeqCst) => Reply::json(
+                404,
+                json!({"error": "not_found", "reason": "Database does not exist."}),
+            ),
+            ("PUT", "/db") => {
+                created.store(true, Ordering::SeqCst);
+                Reply::json(201, json!({"ok": true}))
+            }
+            _ => Reply::json(
+                200,
+                json!({"db_name": "db", "doc_count": 0, "update_seq": "0-a"}),
+            ),
+        },
+    );
+    let db = CouchDB::new(mock.url("db")).unwrap();
+    assert_eq!(db.info().await.unwrap().db_name, "db");
+
+    let session = db.log_in("bob", "secret").await.unwrap();
+    assert_eq!(session.user_ctx.name.as_deref(), Some("bob"));
+    let session = db.get_session().await.unwrap();
+    assert_eq!(session.user_ctx.roles, vec!["admin"]);
+
+    let auth = Auth::basic("alice", "pw");
+    let other = CouchDB::new_with_options(
+        CreateOptions::default()
+            .name(mock.url("db"))
+            .auth(auth)
+            .skip_setup(true),
+    )
+    .unwrap();
+    other.info().await.unwrap();
+    let with_userinfo = CouchDB::new("http://alice:pw@localhost:5984/db").unwrap();
+    assert_eq!(
+        Database::id(&with_userinfo).await.unwrap(),
+        "http://localhost:5984/db/"
+    );
+
+    let requests = mock.requests();
+    let methods: Vec<_> = requests
+        .iter()
+        .map(|request| format!("{} {}", request.method, request.url))
+        .collect();
+    assert_eq!(
+        methods,
+        [
+            "GET /db",
+            "PUT /db",
+            "GET /db",
+            "POST /_session",
+            "GET /_session",
+            "GET /db"
+        ]
+    );
+    assert_eq!(
+        requests[3].json(),
+        json!({"name": "bob", "password": "secret"})
+    );
+    assert_eq!(requests[4].header("Cookie"), Some("AuthSession=abc"));
+    assert_eq!(
+        requests[5].header("Authorization"),
+        Some("Basic YWxpY2U6cHc=")
+    );
+}
+
+#[tokio::test]
+async fn users_are_managed() {
+    let mock = Mock::new(|request| match request.method.as_str() {
+        "GET" => Reply::json(
+            200,
+            json!({
+                "_id": "org.couchdb.user:bob",
+                "_rev": "1-a",
+                "type": "user",
+                "name": "bob",
+                "roles": ["reader"],
+                "derived_key": "abc",
+            }),
+        ),
+        _ => Reply::json(
+            201,
+            json!({"ok": true, "id": "org.couchdb.user:bob", "rev": "2-b"}),
+        ),
+    });
+    let db = mock.db();
+    db.sign_up(&User::new("bob").roles(["reader"]), "secret")
+        .await
+        .unwrap();
+    let user = db.get_user("bob").await.unwrap();
+    assert_eq!(user.roles, vec!["reader"]);
+    let response = db.change_password("bob", "new").await.unwrap();
+    assert_eq!(response.rev, "2-b".into());
+
+    let requests = mock.requests();
+    let methods: Vec<_> = requests
+        .iter()
+        .map(|request| format!("{} {}", request.method, request.url))
+        .collect();
+    assert_eq!(
+        methods,
+        [
+            "PUT /_users/org.couchdb.user:bob",
+            "GET /_users/org.couchdb.user:bob",
+            "GET /_users/org.couchdb.user:bob",
+            "PUT /_users/org.couchdb.user:bob",
+        ]
+    );
+    assert_eq!(
+        requests[0].json(),
+        json!({
+            "_id": "org.couchdb.user:bob",
+            "type": "user",
+            "name": "bob",
+            "roles": ["reader"],
+            "password": "secret",
+        })
+    );
+    let update = requests[3].json();
+    assert_eq!(update["_rev"], "1-a");
+    assert_eq!(update["password"], "new");
+    assert_eq!(update["derived_key"], "abc");
+}
+
+#[tokio::test]
+async fn credentials_can_be_rotated() {
+    let mock = Mock::new(|_| {
+        Reply::json(
+            200,
+            json!({"db_name": "db", "doc_count": 0, "update_seq": "0-a"}),
+        )
+    });
+    let db = CouchDB::new_with_options(
+        CreateOptions::default()
+            .name(mock.url("db"))
+            .auth(Auth::bearer("first"))
+            .skip_setup(true),
+    )
+    .unwrap();
+    db.info().await.unwrap();
+    db.set_auth(Auth::bearer("second"));
+    db.info().await.unwrap();
+    db.set_auth(Auth::proxy("bob", ["reader", "writer"], None));
+    db.info().await.unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].header("Authorization"), Some("Bearer first"));
+    assert_eq!(requests[1].header("Authorization"), Some("Bearer second"));
+    assert_eq!(requests[2].header("Authorization"), None);
+    assert_eq!(requests[2].header("X-Auth-CouchDB-UserName"), Some("bob"));
+    assert_eq!(
+        requests[2].header("X-Auth-CouchDB-Roles"),
+        Some("reader,writer")
+    );
+}
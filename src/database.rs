@@ -0,0 +1,368 @@
+//! The [Database] trait abstracts over database backends, so code using it can run against
+//! [PouchDB] in the browser and against [MemoryDatabase](crate::memory::MemoryDatabase) in
+//! native unit tests.
+
+use js_sys::{Array, Function, Object, Promise, Reflect};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::{
+    document::{self, Document, Revision, SerializedDocument},
+    error::Error,
+    events::{changes_event_emitter::ChangeEvent, SequenceID},
+    options::{all_docs::AllDocsOptions, changes::Changes, fetch::FetchOptions},
+    responses::{AllDocsResponse, ChangeResponse, DatabaseInfo, RevsDiffEntry},
+    PouchDB,
+};
+
+/// A document as plain JSON, as used by [Database].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JsonDocument {
+    pub id: String,
+    pub rev: Option<Revision>,
+    pub deleted: bool,
+    /// The conflicting leaf revisions, if they were requested.
+    pub conflicts: Vec<Revision>,
+    /// The fields of the document, without `_id`, `_rev`, `_deleted` and `_conflicts`.
+    /// Attachments are represented by their stubs in `_attachments`.
+    pub data: Value,
+}
+
+impl JsonDocument {
+    pub fn new<T: Into<String>>(id: T, data: Value) -> Self {
+        Self {
+            id: id.into(),
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// A document deleting revision `rev` of `id`.
+    pub fn deleted<T: Into<String>>(id: T, rev: Revision) -> Self {
+        Self {
+            id: id.into(),
+            rev: Some(rev),
+            deleted: true,
+            data: Value::Object(Map::new()),
+            ..Self::default()
+        }
+    }
+
+    pub fn rev(self, rev: Revision) -> Self {
+        Self {
+            rev: Some(rev),
+            ..self
+        }
+    }
+
+    /// Parse a document as returned by PouchDB or CouchDB.
+    pub fn from_json(json: Value) -> Result<Self, Error> {
+        let mut data = match json {
+            Value::Object(data) => data,
+            _ => {
+                return Err(Error::BadRequest(
+                    "Document must be a JSON object".to_owned(),
+                ))
+            }
+        };
+        let id = match data.remove("_id") {
+            Some(Value::String(id)) => id,
+            _ => return Err(Error::BadRequest("Document id is not a string".to_owned())),
+        };
+        let rev = match data.remove("_rev") {
+            Some(Value::String(rev)) => Some(Revision(rev)),
+            _ => None,
+        };
+        let deleted = data.remove("_deleted") == Some(Value::Bool(true));
+        let conflicts = match data.remove("_conflicts") {
+            Some(Value::Array(conflicts)) => conflicts
+                .into_iter()
+                .filter_map(|rev| rev.as_str().map(Revision::from))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            id,
+            rev,
+            deleted,
+            conflicts,
+            data: Value::Object(data),
+        })
+    }
+
+    /// The document as PouchDB stores it, i.e. the fields followed by `_id` and `_rev`.
+    /// Deleted documents keep their fields, like in PouchDB, and get `_deleted`.
+    pub fn to_json(&self) -> Value {
+        let mut json = match &self.data {
+            Value::Object(data) => data.clone(),
+            _ => Map::new(),
+        };
+        if self.deleted {
+            json.insert("_deleted".to_owned(), Value::Bool(true));
+        }
+        json.insert("_id".to_owned(), Value::String(self.id.clone()));
+        if let Some(rev) = &self.rev {
+            json.insert("_rev".to_owned(), Value::String(rev.0.clone()));
+        }
+        Value::Object(json)
+    }
+
+    /// Deserialize the fields of the document.
+    pub fn deserialize<T>(&self) -> Result<T, Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        Ok(serde_json::from_value(self.data.clone())?)
+    }
+}
+
+impl Document for JsonDocument {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+    fn rev(&self) -> Option<&Revision> {
+        self.rev.as_ref()
+    }
+    fn serialize(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.data).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+    fn deleted(&self) -> bool {
+        self.deleted
+    }
+}
+
+impl TryFrom<SerializedDocument> for JsonDocument {
+    type Error = Error;
+    fn try_from(doc: SerializedDocument) -> Result<Self, Self::Error> {
+        JsonDocument::from_json(doc.data.into_serde()?)
+    }
+}
+
+/// Parses the response of `_bulk_get`, in the format of PouchDB and CouchDB.
+pub(crate) fn bulk_get_results(response: Value) -> Vec<Result<JsonDocument, Error>> {
+    response["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|result| result["docs"].as_array().cloned().unwrap_or_default())
+        .map(|mut doc| match doc.get_mut("ok") {
+            Some(ok) => JsonDocument::from_json(ok.take()),
+            None => Err(Error::from_json(&doc["error"])),
+        })
+        .collect()
+}
+
+/// The operations shared by all database backends.
+///
+/// The methods work like the ones of [PouchDB] with the same name, but use [JsonDocument]
+/// and report per-document errors of [bulk_docs](Database::bulk_docs) individually.
+/// Conflicts and missing documents are reported as [Error::Conflict] and
+/// [Error::NotFound].
+// The futures of `PouchDB` aren't `Send` anyway, so there's no point in requiring it.
+#[allow(async_fn_in_trait)]
+pub trait Database {
+    /// Get information about the database.
+    async fn info(&self) -> Result<DatabaseInfo, Error>;
+    /// Create/update a document. See [PouchDB::put].
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error>;
+    /// Create a document with a generated id. See [PouchDB::post].
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error>;
+    /// Fetch a document. See [PouchDB::fetch]. [FetchOptions::attachments] is ignored.
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error>;
+    /// Delete revision `rev` of a document.
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error>;
+    /// Create/update a batch of documents, returning the result for each document.
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error>;
+    /// Fetch multiple documents. See [PouchDB::all_docs]. [AllDocsOptions::attachments] is
+    /// ignored.
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error>;
+    /// The changes made since [Changes::since]. See [PouchDB::changes_oneshot].
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error>;
+    /// An id identifying the database, which is part of replication ids.
+    async fn id(&self) -> Result<String, Error>;
+    /// Compare revisions with the database. See [PouchDB::revs_diff].
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error>;
+    /// Fetch the given revisions of documents, with their history in `_revisions` and
+    /// the attachment data inline, like `_bulk_get` does.
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error>;
+    /// Store revisions made in another database, like `_bulk_docs` with `new_edits: false`.
+    ///
+    /// The revisions are added as they are, with the history given in `_revisions` of the
+    /// documents, so they may create conflicts. Known revisions are skipped.
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error>;
+}
+
+impl Database for PouchDB {
+    async fn info(&self) -> Result<DatabaseInfo, Error> {
+        PouchDB::info(self).await
+    }
+
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        PouchDB::put(self, doc, false).await
+    }
+
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        PouchDB::post(self, doc).await
+    }
+
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        let options = FetchOptions {
+            attachments: false,
+            ..options.clone()
+        };
+        PouchDB::fetch(self, doc_id, &options).await?.try_into()
+    }
+
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        PouchDB::remove(self, &JsonDocument::deleted(doc_id, rev.clone())).await
+    }
+
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        let array = Array::new();
+        for doc in docs {
+            array.push(&self.2.incoming_js(document::serialize(doc)?).await?);
+        }
+        let response: Array = JsFuture::from(self.0.bulk_docs(array.into()))
+            .await?
+            .dyn_into()?;
+
+        Ok(response
+            .iter()
+            .map(|result| {
+                if Reflect::get(&result, &JsValue::from_str("error"))?.is_truthy() {
+                    Err(Error::from_status(result))
+                } else {
+                    result.try_into()
+                }
+            })
+            .collect())
+    }
+
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        let options = AllDocsOptions {
+            attachments: false,
+            ..options.clone()
+        };
+        PouchDB::all_docs(self, &options)
+            .await?
+            .try_map_docs(JsonDocument::try_from)
+    }
+
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        let (results, last_seq) = self.changes_oneshot(options).await?;
+        let results = results
+            .into_iter()
+            .map(|event| {
+                Ok(ChangeEvent {
+                    id: event.id,
+                    changes: event.changes,
+                    seq: event.seq,
+                    deleted: event.deleted,
+                    doc: event.doc.map(JsonDocument::try_from).transpose()?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok((results, last_seq))
+    }
+    async fn id(&self) -> Result<String, Error> {
+        let id: Function = Reflect::get(&self.0, &JsValue::from_str("id"))?.dyn_into()?;
+        let id: Promise = id.call0(&self.0)?.dyn_into()?;
+        JsFuture::from(id)
+            .await?
+            .as_string()
+            .ok_or_else(|| Error::BadRequest("The database id is not a string".to_owned()))
+    }
+
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        PouchDB::revs_diff(self, revs).await
+    }
+
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        let requests = Array::new();
+        for (id, rev) in docs {
+            let request = Object::new();
+            Reflect::set(&request, &JsValue::from_str("id"), &JsValue::from_str(id))?;
+            Reflect::set(&request, &JsValue::from_str("rev"), &rev.to_js())?;
+            requests.push(&request);
+        }
+        let options = Object::new();
+        Reflect::set(&options, &JsValue::from_str("docs"), &requests)?;
+        Reflect::set(&options, &JsValue::from_str("revs"), &JsValue::TRUE)?;
+        Reflect::set(&options, &JsValue::from_str("attachments"), &JsValue::TRUE)?;
+
+        let response = JsFuture::from(self.0.bulk_get(options.into())).await?;
+        Ok(bulk_get_results(response.into_serde()?))
+    }
+
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let array = Array::new();
+        for doc in docs {
+            array.push(&JsValue::from_serde(&doc.to_json())?);
+        }
+        let options = Object::new();
+        Reflect::set(&options, &JsValue::from_str("new_edits"), &JsValue::FALSE)?;
+        let response: Array =
+            JsFuture::from(self.0.bulk_docs_with_options(array.into(), options.into()))
+                .await?
+                .dyn_into()?;
+
+        let error = JsValue::from_str("error");
+        let id = JsValue::from_str("id");
+        Ok(docs
+            .iter()
+            .map(|doc| {
+                let failure = response.iter().find(|result| {
+                    Reflect::get(result, &error).is_ok_and(|error| error.is_truthy())
+                        && Reflect::get(result, &id).ok().and_then(|id| id.as_string())
+                            == Some(doc.id.clone())
+                });
+                match failure {
+                    Some(failure) => Err(Error::from_status(failure)),
+                    None => Ok(()),
+                }
+            })
+            .collect())
+    }
+}
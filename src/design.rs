@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
+
+use crate::document::{Document, Revision};
+
+pub(crate) const DESIGN_PREFIX: &str = "_design/";
+
+/// A view in a design document. The map and reduce functions are JavaScript source
+/// strings; `reduce` may also be one of the built-in reduce functions (`"_count"`,
+/// `"_sum"` or `"_stats"`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct View {
+    pub map: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reduce: Option<String>,
+}
+
+impl View {
+    pub fn new<T: Into<String>>(map: T) -> Self {
+        Self {
+            map: map.into(),
+            reduce: None,
+        }
+    }
+    pub fn reduce<T: Into<String>>(self, reduce: T) -> Self {
+        Self {
+            reduce: Some(reduce.into()),
+            ..self
+        }
+    }
+}
+
+/// A design document, containing persisted views, filter functions and validation.
+///
+/// Views in a design document are indexed incrementally, so querying them with
+/// [PouchDB::query_view](crate::PouchDB::query_view) is much faster than running a
+/// temporary view with [PouchDB::query](crate::PouchDB::query).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DesignDocument {
+    /// The name of the design document, without the `_design/` prefix.
+    #[serde(skip)]
+    pub name: String,
+    /// The revision, required when updating an existing design document.
+    #[serde(skip)]
+    pub rev: Option<Revision>,
+    /// The language of the functions. Defaults to `"javascript"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub views: HashMap<String, View>,
+    /// Filter functions for the changes feed and replication, referenced as
+    /// `"<design document name>/<filter name>"`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub filters: HashMap<String, String>,
+    /// Validation function, called for every document written to the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validate_doc_update: Option<String>,
+}
+
+impl DesignDocument {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+    pub fn language<T: Into<String>>(self, language: T) -> Self {
+        Self {
+            language: Some(language.into()),
+            ..self
+        }
+    }
+    pub fn view<T: Into<String>>(mut self, name: T, view: View) -> Self {
+        self.views.insert(name.into(), view);
+        self
+    }
+    pub fn filter<T: Into<String>, F: Into<String>>(mut self, name: T, filter: F) -> Self {
+        self.filters.insert(name.into(), filter.into());
+        self
+    }
+    pub fn validate_doc_update<T: Into<String>>(self, validate_doc_update: T) -> Self {
+        Self {
+            validate_doc_update: Some(validate_doc_update.into()),
+            ..self
+        }
+    }
+}
+
+impl Document for DesignDocument {
+    fn id(&self) -> String {
+        format!("{}{}", DESIGN_PREFIX, self.name)
+    }
+    fn rev(&self) -> Option<&Revision> {
+        self.rev.as_ref()
+    }
+    fn serialize(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(self).map_err(|err| JsValue::from_str(&format!("{}", err)))
+    }
+}
@@ -1,43 +1,81 @@
 use js_sys::{Array, JsString, Object, Promise, Reflect, Uint8Array, WebAssembly, JSON};
+use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
 use serde_json::error::Result as SerdeResult;
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    convert::TryFrom,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{Blob, BlobPropertyBag};
 
-#[derive(Clone, PartialEq)]
-pub struct Revision(pub(crate) JsValue);
+/// The revision of a document, e.g. `1-967a00dff5e02add41819138abb3284d`.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Revision(pub(crate) String);
 
-impl AsRef<JsValue> for Revision {
-    fn as_ref(&self) -> &JsValue {
+impl Revision {
+    pub(crate) fn from_js(value: JsValue) -> Option<Self> {
+        value.as_string().map(Self)
+    }
+    pub(crate) fn to_js(&self) -> JsValue {
+        JsValue::from_str(&self.0)
+    }
+    pub fn as_str(&self) -> &str {
         &self.0
     }
-}
-
-impl From<JsValue> for Revision {
-    fn from(value: JsValue) -> Self {
-        Revision(value)
+    /// The number of edits that led to this revision (the part before the `-`).
+    ///
+    /// Returns 0 if the revision isn't well-formed.
+    pub fn generation(&self) -> u64 {
+        self.0
+            .split('-')
+            .next()
+            .and_then(|generation| generation.parse().ok())
+            .unwrap_or(0)
+    }
+    /// The hash of this revision (the part after the `-`).
+    pub fn hash(&self) -> &str {
+        self.0.split_once('-').map_or("", |(_, hash)| hash)
     }
 }
 
-impl Into<String> for Revision {
-    fn into(self) -> String {
-        self.0.as_string().unwrap()
+impl From<Revision> for String {
+    fn from(rev: Revision) -> Self {
+        rev.0
     }
 }
 
 impl From<&str> for Revision {
     fn from(s: &str) -> Self {
-        Self(JsValue::from_str(s))
+        Self(s.to_owned())
+    }
+}
+
+impl From<String> for Revision {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl AsRef<str> for Revision {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Revision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
 impl std::fmt::Debug for Revision {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("Revision")
-            .field(&self.0.as_string().unwrap())
-            .finish()
+        f.debug_tuple("Revision").field(&self.0).finish()
     }
 }
 
@@ -95,7 +133,7 @@ where
         &JsValue::from_str(&doc.id()),
     )?;
     if let Some(rev) = doc.rev() {
-        Reflect::set(&object, &JsValue::from_str("_rev"), &rev.0)?;
+        Reflect::set(&object, &JsValue::from_str("_rev"), &rev.to_js())?;
     }
 
     Ok(object)
@@ -124,6 +162,8 @@ impl Attachment {
 
 #[derive(Debug, Clone)]
 pub struct SerializedDocument {
+    /// Empty for the documents of [PouchDB::find](crate::PouchDB::find) if
+    /// [FindOptions::fields](crate::options::find::FindOptions::fields) leaves out `_id`.
     pub id: String,
     pub rev: Option<Revision>,
     pub conflicts: Vec<Revision>,
@@ -181,37 +221,31 @@ impl SerializedDocument {
             data,
         })
     }
-
-    pub(crate) fn new_deleted(id: &str, rev: JsValue) -> Self {
-        Self {
-            id: id.to_owned(),
-            rev: Some(Revision(rev)),
-            conflicts: Vec::new(),
-            attachments: HashMap::new(),
-            deleted: true,
-            data: JsValue::NULL,
-        }
-    }
 }
 
 impl TryFrom<JsValue> for SerializedDocument {
     type Error = JsValue;
 
     fn try_from(data: JsValue) -> Result<Self, Self::Error> {
-        let id: JsString = Reflect::get(&data, &JsValue::from_str("_id"))?.dyn_into()?;
-        let id = id
-            .as_string()
-            .ok_or_else(|| JsValue::from_str("Document id is not a string."))?;
+        // Documents of `find` don't have an id if its `fields` leave it out.
+        let id = Reflect::get(&data, &JsValue::from_str("_id"))?;
+        let id = if id.is_undefined() {
+            String::new()
+        } else {
+            id.dyn_into::<JsString>()?
+                .as_string()
+                .ok_or_else(|| JsValue::from_str("Document id is not a string."))?
+        };
         let rev = Reflect::get(&data, &JsValue::from_str("_rev"))
             .ok()
-            .map(Revision);
+            .and_then(Revision::from_js);
         let conflicts = Reflect::get(&data, &JsValue::from_str("_conflicts"))
             .ok()
             .filter(|conflicts| conflicts.is_truthy())
             .map(|conflicts| {
                 <js_sys::Array as std::convert::From<JsValue>>::from(conflicts)
                     .iter()
-                    .map(Revision)
+                    .filter_map(Revision::from_js)
                     .collect()
             })
             .unwrap_or_else(Vec::new);
@@ -344,3 +378,41 @@ impl Document for SerializedDocumentData {
             .collect()
     }
 }
+
+/// A non-replicating `_local` document.
+///
+/// Local documents are never replicated and keep no revision history, so their
+/// revisions are always of the form `0-N`.
+#[derive(Debug, Clone)]
+pub struct LocalDocument<T> {
+    /// The id without the `_local/` prefix.
+    pub id: String,
+    pub rev: Revision,
+    pub data: T,
+}
+
+pub(crate) const LOCAL_PREFIX: &str = "_local/";
+
+/// A new random document id, for the backends implemented in Rust. `salt` distinguishes
+/// the ids of different databases.
+pub(crate) fn generate_id(salt: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    let mut md5 = Md5::new();
+    md5.update(salt.as_bytes());
+    md5.update(hasher.finish().to_le_bytes());
+    md5.finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Adds the `_local/` prefix to `id` if it isn't there yet.
+pub(crate) fn local_id(id: &str) -> String {
+    if id.starts_with(LOCAL_PREFIX) {
+        id.to_owned()
+    } else {
+        format!("{}{}", LOCAL_PREFIX, id)
+    }
+}
@@ -0,0 +1,952 @@
+//! A [Database] stored in a single file, for native (non-wasm) targets with the `embedded`
+//! feature.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use md5::{Digest, Md5};
+use redb::{Key, ReadableTable, Table, TableDefinition, TypeName, WriteTransaction};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{cmp::Ordering, collections::HashMap, path::Path};
+
+use crate::{
+    collate::string_collate,
+    database::{Database, JsonDocument},
+    document::{self, Revision, LOCAL_PREFIX},
+    error::Error,
+    events::{changes_event_emitter::ChangeEvent, SequenceID},
+    options::{all_docs::AllDocsOptions, changes::Changes, fetch::FetchOptions},
+    responses::{
+        AllDocsResponse, AllDocsRow, AllDocsValue, ChangeResponse, DatabaseInfo, RevsDiffEntry,
+    },
+    rev_tree::{self, DocRecord},
+};
+
+/// Document id to [DocRecord], as JSON.
+const DOCS: TableDefinition<DocId, &[u8]> = TableDefinition::new("docs");
+/// Sequence of the last change to the id of the changed document.
+const BY_SEQ: TableDefinition<u64, &str> = TableDefinition::new("by_seq");
+/// Local document id to [LocalRecord], as JSON.
+const LOCAL: TableDefinition<&str, &[u8]> = TableDefinition::new("local");
+/// Attachment digest to the attachment data.
+const ATTACHMENTS: TableDefinition<&str, &[u8]> = TableDefinition::new("attachments");
+/// Attachment digest to the number of stored revisions referencing it.
+const ATTACHMENT_REFS: TableDefinition<&str, u64> = TableDefinition::new("attachment_refs");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+const INFO: TableDefinition<&str, &str> = TableDefinition::new("info");
+
+const DOC_COUNT: &str = "doc_count";
+/// The id of the database, generated when the file is created.
+const INSTANCE_ID: &str = "instance_id";
+
+/// Document ids as table keys, sorted like PouchDB sorts them, so that ranges of
+/// [all_docs](Database::all_docs) can be read directly.
+#[derive(Debug)]
+struct DocId;
+
+impl redb::Value for DocId {
+    type SelfType<'a> = &'a str;
+    type AsBytes<'a> = &'a str;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> &'a str
+    where
+        Self: 'a,
+    {
+        <&str as redb::Value>::from_bytes(data)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a &'b str) -> &'a str
+    where
+        Self: 'b,
+    {
+        value
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new("pouchdb::DocId")
+    }
+}
+
+impl Key for DocId {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        string_collate(
+            <Self as redb::Value>::from_bytes(data1),
+            <Self as redb::Value>::from_bytes(data2),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LocalRecord {
+    rev: u64,
+    data: Value,
+}
+
+fn read_record<T>(docs: &T, id: &str) -> Result<Option<DocRecord>, Error>
+where
+    T: ReadableTable<DocId, &'static [u8]>,
+{
+    match docs.get(id)? {
+        Some(record) => Ok(Some(serde_json::from_slice(record.value())?)),
+        None => Ok(None),
+    }
+}
+
+fn update_seq<T>(by_seq: &T) -> Result<u64, Error>
+where
+    T: ReadableTable<u64, &'static str>,
+{
+    Ok(by_seq.last()?.map_or(0, |(seq, _)| seq.value()))
+}
+
+/// The digests of the attachments of every revision body of `record`, once per body.
+fn attachment_digests(record: &DocRecord) -> Vec<String> {
+    record
+        .bodies
+        .values()
+        .filter_map(|body| body.get("_attachments").and_then(Value::as_object))
+        .flat_map(|attachments| attachments.values())
+        .filter_map(|attachment| attachment["digest"].as_str())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The digest of attachment data, like PouchDB computes it.
+fn digest(data: &[u8]) -> String {
+    format!("md5-{}", BASE64_STANDARD.encode(Md5::digest(data)))
+}
+
+/// The tables of a write transaction.
+struct Writer<'txn> {
+    docs: Table<'txn, DocId, &'static [u8]>,
+    by_seq: Table<'txn, u64, &'static str>,
+    local: Table<'txn, &'static str, &'static [u8]>,
+    attachments: Table<'txn, &'static str, &'static [u8]>,
+    attachment_refs: Table<'txn, &'static str, u64>,
+    meta: Table<'txn, &'static str, u64>,
+}
+
+impl<'txn> Writer<'txn> {
+    fn open(txn: &'txn WriteTransaction) -> Result<Self, Error> {
+        Ok(Self {
+            docs: txn.open_table(DOCS)?,
+            by_seq: txn.open_table(BY_SEQ)?,
+            local: txn.open_table(LOCAL)?,
+            attachments: txn.open_table(ATTACHMENTS)?,
+            attachment_refs: txn.open_table(ATTACHMENT_REFS)?,
+            meta: txn.open_table(META)?,
+        })
+    }
+
+    fn write(&mut self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        if doc.id.starts_with(LOCAL_PREFIX) {
+            return self.write_local(doc);
+        }
+        let mut record = read_record(&self.docs, &doc.id)?.unwrap_or_default();
+        let was_live = !record.is_deleted();
+        let rev = record.update(doc)?;
+        self.store_attachments(&mut record, &rev, false)?;
+        self.save(&doc.id, record, was_live)?;
+        Ok(ChangeResponse {
+            ok: true,
+            id: doc.id.clone(),
+            rev,
+        })
+    }
+
+    /// Adds a revision made elsewhere, with its history in `_revisions`.
+    fn merge(&mut self, doc: &JsonDocument) -> Result<(), Error> {
+        let mut doc = doc.clone();
+        let ancestry = rev_tree::take_revisions(&mut doc);
+        let mut record = read_record(&self.docs, &doc.id)?.unwrap_or_default();
+        let was_live = !record.is_deleted();
+        if record.merge(&doc, &ancestry)? {
+            self.store_attachments(&mut record, &ancestry[0], true)?;
+            self.save(&doc.id, record, was_live)?;
+        }
+        Ok(())
+    }
+
+    /// Compacts `record` and stores it as the last change, deleting the attachments no
+    /// stored revision references any more.
+    ///
+    /// The last change is always in `by_seq`, so its sequence is the update sequence.
+    fn save(&mut self, id: &str, mut record: DocRecord, was_live: bool) -> Result<(), Error> {
+        record.compact();
+        let previous = read_record(&self.docs, id)?;
+        for digest in attachment_digests(&record) {
+            let count = self
+                .attachment_refs
+                .get(digest.as_str())?
+                .map_or(0, |count| count.value());
+            self.attachment_refs.insert(digest.as_str(), count + 1)?;
+        }
+        for digest in previous.iter().flat_map(attachment_digests) {
+            let count = self
+                .attachment_refs
+                .get(digest.as_str())?
+                .map_or(0, |count| count.value());
+            if count > 1 {
+                self.attachment_refs.insert(digest.as_str(), count - 1)?;
+            } else {
+                self.attachment_refs.remove(digest.as_str())?;
+                self.attachments.remove(digest.as_str())?;
+            }
+        }
+
+        let seq = update_seq(&self.by_seq)? + 1;
+        if record.seq > 0 {
+            self.by_seq.remove(record.seq)?;
+        }
+        record.seq = seq;
+        self.by_seq.insert(record.seq, id)?;
+        self.docs
+            .insert(id, serde_json::to_vec(&record)?.as_slice())?;
+
+        let is_live = !record.is_deleted();
+        if is_live != was_live {
+            let count = self.meta.get(DOC_COUNT)?.map_or(0, |count| count.value());
+            let count = if is_live { count + 1 } else { count - 1 };
+            self.meta.insert(DOC_COUNT, count)?;
+        }
+        Ok(())
+    }
+
+    /// Moves inline attachment data of revision `rev` into the attachments table, leaving
+    /// stubs with the digest, and fills in the stubs of unchanged attachments.
+    ///
+    /// Replicated revisions keep the `revpos` they were given, others get the generation of
+    /// `rev`.
+    fn store_attachments(
+        &mut self,
+        record: &mut DocRecord,
+        rev: &Revision,
+        replicated: bool,
+    ) -> Result<(), Error> {
+        let previous = record
+            .revs
+            .ancestry(rev)
+            .get(1)
+            .and_then(|parent| record.bodies.get(parent))
+            .and_then(|body| body.get("_attachments"))
+            .cloned()
+            .unwrap_or_default();
+        let attachments = match record
+            .bodies
+            .get_mut(rev)
+            .and_then(|body| body.get_mut("_attachments"))
+            .and_then(Value::as_object_mut)
+        {
+            Some(attachments) => attachments,
+            None => return Ok(()),
+        };
+
+        for (name, attachment) in attachments.iter_mut() {
+            if let Some(data) = attachment.get("data").and_then(Value::as_str) {
+                let data = BASE64_STANDARD.decode(data).map_err(|_| {
+                    Error::BadRequest(format!("Invalid data of attachment {}", name))
+                })?;
+                let digest = digest(&data);
+                self.attachments.insert(digest.as_str(), data.as_slice())?;
+                let revpos = match attachment["revpos"].as_u64() {
+                    Some(revpos) if replicated => revpos,
+                    _ => rev.generation(),
+                };
+                *attachment = json!({
+                    "content_type": attachment["content_type"].clone(),
+                    "digest": digest,
+                    "length": data.len(),
+                    "revpos": revpos,
+                    "stub": true,
+                });
+            } else if attachment["stub"] == true {
+                let known = match attachment["digest"].as_str() {
+                    Some(digest) => self.attachments.get(digest)?.is_some(),
+                    None => false,
+                };
+                match previous.get(name) {
+                    Some(previous) => *attachment = previous.clone(),
+                    None if known => {}
+                    None => {
+                        return Err(Error::BadRequest(format!(
+                            "Unknown stub attachment: {}",
+                            name
+                        )))
+                    }
+                }
+            } else {
+                return Err(Error::BadRequest(format!(
+                    "Attachment {} has neither data nor a stub",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Local documents keep no history and only need the current revision to be updated.
+    fn write_local(&mut self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let current = match self.local.get(doc.id.as_str())? {
+            Some(record) => Some(serde_json::from_slice::<LocalRecord>(record.value())?.rev),
+            None => None,
+        };
+        let rev = rev_tree::update_local(current, doc)?;
+        match rev {
+            Some(rev) => {
+                let record = LocalRecord {
+                    rev,
+                    data: doc.data.clone(),
+                };
+                self.local
+                    .insert(doc.id.as_str(), serde_json::to_vec(&record)?.as_slice())?;
+            }
+            None => {
+                self.local.remove(doc.id.as_str())?;
+            }
+        }
+        Ok(ChangeResponse {
+            ok: true,
+            id: doc.id.clone(),
+            rev: rev_tree::local_revision(rev.unwrap_or(0)),
+        })
+    }
+}
+
+/// A database stored in a file, using the embedded key-value store `redb`.
+///
+/// Like [MemoryDatabase](crate::memory::MemoryDatabase), it keeps the full revision tree of
+/// every document and picks the winning revision like PouchDB does, so it can replicate with
+/// PouchDB and CouchDB. Attachments are stored once per digest, and documents only keep
+/// stubs of them.
+///
+/// Filters, views and selectors in [Changes] aren't supported.
+pub struct EmbeddedDatabase {
+    name: String,
+    id: String,
+    db: redb::Database,
+}
+
+impl EmbeddedDatabase {
+    /// Open the database in the file at `path`, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let name = path.as_ref().to_string_lossy().into_owned();
+        let db = redb::Database::create(path.as_ref())?;
+        let txn = db.begin_write()?;
+        Writer::open(&txn)?;
+        let id = {
+            let mut info = txn.open_table(INFO)?;
+            let id = info.get(INSTANCE_ID)?.map(|id| id.value().to_owned());
+            match id {
+                Some(id) => id,
+                None => {
+                    let id = document::generate_id(&name);
+                    info.insert(INSTANCE_ID, id.as_str())?;
+                    id
+                }
+            }
+        };
+        txn.commit()?;
+        Ok(Self { name, id, db })
+    }
+
+    fn write<T, F>(&self, write: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Writer) -> Result<T, Error>,
+    {
+        let txn = self.db.begin_write()?;
+        let result = write(&mut Writer::open(&txn)?)?;
+        txn.commit()?;
+        Ok(result)
+    }
+
+    fn record(&self, id: &str) -> Result<Option<DocRecord>, Error> {
+        let txn = self.db.begin_read()?;
+        read_record(&txn.open_table(DOCS)?, id)
+    }
+
+    /// Get the data and content type of an attachment, of the winning revision unless
+    /// `rev` is given.
+    pub async fn get_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: Option<&Revision>,
+    ) -> Result<(String, Vec<u8>), Error> {
+        let txn = self.db.begin_read()?;
+        let record = read_record(&txn.open_table(DOCS)?, doc_id)?.ok_or_else(rev_tree::missing)?;
+        let rev = match rev {
+            Some(rev) => rev,
+            None if record.is_deleted() => return Err(rev_tree::missing()),
+            None => record.winner().ok_or_else(rev_tree::missing)?,
+        };
+        let attachment = record
+            .bodies
+            .get(rev)
+            .and_then(|body| body.get("_attachments"))
+            .and_then(|attachments| attachments.get(attachment_id))
+            .ok_or_else(rev_tree::missing)?;
+        let digest = attachment["digest"].as_str().unwrap_or_default();
+        let data = txn
+            .open_table(ATTACHMENTS)?
+            .get(digest)?
+            .ok_or_else(rev_tree::missing)?
+            .value()
+            .to_vec();
+        let content_type = attachment["content_type"]
+            .as_str()
+            .unwrap_or("application/octet-stream")
+            .to_owned();
+        Ok((content_type, data))
+    }
+
+    /// Add or replace an attachment
+    ///
+    /// `rev` must be the current revision of the document, or `None` to create a new
+    /// document with only this attachment.
+    pub async fn put_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: Option<&Revision>,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> Result<ChangeResponse, Error> {
+        let attachment = json!({
+            "content_type": content_type,
+            "data": BASE64_STANDARD.encode(data),
+        });
+        self.update_attachments(doc_id, rev, |attachments| {
+            attachments.insert(attachment_id.to_owned(), attachment);
+            Ok(())
+        })
+    }
+
+    /// Delete an attachment
+    pub async fn remove_attachment(
+        &self,
+        doc_id: &str,
+        attachment_id: &str,
+        rev: &Revision,
+    ) -> Result<ChangeResponse, Error> {
+        self.update_attachments(doc_id, Some(rev), |attachments| {
+            attachments
+                .remove(attachment_id)
+                .map(drop)
+                .ok_or_else(rev_tree::missing)
+        })
+    }
+
+    /// Adds a revision of a document with changed attachments.
+    fn update_attachments<F>(
+        &self,
+        doc_id: &str,
+        rev: Option<&Revision>,
+        update: F,
+    ) -> Result<ChangeResponse, Error>
+    where
+        F: FnOnce(&mut Map<String, Value>) -> Result<(), Error>,
+    {
+        self.write(|writer| {
+            let mut data = match (rev, read_record(&writer.docs, doc_id)?) {
+                (Some(rev), Some(record)) => record
+                    .bodies
+                    .get(rev)
+                    .cloned()
+                    .ok_or_else(rev_tree::conflict)?,
+                (Some(_), None) => return Err(rev_tree::missing()),
+                (None, _) => Value::Object(Map::new()),
+            };
+            let attachments = data
+                .as_object_mut()
+                .ok_or_else(|| Error::BadRequest("Document must be a JSON object".to_owned()))?
+                .entry("_attachments")
+                .or_insert_with(|| Value::Object(Map::new()));
+            update(attachments.as_object_mut().ok_or_else(|| {
+                Error::BadRequest("_attachments must be a JSON object".to_owned())
+            })?)?;
+            let doc = JsonDocument {
+                id: doc_id.to_owned(),
+                rev: rev.cloned(),
+                data,
+                ..JsonDocument::default()
+            };
+            writer.write(&doc)
+        })
+    }
+}
+
+impl std::fmt::Debug for EmbeddedDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "EmbeddedDatabase {}", self.name)
+    }
+}
+
+impl Database for EmbeddedDatabase {
+    async fn info(&self) -> Result<DatabaseInfo, Error> {
+        let txn = self.db.begin_read()?;
+        let doc_count = txn
+            .open_table(META)?
+            .get(DOC_COUNT)?
+            .map_or(0, |count| count.value());
+        Ok(DatabaseInfo {
+            db_name: self.name.clone(),
+            doc_count,
+            update_seq: update_seq(&txn.open_table(BY_SEQ)?)?.into(),
+        })
+    }
+
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        self.write(|writer| writer.write(doc))
+    }
+
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let doc = JsonDocument {
+            id: document::generate_id(&self.name),
+            ..doc.clone()
+        };
+        self.write(|writer| writer.write(&doc))
+    }
+
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        if doc_id.starts_with(LOCAL_PREFIX) {
+            let txn = self.db.begin_read()?;
+            let local = txn.open_table(LOCAL)?;
+            let record = local.get(doc_id)?.ok_or_else(rev_tree::missing)?;
+            let record: LocalRecord = serde_json::from_slice(record.value())?;
+            return Ok(JsonDocument {
+                id: doc_id.to_owned(),
+                rev: Some(rev_tree::local_revision(record.rev)),
+                data: record.data,
+                ..JsonDocument::default()
+            });
+        }
+        self.record(doc_id)?
+            .ok_or_else(rev_tree::missing)?
+            .document(doc_id, options)
+    }
+
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        let doc = JsonDocument::deleted(doc_id, rev.clone());
+        self.write(|writer| writer.write(&doc))
+    }
+
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        self.write(|writer| {
+            Ok(docs
+                .iter()
+                .map(|doc| {
+                    if doc.id.is_empty() {
+                        let doc = JsonDocument {
+                            id: document::generate_id(&self.name),
+                            ..doc.clone()
+                        };
+                        writer.write(&doc)
+                    } else {
+                        writer.write(doc)
+                    }
+                })
+                .collect())
+        })
+    }
+
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        let txn = self.db.begin_read()?;
+        let docs = txn.open_table(DOCS)?;
+        let rows = if options.keys.is_empty() {
+            // Only read the documents in the range, up to the limit.
+            let range = match (options.start(), options.descending) {
+                (Some(start), false) => docs.range(start.as_str()..)?,
+                (Some(start), true) => docs.range(..=start.as_str())?,
+                (None, _) => docs.iter()?,
+            };
+            let entries: Box<dyn Iterator<Item = _>> = if options.descending {
+                Box::new(range.rev())
+            } else {
+                Box::new(range)
+            };
+            let limit = options.limit.map_or(usize::MAX, |limit| limit as usize);
+            let mut skip = options.skip.unwrap_or(0);
+            let mut rows = Vec::new();
+            for entry in entries {
+                if rows.len() == limit {
+                    break;
+                }
+                let (id, record) = entry?;
+                let id = id.value();
+                if !options.before_end(id) {
+                    break;
+                }
+                let record: DocRecord = serde_json::from_slice(record.value())?;
+                if record.is_deleted() {
+                    continue;
+                }
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                rows.push(AllDocsRow {
+                    key: id.to_owned(),
+                    value: record.all_docs_value(id, options)?,
+                });
+            }
+            rows
+        } else {
+            options
+                .select(&[])
+                .into_iter()
+                .map(|id| {
+                    let value = match read_record(&docs, id)? {
+                        Some(record) => record.all_docs_value(id, options)?,
+                        None => AllDocsValue::NotFound,
+                    };
+                    Ok(AllDocsRow {
+                        key: id.clone(),
+                        value,
+                    })
+                })
+                .collect::<Result<_, Error>>()?
+        };
+        let total_rows = txn
+            .open_table(META)?
+            .get(DOC_COUNT)?
+            .map_or(0, |count| count.value());
+        let update_seq = update_seq(&txn.open_table(BY_SEQ)?)?;
+
+        Ok(AllDocsResponse {
+            total_rows,
+            offset: options.skip.unwrap_or(0) as u64,
+            update_seq: Some(update_seq.into()).filter(|_| options.update_seq),
+            rows,
+        })
+    }
+
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        if options.filter.is_some() || options.view.is_some() || options.selector.is_some() {
+            return Err(Error::Unsupported(
+                "Filters are not supported by EmbeddedDatabase.".to_owned(),
+            ));
+        }
+        let txn = self.db.begin_read()?;
+        let docs = txn.open_table(DOCS)?;
+        let by_seq = txn.open_table(BY_SEQ)?;
+        let since = options.since_number(update_seq(&by_seq)?)?;
+
+        let range = by_seq.range(since + 1..)?;
+        let entries: Box<dyn Iterator<Item = _>> = if options.descending {
+            Box::new(range.rev())
+        } else {
+            Box::new(range)
+        };
+        let limit = options.limit.map_or(usize::MAX, |limit| limit as usize);
+        // The last sequence scanned, so that the next request continues after the changes
+        // that were filtered out.
+        let mut last_seq = since;
+        let mut results = Vec::new();
+        for entry in entries {
+            if results.len() == limit {
+                break;
+            }
+            let (seq, id) = entry?;
+            let (seq, id) = (seq.value(), id.value());
+            if !options.descending {
+                last_seq = seq;
+            }
+            if options.doc_ids.is_empty() || options.doc_ids.iter().any(|doc_id| doc_id == id) {
+                let record = read_record(&docs, id)?.ok_or_else(rev_tree::missing)?;
+                results.push(record.change_event(id, seq, options)?);
+                last_seq = seq;
+            }
+        }
+        Ok((results, last_seq.into()))
+    }
+
+    async fn id(&self) -> Result<String, Error> {
+        Ok(self.id.clone())
+    }
+
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        let txn = self.db.begin_read()?;
+        let docs = txn.open_table(DOCS)?;
+        let mut diff = HashMap::new();
+        for (id, revs) in revs {
+            let entry = match read_record(&docs, &id)? {
+                Some(record) => record.revs_diff(&revs),
+                None => RevsDiffEntry {
+                    missing: revs,
+                    possible_ancestors: Vec::new(),
+                },
+            };
+            if !entry.missing.is_empty() {
+                diff.insert(id, entry);
+            }
+        }
+        Ok(diff)
+    }
+
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        let txn = self.db.begin_read()?;
+        let records = txn.open_table(DOCS)?;
+        let attachments = txn.open_table(ATTACHMENTS)?;
+        let mut results = Vec::new();
+        for (id, rev) in docs {
+            let record = match read_record(&records, id)? {
+                Some(record) => record,
+                None => {
+                    results.push(Err(rev_tree::missing()));
+                    continue;
+                }
+            };
+            let options = FetchOptions::default().rev(rev.as_str()).revs(true);
+            let mut doc = match record.document(id, &options) {
+                Ok(doc) => doc,
+                Err(err) => {
+                    results.push(Err(err));
+                    continue;
+                }
+            };
+            let stubs = doc
+                .data
+                .get_mut("_attachments")
+                .and_then(Value::as_object_mut);
+            for attachment in stubs.into_iter().flat_map(|stubs| stubs.values_mut()) {
+                let digest = attachment["digest"].as_str().unwrap_or_default();
+                let data = attachments.get(digest)?.ok_or_else(rev_tree::missing)?;
+                *attachment = json!({
+                    "content_type": attachment["content_type"].clone(),
+                    "digest": digest,
+                    "revpos": attachment["revpos"].clone(),
+                    "data": BASE64_STANDARD.encode(data.value()),
+                });
+            }
+            results.push(Ok(doc));
+        }
+        Ok(results)
+    }
+
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        self.write(|writer| Ok(docs.iter().map(|doc| writer.merge(doc)).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    fn open(dir: &TempDir) -> EmbeddedDatabase {
+        EmbeddedDatabase::open(dir.path().join("test.redb")).unwrap()
+    }
+
+    #[test]
+    fn documents_persist() {
+        let dir = TempDir::new().unwrap();
+        block_on(async {
+            let db = open(&dir);
+            let rev = db
+                .put(&JsonDocument::new("a", json!({"n": 1})))
+                .await
+                .unwrap()
+                .rev;
+            db.put(&JsonDocument::new("a", json!({"n": 2})).rev(rev))
+                .await
+                .unwrap();
+            db.put(&JsonDocument::new("b", json!({}))).await.unwrap();
+            db.put(&JsonDocument::new("_local/x", json!({"last": 1})))
+                .await
+                .unwrap();
+        });
+
+        let db = open(&dir);
+        block_on(async {
+            let info = db.info().await.unwrap();
+            assert_eq!(info.doc_count, 2);
+            assert_eq!(info.update_seq, SequenceID::from(3));
+            let doc = db.fetch("a", &FetchOptions::default()).await.unwrap();
+            assert_eq!(doc.data, json!({"n": 2}));
+            assert_eq!(doc.rev.unwrap().generation(), 2);
+            let local = db
+                .fetch("_local/x", &FetchOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(local.rev, Some(Revision::from("0-1")));
+
+            let (changes, _) = db.changes(&Changes::default()).await.unwrap();
+            let ids: Vec<_> = changes.iter().map(|change| change.id.as_str()).collect();
+            assert_eq!(ids, vec!["a", "b"]);
+            let all = db.all_docs(&AllDocsOptions::default()).await.unwrap();
+            assert_eq!(all.rows.len(), 2);
+        });
+    }
+
+    #[test]
+    fn ranges_are_read_in_order() {
+        let dir = TempDir::new().unwrap();
+        let db = open(&dir);
+        block_on(async {
+            // "\u{ff00}" sorts after "\u{10000}" in UTF-8, but before it in PouchDB.
+            for id in &["b", "a", "c", "d", "\u{ff00}", "\u{10000}"] {
+                db.put(&JsonDocument::new(*id, json!({}))).await.unwrap();
+            }
+            let rev = db.fetch("c", &FetchOptions::default()).await.unwrap().rev;
+            db.remove("c", &rev.unwrap()).await.unwrap();
+
+            let db = &db;
+            let ids = |options: AllDocsOptions| async move {
+                let rows = db.all_docs(&options).await.unwrap().rows;
+                rows.into_iter().map(|row| row.key).collect::<Vec<_>>()
+            };
+            let all = ids(AllDocsOptions::default()).await;
+            assert_eq!(all, vec!["a", "b", "d", "\u{10000}", "\u{ff00}"]);
+            let options = AllDocsOptions {
+                startkey: Some("b".to_owned()),
+                skip: Some(1),
+                limit: Some(1),
+                ..AllDocsOptions::default()
+            };
+            assert_eq!(ids(options).await, vec!["d"]);
+            let options = AllDocsOptions {
+                descending: true,
+                startkey: Some("c".to_owned()),
+                endkey: Some("a".to_owned()),
+                inclusive_end: false,
+                ..AllDocsOptions::default()
+            };
+            assert_eq!(ids(options).await, vec!["b"]);
+
+            let options = Changes {
+                doc_ids: vec!["a".to_owned()],
+                ..Changes::default()
+            };
+            let (changes, last_seq) = db.changes(&options).await.unwrap();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(last_seq, SequenceID::from(7));
+            let options = Changes {
+                since: Some(SequenceID::from(1)),
+                limit: Some(2),
+                ..Changes::default()
+            };
+            let (changes, last_seq) = db.changes(&options).await.unwrap();
+            let ids: Vec<_> = changes.iter().map(|change| change.id.as_str()).collect();
+            assert_eq!(ids, vec!["a", "d"]);
+            assert_eq!(last_seq, SequenceID::from(4));
+        });
+    }
+
+    #[test]
+    fn attachments_are_stored_by_digest() {
+        let dir = TempDir::new().unwrap();
+        let db = open(&dir);
+        block_on(async {
+            let rev = db
+                .put_attachment("a", "hello.txt", None, b"hello".to_vec(), "text/plain")
+                .await
+                .unwrap()
+                .rev;
+            let doc = db.fetch("a", &FetchOptions::default()).await.unwrap();
+            // base64 of the md5 of "hello", like PouchDB computes it
+            assert_eq!(
+                doc.data["_attachments"]["hello.txt"]["digest"],
+                "md5-XUFAKrxLKna5cZ2REBfFkg=="
+            );
+
+            // Updates keep attachments given as stubs.
+            let rev = db
+                .put(&JsonDocument::new("a", doc.data.clone()).rev(rev))
+                .await
+                .unwrap()
+                .rev;
+            let (content_type, data) = db.get_attachment("a", "hello.txt", None).await.unwrap();
+            assert_eq!(content_type, "text/plain");
+            assert_eq!(data, b"hello");
+
+            db.remove_attachment("a", "hello.txt", &rev).await.unwrap();
+            assert!(matches!(
+                db.get_attachment("a", "hello.txt", None).await,
+                Err(Error::NotFound(_))
+            ));
+            // No revision references the data any more.
+            let txn = db.db.begin_read().unwrap();
+            let attachments = txn.open_table(ATTACHMENTS).unwrap();
+            assert!(attachments.first().unwrap().is_none());
+            let stub = json!({"_attachments": {"other.txt": {"stub": true}}});
+            assert!(matches!(
+                db.put(&JsonDocument::new("b", stub)).await,
+                Err(Error::BadRequest(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn replicated_revisions_keep_their_history() {
+        let dir = TempDir::new().unwrap();
+        let db = open(&dir);
+        block_on(async {
+            let rev = db
+                .put(&JsonDocument::new("a", json!({})))
+                .await
+                .unwrap()
+                .rev;
+            let branch = |rev: &str, ids: Value| {
+                let revisions = json!({"start": 3, "ids": ids});
+                JsonDocument::new("a", json!({"branch": rev, "_revisions": revisions}))
+                    .rev(Revision::from(rev))
+            };
+            let remote = branch("3-ccc", json!(["ccc", "bbb", rev.hash()]));
+            let results = db
+                .bulk_docs_replicated(std::slice::from_ref(&remote))
+                .await
+                .unwrap();
+            assert!(results[0].is_ok());
+            // Storing the same revision again changes nothing.
+            db.bulk_docs_replicated(&[remote]).await.unwrap();
+            assert_eq!(db.info().await.unwrap().update_seq, SequenceID::from(2));
+
+            db.bulk_docs_replicated(&[branch("3-ddd", json!(["ddd", "eee", rev.hash()]))])
+                .await
+                .unwrap();
+            let options = FetchOptions::default()
+                .conflicts(Vec::<String>::new())
+                .revs(true);
+            let doc = db.fetch("a", &options).await.unwrap();
+            assert_eq!(doc.rev, Some(Revision::from("3-ddd")));
+            assert_eq!(doc.conflicts, vec![Revision::from("3-ccc")]);
+            assert_eq!(
+                doc.data["_revisions"],
+                json!({"start": 3, "ids": ["ddd", "eee", rev.hash()]})
+            );
+
+            let mut revs = HashMap::new();
+            revs.insert(
+                "a".to_owned(),
+                vec![Revision::from("3-ccc"), Revision::from("4-fff")],
+            );
+            revs.insert("b".to_owned(), vec![Revision::from("1-b")]);
+            let diff = db.revs_diff(revs).await.unwrap();
+            assert_eq!(diff["a"].missing, vec![Revision::from("4-fff")]);
+            assert_eq!(diff["a"].possible_ancestors.len(), 2);
+            assert_eq!(diff["b"].missing, vec![Revision::from("1-b")]);
+        });
+    }
+}
@@ -0,0 +1,643 @@
+//! End-to-end encryption of documents with AES-256-GCM.
+//!
+//! [Encryption] is a [DocumentTransform], so once it's registered with
+//! [PouchDB::add_transform](crate::PouchDB::add_transform), documents are encrypted before
+//! they reach PouchDB and decrypted when they're read. The database, and every server it
+//! replicates with, only stores the ciphertext of the fields:
+//!
+//! ```json
+//! {
+//!   "_id": "note", "_rev": "1-a", "type": "note",
+//!   "encrypted": {"key": "2024", "nonce": "...", "data": "..."}
+//! }
+//! ```
+//!
+//! Fields starting with `_` and the [plaintext fields](Encryption::plaintext_fields) are
+//! kept as they are, so selectors and views can use them. The plaintext fields and the
+//! names and types of the attachments are authenticated with the ciphertext, so they
+//! can't be changed without the key. Design documents aren't encrypted, so views keep
+//! working. The data of attachments is encrypted as well.
+//!
+//! Replication copies the encrypted documents, so only the clients need the keys.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use argon2::Argon2;
+use base64::prelude::*;
+use serde_json::{json, Map, Value};
+use std::{cell::RefCell, convert::TryFrom, rc::Rc};
+
+use crate::{
+    database::{Database, JsonDocument},
+    design::DESIGN_PREFIX,
+    error::Error,
+    options::all_docs::AllDocsOptions,
+    responses::{AllDocsValue, ChangeResponse},
+    rev_tree::js_stringify,
+    transform::{self, DocumentTransform},
+};
+
+/// The field holding the encrypted fields of a document.
+pub const ENCRYPTED_FIELD: &str = "encrypted";
+
+/// The start of encrypted attachment data, followed by the length and the id of the key,
+/// the nonce and the ciphertext.
+const ATTACHMENT_MAGIC: &[u8] = b"\0PDBENC1";
+const NONCE_LENGTH: usize = 12;
+/// The number of documents [Encryption::reencrypt] reads and writes at once.
+const REENCRYPT_BATCH_SIZE: u32 = 100;
+
+/// A 256 bit key for [Encryption].
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl EncryptionKey {
+    /// A key from 32 random bytes, e.g. from a key management service. The `id` is stored
+    /// with the encrypted documents, to find the key for decrypting them.
+    pub fn new<T: Into<String>>(id: T, key: [u8; 32]) -> Self {
+        Self {
+            id: id.into(),
+            cipher: Aes256Gcm::new(&key.into()),
+        }
+    }
+
+    /// Derive a key from a passphrase with Argon2id
+    ///
+    /// The same passphrase and salt always give the same key, so every client can derive
+    /// it. The salt must be at least 8 bytes long and should be unique, e.g. a random
+    /// value stored with the account of the user.
+    pub fn from_passphrase<T: Into<String>>(
+        id: T,
+        passphrase: &str,
+        salt: &[u8],
+    ) -> Result<Self, Error> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| Error::Encryption(err.to_string()))?;
+        Ok(Self::new(id, key))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn encrypt(&self, data: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|err| Error::Encryption(err.to_string()))?;
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+        if nonce.len() != NONCE_LENGTH {
+            return Err(Error::Encryption("Invalid nonce.".to_owned()));
+        }
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| Error::Encryption(format!("Decrypting with key {} failed.", self.id)))
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "EncryptionKey {}", self.id)
+    }
+}
+
+struct Keys {
+    current: EncryptionKey,
+    /// Older keys, which only decrypt.
+    previous: Vec<EncryptionKey>,
+}
+
+/// Encrypts documents when they're written and decrypts them when they're read.
+///
+/// Clones share their keys, so keep a clone of the registered transform to
+/// [rotate](Self::rotate) them.
+#[derive(Clone)]
+pub struct Encryption {
+    keys: Rc<RefCell<Keys>>,
+    plaintext_fields: Vec<String>,
+}
+
+impl Encryption {
+    pub fn new(key: EncryptionKey) -> Self {
+        Self {
+            keys: Rc::new(RefCell::new(Keys {
+                current: key,
+                previous: Vec::new(),
+            })),
+            plaintext_fields: Vec::new(),
+        }
+    }
+    /// Fields which aren't encrypted, e.g. for selectors and views.
+    pub fn plaintext_fields<T: Into<String>, I: IntoIterator<Item = T>>(self, fields: I) -> Self {
+        Self {
+            plaintext_fields: fields.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Encrypt with `key` from now on. The previous key is kept for decrypting the
+    /// documents encrypted with it, until they're [re-encrypted](Self::reencrypt).
+    pub fn rotate(&self, key: EncryptionKey) {
+        let mut keys = self.keys.borrow_mut();
+        keys.previous.retain(|previous| previous.id != key.id);
+        let previous = std::mem::replace(&mut keys.current, key);
+        if previous.id != keys.current.id {
+            keys.previous.push(previous);
+        }
+    }
+
+    /// Add a key for decrypting documents encrypted with it, e.g. an older key.
+    pub fn add_key(&self, key: EncryptionKey) {
+        let mut keys = self.keys.borrow_mut();
+        if keys.current.id != key.id {
+            keys.previous.retain(|previous| previous.id != key.id);
+            keys.previous.push(key);
+        }
+    }
+
+    /// Remove a previous key, returning whether it existed. The current key can't be
+    /// removed.
+    pub fn remove_key(&self, id: &str) -> bool {
+        let mut keys = self.keys.borrow_mut();
+        let count = keys.previous.len();
+        keys.previous.retain(|previous| previous.id != id);
+        keys.previous.len() != count
+    }
+
+    /// The id of the key used for encrypting.
+    pub fn current_key(&self) -> String {
+        self.keys.borrow().current.id.clone()
+    }
+
+    fn key(&self, id: &str) -> Result<EncryptionKey, Error> {
+        let keys = self.keys.borrow();
+        std::iter::once(&keys.current)
+            .chain(&keys.previous)
+            .find(|key| key.id == id)
+            .cloned()
+            .ok_or_else(|| Error::Encryption(format!("The key {} is missing.", id)))
+    }
+
+    /// Encrypt the fields and the attachment data of a document.
+    pub fn encrypt(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        let data = match doc.data {
+            Value::Object(data) if !doc.id.starts_with(DESIGN_PREFIX) => data,
+            _ => return Ok(doc),
+        };
+        let (mut plain, secret): (Map<String, Value>, Map<String, Value>) = data
+            .into_iter()
+            .partition(|(name, _)| name.starts_with('_') || self.plaintext_fields.contains(name));
+        if let Some(Value::Object(attachments)) = plain.get_mut("_attachments") {
+            for (name, attachment) in attachments {
+                self.map_attachment(&doc.id, name, attachment, true)?;
+            }
+        }
+
+        let key = self.keys.borrow().current.clone();
+        let secret = serde_json::to_vec(&secret)?;
+        let (nonce, ciphertext) = key.encrypt(&secret, &document_aad(&doc.id, &plain))?;
+        plain.insert(
+            ENCRYPTED_FIELD.to_owned(),
+            json!({
+                "key": key.id,
+                "nonce": BASE64_STANDARD.encode(nonce),
+                "data": BASE64_STANDARD.encode(ciphertext),
+            }),
+        );
+        Ok(JsonDocument {
+            data: Value::Object(plain),
+            ..doc
+        })
+    }
+
+    /// Decrypt a document encrypted by [encrypt](Self::encrypt). Design documents,
+    /// `_local` documents and deletions without fields are returned as they are, other
+    /// documents must be encrypted.
+    pub fn decrypt(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        if doc.id.starts_with(DESIGN_PREFIX) || !transform::is_transformable(&doc) {
+            return Ok(doc);
+        }
+        let mut data = match doc.data {
+            Value::Object(data) if is_envelope(data.get(ENCRYPTED_FIELD)) => data,
+            _ => {
+                return Err(Error::Encryption(format!(
+                    "The document {} isn't encrypted.",
+                    doc.id
+                )))
+            }
+        };
+        let envelope = data.remove(ENCRYPTED_FIELD).unwrap_or_default();
+        let field = |name: &str| -> Result<Vec<u8>, Error> {
+            BASE64_STANDARD
+                .decode(envelope[name].as_str().unwrap_or_default())
+                .map_err(|err| Error::Encryption(err.to_string()))
+        };
+        let key = self.key(envelope["key"].as_str().unwrap_or_default())?;
+        let aad = document_aad(&doc.id, &data);
+        let secret = key.decrypt(&field("nonce")?, &field("data")?, &aad)?;
+        let secret: Map<String, Value> = serde_json::from_slice(&secret)?;
+
+        if let Some(Value::Object(attachments)) = data.get_mut("_attachments") {
+            for (name, attachment) in attachments {
+                self.map_attachment(&doc.id, name, attachment, false)?;
+            }
+        }
+        data.extend(secret);
+        Ok(JsonDocument {
+            data: Value::Object(data),
+            ..doc
+        })
+    }
+
+    /// Encrypt or decrypt the base64 data of an attachment, if it's included.
+    fn map_attachment(
+        &self,
+        doc_id: &str,
+        name: &str,
+        attachment: &mut Value,
+        encrypt: bool,
+    ) -> Result<(), Error> {
+        let Some(data) = attachment.get("data").and_then(Value::as_str) else {
+            return Ok(());
+        };
+        let data = BASE64_STANDARD
+            .decode(data)
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+        let data = if encrypt {
+            self.encrypt_attachment(doc_id, name, &data)?
+        } else {
+            self.decrypt_attachment(doc_id, name, &data)?
+        };
+        attachment["data"] = json!(BASE64_STANDARD.encode(data));
+        Ok(())
+    }
+
+    /// Encrypt the data of the attachment `name` of the document `doc_id`. Already
+    /// encrypted data is returned as it is.
+    pub fn encrypt_attachment(
+        &self,
+        doc_id: &str,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if data.starts_with(ATTACHMENT_MAGIC) {
+            return Ok(data.to_vec());
+        }
+        let key = self.keys.borrow().current.clone();
+        let key_id = u8::try_from(key.id.len())
+            .map_err(|_| Error::Encryption("The key id is too long.".to_owned()))?;
+        let (nonce, ciphertext) = key.encrypt(data, &attachment_aad(doc_id, name))?;
+        let mut encrypted = ATTACHMENT_MAGIC.to_vec();
+        encrypted.push(key_id);
+        encrypted.extend(key.id.as_bytes());
+        encrypted.extend(nonce);
+        encrypted.extend(ciphertext);
+        Ok(encrypted)
+    }
+
+    /// Decrypt the data of the attachment `name` of the document `doc_id`, e.g. as
+    /// returned by [PouchDB::get_attachment](crate::PouchDB::get_attachment).
+    pub fn decrypt_attachment(
+        &self,
+        doc_id: &str,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let invalid = || Error::Encryption("Invalid attachment data.".to_owned());
+        let encrypted = data.strip_prefix(ATTACHMENT_MAGIC).ok_or_else(invalid)?;
+        let (&key_id_length, encrypted) = encrypted.split_first().ok_or_else(invalid)?;
+        let key_id_length = usize::from(key_id_length);
+        if encrypted.len() < key_id_length + NONCE_LENGTH {
+            return Err(invalid());
+        }
+        let (key_id, encrypted) = encrypted.split_at(key_id_length);
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let key = self.key(std::str::from_utf8(key_id).map_err(|_| invalid())?)?;
+        key.decrypt(nonce, ciphertext, &attachment_aad(doc_id, name))
+    }
+
+    /// Write all documents of `db` again, so they're encrypted with the current key
+    ///
+    /// `db` must decrypt and encrypt documents with this transform. The documents are
+    /// read and written in batches, so the database may be large. Afterwards, the
+    /// previous keys are only needed for attachments, which keep the key they were
+    /// encrypted with until their data is written again.
+    pub async fn reencrypt<D: Database>(
+        &self,
+        db: &D,
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        let mut options = AllDocsOptions {
+            include_docs: true,
+            limit: Some(REENCRYPT_BATCH_SIZE + 1),
+            ..AllDocsOptions::default()
+        };
+        let mut results = Vec::new();
+        loop {
+            let mut rows = db.all_docs(&options).await?.rows;
+            // The extra row starts the next batch.
+            let next = if rows.len() > REENCRYPT_BATCH_SIZE as usize {
+                rows.pop()
+            } else {
+                None
+            };
+            let docs: Vec<_> = rows
+                .into_iter()
+                .filter_map(|row| match row.value {
+                    AllDocsValue::Found { doc, .. } => doc,
+                    _ => None,
+                })
+                .filter(|doc| !doc.id.starts_with(DESIGN_PREFIX))
+                .collect();
+            if !docs.is_empty() {
+                results.extend(db.bulk_docs(&docs).await?);
+            }
+            match next {
+                Some(next) => options.startkey = Some(next.key),
+                None => return Ok(results),
+            }
+        }
+    }
+}
+
+impl DocumentTransform for Encryption {
+    fn incoming(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        self.encrypt(doc)
+    }
+    fn outgoing(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        self.decrypt(doc)
+    }
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Encryption with key {}", self.current_key())
+    }
+}
+
+fn is_envelope(value: Option<&Value>) -> bool {
+    value.is_some_and(|envelope| {
+        ["key", "nonce", "data"]
+            .iter()
+            .all(|field| envelope[field].is_string())
+    })
+}
+
+/// Binds the ciphertext of the fields to the document, its plaintext fields and the names
+/// and content types of its attachments. Their order doesn't matter, since it may change
+/// when they're stored.
+fn document_aad(doc_id: &str, plain: &Map<String, Value>) -> Vec<u8> {
+    let fields: Map<String, Value> = plain
+        .iter()
+        .filter(|(name, _)| !name.starts_with('_') && name.as_str() != ENCRYPTED_FIELD)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let attachments: Map<String, Value> = plain
+        .get("_attachments")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .map(|(name, attachment)| (name.clone(), attachment["content_type"].clone()))
+        .collect();
+    let bound = sorted(&json!({ "fields": fields, "attachments": attachments }));
+    [doc_id.as_bytes(), b"\0", js_stringify(&bound).as_bytes()].concat()
+}
+
+/// `value` with the keys of all objects sorted.
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by_key(|&(name, _)| name);
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(name, value)| (name.clone(), sorted(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.iter().map(sorted).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Binds the ciphertext of an attachment to the document and the name.
+fn attachment_aad(doc_id: &str, name: &str) -> Vec<u8> {
+    [doc_id.as_bytes(), b"\0", name.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        memory::MemoryDatabase,
+        options::{fetch::FetchOptions, replication::Replication},
+        replicator::Replicator,
+        transform::Transformed,
+    };
+    use futures::executor::block_on;
+
+    fn key(id: &str) -> EncryptionKey {
+        EncryptionKey::new(id, [id.len() as u8; 32])
+    }
+
+    fn encrypted(db: MemoryDatabase, encryption: &Encryption) -> Transformed<MemoryDatabase> {
+        let mut db = Transformed::new(db);
+        db.add_transform(encryption.clone());
+        db
+    }
+
+    #[test]
+    fn documents_are_encrypted() {
+        let encryption = Encryption::new(key("k1")).plaintext_fields(["type"]);
+        let doc = JsonDocument::new(
+            "note",
+            json!({
+                "type": "note",
+                "text": "secret",
+                "_attachments": {
+                    "a.txt": {"content_type": "text/plain", "data": "aGVsbG8="},
+                    "b.txt": {"content_type": "text/plain", "stub": true, "digest": "md5-x"},
+                },
+            }),
+        );
+        let stored = encryption.encrypt(doc.clone()).unwrap();
+        assert_eq!(stored.data["type"], "note");
+        assert!(stored.data.get("text").is_none());
+        assert_eq!(stored.data[ENCRYPTED_FIELD]["key"], "k1");
+        assert_ne!(stored.data["_attachments"]["a.txt"]["data"], "aGVsbG8=");
+        assert_eq!(
+            stored.data["_attachments"]["b.txt"],
+            doc.data["_attachments"]["b.txt"]
+        );
+        assert!(!stored.data.to_string().contains("secret"));
+        assert_eq!(encryption.decrypt(stored.clone()).unwrap(), doc);
+
+        // The ciphertext can't be moved to another document.
+        let moved = JsonDocument {
+            id: "other".to_owned(),
+            ..stored
+        };
+        assert!(matches!(
+            encryption.decrypt(moved),
+            Err(Error::Encryption(_))
+        ));
+
+        let ddoc = JsonDocument::new("_design/views", json!({"views": {}}));
+        assert_eq!(encryption.encrypt(ddoc.clone()).unwrap(), ddoc);
+    }
+
+    #[test]
+    fn tampered_documents_are_rejected() {
+        let encryption = Encryption::new(key("k1")).plaintext_fields(["type", "owner"]);
+        let doc = JsonDocument::new(
+            "note",
+            json!({
+                "type": "note",
+                "owner": "alice",
+                "text": "secret",
+                "_attachments": {"a.txt": {"content_type": "text/plain", "data": "aGVsbG8="}},
+            }),
+        );
+        let stored = encryption.encrypt(doc.clone()).unwrap();
+        let rejected = |change: &dyn Fn(&mut Value)| {
+            let mut tampered = stored.clone();
+            change(&mut tampered.data);
+            matches!(encryption.decrypt(tampered), Err(Error::Encryption(_)))
+        };
+        assert!(rejected(&|data| data["owner"] = json!("mallory")));
+        assert!(rejected(&|data| data["role"] = json!("admin")));
+        assert!(rejected(&|data| {
+            data["_attachments"]["a.txt"]["content_type"] = json!("text/html")
+        }));
+        // Downgrades to plaintext.
+        assert!(rejected(&|data| {
+            data.as_object_mut().unwrap().remove(ENCRYPTED_FIELD);
+            data["text"] = json!("forged");
+        }));
+        assert!(rejected(&|data| {
+            data["_attachments"]["a.txt"]["data"] = json!("Zm9yZ2Vk");
+        }));
+        assert!(matches!(
+            encryption.decrypt_attachment("note", "a.txt", b"forged"),
+            Err(Error::Encryption(_))
+        ));
+
+        // The order of the fields doesn't matter.
+        let mut reordered = stored.clone();
+        let mut fields: Vec<_> = reordered
+            .data
+            .as_object()
+            .unwrap()
+            .clone()
+            .into_iter()
+            .collect();
+        fields.reverse();
+        reordered.data = Value::Object(fields.into_iter().collect());
+        assert_eq!(encryption.decrypt(reordered).unwrap(), doc);
+        let deletion = JsonDocument::deleted("note", "2-a".into());
+        assert_eq!(encryption.decrypt(deletion.clone()).unwrap(), deletion);
+    }
+
+    #[test]
+    fn keys_can_be_rotated() {
+        let encryption = Encryption::new(key("k1"));
+        let db = encrypted(MemoryDatabase::new("db"), &encryption);
+        block_on(async {
+            db.put(&JsonDocument::new("a", json!({"n": 1})))
+                .await
+                .unwrap();
+            let old_attachment = encryption.encrypt_attachment("a", "x", b"data").unwrap();
+
+            let more: Vec<_> = (0..2 * REENCRYPT_BATCH_SIZE)
+                .map(|n| JsonDocument::new(format!("b{:03}", n), json!({ "n": n })))
+                .collect();
+            db.bulk_docs(&more).await.unwrap();
+
+            encryption.rotate(key("key2"));
+            assert_eq!(encryption.current_key(), "key2");
+            let a = db.fetch("a", &FetchOptions::default()).await.unwrap();
+            assert_eq!(a.data, json!({"n": 1}));
+
+            let results = encryption.reencrypt(&db).await.unwrap();
+            assert_eq!(results.len(), more.len() + 1);
+            assert!(results.iter().all(Result::is_ok));
+            for id in ["a", "b000", "b199"] {
+                let stored = db.inner().fetch(id, &FetchOptions::default()).await;
+                assert_eq!(stored.unwrap().data[ENCRYPTED_FIELD]["key"], "key2");
+            }
+
+            assert!(encryption.remove_key("k1"));
+            assert_eq!(
+                db.fetch("a", &FetchOptions::default()).await.unwrap().data,
+                json!({"n": 1})
+            );
+            assert!(matches!(
+                encryption.decrypt_attachment("a", "x", &old_attachment),
+                Err(Error::Encryption(_))
+            ));
+            encryption.add_key(key("k1"));
+            assert_eq!(
+                encryption
+                    .decrypt_attachment("a", "x", &old_attachment)
+                    .unwrap(),
+                b"data"
+            );
+        });
+    }
+
+    #[test]
+    fn encrypted_documents_replicate() {
+        let encryption = Encryption::new(key("k1"));
+        let source = encrypted(MemoryDatabase::new("source"), &encryption);
+        let target = encrypted(MemoryDatabase::new("target"), &encryption);
+        let options = Replication::default();
+        block_on(async {
+            source
+                .put(&JsonDocument::new("a", json!({"text": "secret"})))
+                .await
+                .unwrap();
+            Replicator::new(&source, &target, &options)
+                .run(|_| {})
+                .await
+                .unwrap();
+
+            let stored = target.inner().fetch("a", &FetchOptions::default()).await;
+            assert!(stored.unwrap().data.get("text").is_none());
+            let doc = target.fetch("a", &FetchOptions::default()).await.unwrap();
+            assert_eq!(doc.data, json!({"text": "secret"}));
+        });
+    }
+
+    #[test]
+    fn keys_are_derived_from_passphrases() {
+        let salt = b"user@example.com";
+        let first = EncryptionKey::from_passphrase("k", "correct horse", salt).unwrap();
+        let second = EncryptionKey::from_passphrase("k", "correct horse", salt).unwrap();
+        let other = EncryptionKey::from_passphrase("k", "battery staple", salt).unwrap();
+        let doc = JsonDocument::new("a", json!({"n": 1}));
+        let stored = Encryption::new(first).encrypt(doc.clone()).unwrap();
+        assert_eq!(
+            Encryption::new(second).decrypt(stored.clone()).unwrap(),
+            doc
+        );
+        assert!(Encryption::new(other).decrypt(stored).is_err());
+        assert!(EncryptionKey::from_passphrase("k", "pw", b"short").is_err());
+    }
+}
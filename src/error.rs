@@ -1,3 +1,4 @@
+use js_sys::Reflect;
 use serde_json::Error as SerdeError;
 use std::fmt::Debug;
 use wasm_bindgen::JsValue;
@@ -6,6 +7,98 @@ use wasm_bindgen::JsValue;
 pub enum Error {
     Js(JsValue),
     Serde(SerdeError),
+    /// The document doesn't exist or was deleted. Contains the reason given by PouchDB.
+    NotFound(String),
+    /// The revision doesn't match the current revision of the document. Contains the
+    /// reason given by PouchDB.
+    Conflict(String),
+    /// The operation isn't supported by the adapter of the database.
+    Unsupported(String),
+    /// The method is provided by a plugin that isn't registered with
+    /// [PouchDB::plugin](crate::PouchDB::plugin). Contains the name of the plugin.
+    PluginMissing(String),
+    /// The request was rejected as invalid, e.g. because of a malformed document. Contains
+    /// the reason given by the database.
+    BadRequest(String),
+    /// The credentials are invalid or lack the permission for the request. Contains the
+    /// reason given by the database.
+    Unauthorized(String),
+    /// Any other error status returned by the server, with the reason given by it.
+    Status(u16, String),
+    /// Encrypting or decrypting a document failed, e.g. because its key is missing.
+    Encryption(String),
+    /// Decompressing a document or an attachment failed, because the data is corrupt.
+    Compression(String),
+    /// The HTTP request failed.
+    #[cfg(all(feature = "couchdb", not(target_arch = "wasm32")))]
+    Http(reqwest::Error),
+    /// Reading or writing the file of an [EmbeddedDatabase](crate::embedded::EmbeddedDatabase)
+    /// failed.
+    #[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+    Storage(Box<redb::Error>),
+}
+
+impl Error {
+    /// Turns PouchDB errors with a known status into the matching variant.
+    pub(crate) fn from_status(err: JsValue) -> Error {
+        let status = Reflect::get(&err, &JsValue::from_str("status"))
+            .ok()
+            .and_then(|status| status.as_f64())
+            .map(|status| status as u16);
+        let reason = || {
+            Reflect::get(&err, &JsValue::from_str("message"))
+                .ok()
+                .and_then(|message| message.as_string())
+                .unwrap_or_default()
+        };
+        match status {
+            Some(status @ (400 | 401 | 403 | 404 | 409)) => Error::from_code(status, reason()),
+            _ => Error::Js(err),
+        }
+    }
+
+    /// Turns an error reported as JSON for a single document (e.g. by `_bulk_docs`) into
+    /// the matching variant. Understands both the CouchDB and the PouchDB format.
+    pub(crate) fn from_json(err: &serde_json::Value) -> Error {
+        let name = err["error"]
+            .as_str()
+            .or_else(|| err["name"].as_str())
+            .unwrap_or_default();
+        let reason = err["reason"]
+            .as_str()
+            .or_else(|| err["message"].as_str())
+            .unwrap_or_default()
+            .to_owned();
+        let status = match name {
+            "bad_request" => 400,
+            "unauthorized" => 401,
+            "forbidden" => 403,
+            "not_found" => 404,
+            "conflict" => 409,
+            _ => err["status"].as_u64().map_or(500, |status| status as u16),
+        };
+        Error::from_code(status, reason)
+    }
+
+    /// Turns an HTTP error response of CouchDB into the matching variant.
+    pub(crate) fn from_response(status: u16, body: &serde_json::Value) -> Error {
+        let reason = body["reason"]
+            .as_str()
+            .or_else(|| body["error"].as_str())
+            .unwrap_or_default();
+        Error::from_code(status, reason.to_owned())
+    }
+
+    /// Turns an HTTP error status into the matching variant.
+    pub(crate) fn from_code(status: u16, reason: String) -> Error {
+        match status {
+            400 => Error::BadRequest(reason),
+            401 | 403 => Error::Unauthorized(reason),
+            404 => Error::NotFound(reason),
+            409 => Error::Conflict(reason),
+            _ => Error::Status(status, reason),
+        }
+    }
 }
 
 impl From<JsValue> for Error {
@@ -20,11 +113,65 @@ impl From<SerdeError> for Error {
     }
 }
 
+#[cfg(all(feature = "couchdb", not(target_arch = "wasm32")))]
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        Error::Http(err)
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::Error> for Error {
+    fn from(err: redb::Error) -> Error {
+        Error::Storage(Box::new(err))
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::DatabaseError> for Error {
+    fn from(err: redb::DatabaseError) -> Error {
+        Error::Storage(Box::new(err.into()))
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::TransactionError> for Error {
+    fn from(err: redb::TransactionError) -> Error {
+        Error::Storage(Box::new(err.into()))
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::TableError> for Error {
+    fn from(err: redb::TableError) -> Error {
+        Error::Storage(Box::new(err.into()))
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::StorageError> for Error {
+    fn from(err: redb::StorageError) -> Error {
+        Error::Storage(Box::new(err.into()))
+    }
+}
+
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+impl From<redb::CommitError> for Error {
+    fn from(err: redb::CommitError) -> Error {
+        Error::Storage(Box::new(err.into()))
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::Js(_) => None,
             Self::Serde(err) => err.source(),
+            #[cfg(all(feature = "couchdb", not(target_arch = "wasm32")))]
+            Self::Http(err) => Some(err),
+            #[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+            Self::Storage(err) => Some(err.as_ref()),
+            _ => None,
         }
     }
 }
@@ -34,6 +181,19 @@ impl std::fmt::Display for Error {
         match self {
             Self::Js(err) => err.fmt(f),
             Self::Serde(err) => <SerdeError as std::fmt::Display>::fmt(err, f),
+            Self::NotFound(reason) => write!(f, "not found: {}", reason),
+            Self::Conflict(reason) => write!(f, "conflict: {}", reason),
+            Self::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+            Self::PluginMissing(plugin) => write!(f, "plugin missing: {}", plugin),
+            Self::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            Self::Unauthorized(reason) => write!(f, "unauthorized: {}", reason),
+            Self::Status(status, reason) => write!(f, "status {}: {}", status, reason),
+            Self::Encryption(reason) => write!(f, "encryption: {}", reason),
+            Self::Compression(reason) => write!(f, "compression: {}", reason),
+            #[cfg(all(feature = "couchdb", not(target_arch = "wasm32")))]
+            Self::Http(err) => <reqwest::Error as std::fmt::Display>::fmt(err, f),
+            #[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+            Self::Storage(err) => write!(f, "storage: {}", err),
         }
     }
 }
@@ -3,15 +3,20 @@ use std::convert::TryFrom;
 use wasm_bindgen::JsValue;
 
 use super::{EventEmitter, EventListener, EventName, SequenceID};
-use crate::document::{Revision, SerializedDocument};
+use crate::{
+    document::{Revision, SerializedDocument},
+    error::Error,
+    transform::Transforms,
+};
 
+/// A change of a document. `D` is the type of the included document.
 #[derive(Debug)]
-pub struct ChangeEvent {
+pub struct ChangeEvent<D = SerializedDocument> {
     pub id: String,
     pub changes: Vec<Revision>,
     pub seq: SequenceID,
     pub deleted: bool,
-    pub doc: Option<SerializedDocument>,
+    pub doc: Option<D>,
 }
 
 impl ChangeEvent {
@@ -28,11 +33,13 @@ impl ChangeEvent {
                 let rev = JsValue::from_str("rev");
                 let changes: Vec<Revision> = Array::from(&changes)
                     .iter()
-                    .filter_map(|change| Reflect::get(&change, &rev).ok().map(Revision))
+                    .filter_map(|change| {
+                        Reflect::get(&change, &rev).ok().and_then(Revision::from_js)
+                    })
                     .collect();
                 if let Some(seq) = Reflect::get(&info, &JsValue::from_str("seq"))
                     .ok()
-                    .map(SequenceID)
+                    .and_then(|seq| SequenceID::from_js(seq).ok())
                 {
                     if Some(true)
                         == Reflect::get(&info, &JsValue::from_str("deleted"))
@@ -71,11 +78,11 @@ impl ChangeEvent {
 }
 
 /// Returned by [PouchDB::changes]
-pub struct ChangesEventEmitter(EventEmitter);
+pub struct ChangesEventEmitter(EventEmitter, Transforms);
 
 impl ChangesEventEmitter {
-    pub(crate) fn new(value: JsValue) -> Self {
-        Self(EventEmitter::new(value))
+    pub(crate) fn new(value: JsValue, transforms: Transforms) -> Self {
+        Self(EventEmitter::new(value), transforms)
     }
 
     fn as_js(&self) -> &JsValue {
@@ -84,6 +91,10 @@ impl ChangesEventEmitter {
     /// Call this function if you don’t want to listen to new changes anymore.
     /// It will unsubscribe all event listeners automatically.
     pub fn cancel(self) {
+        self.stop();
+    }
+    /// Cancel the changes without consuming the emitter, e.g. when its owner is dropped.
+    pub(crate) fn stop(&self) {
         if let Ok(cancel) = Reflect::get(self.as_js(), &JsValue::from_str("cancel")) {
             if cancel.is_function() {
                 Function::from(cancel).call0(self.as_js()).ok();
@@ -94,15 +105,23 @@ impl ChangesEventEmitter {
     /// This event fires when a change has been found. The parameter will contain
     /// details about the change, such as whether it was deleted and what the new `rev` is.
     /// Will contain the doc if you set `include_docs` to true.
+    ///
+    /// If the change can't be parsed or the transforms fail on its doc, the listener gets
+    /// the error instead, and the changes feed goes on.
     pub fn add_change_listener(
         &self,
-        listener: impl Fn(ChangeEvent) + 'static,
+        listener: impl Fn(Result<ChangeEvent, Error>) + 'static,
     ) -> Result<EventListener, JsValue> {
+        let transforms = self.1.clone();
         self.0
             .add_listener(&EventName::string("change"), move |info| {
-                if let Ok(event) = ChangeEvent::new(&info) {
-                    listener(event);
-                }
+                let doc = JsValue::from_str("doc");
+                let event = Reflect::get(&info, &doc)
+                    .map_err(Error::from)
+                    .and_then(|value| transforms.outgoing_js_sync(value))
+                    .and_then(|value| Ok(Reflect::set(&info, &doc, &value)?))
+                    .and_then(|_| Ok(ChangeEvent::new(&info)?));
+                listener(event);
             })
     }
 
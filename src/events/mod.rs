@@ -1,12 +1,59 @@
 use js_sys::{Array, Function, JsString, Reflect, Symbol};
+use serde::{Deserialize, Serialize};
 use std::convert::AsRef;
 use wasm_bindgen::{closure::Closure, JsValue};
 
+use crate::error::Error;
+
 pub mod changes_event_emitter;
 pub mod replication_event_emitter;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct SequenceID(pub(crate) JsValue);
+/// A position in the changes feed of a database.
+///
+/// Local databases use numbers, CouchDB uses opaque strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SequenceID(pub(crate) serde_json::Value);
+
+impl SequenceID {
+    /// Only changes made after the request, for [Changes::since](crate::options::changes::Changes::since).
+    pub fn now() -> Self {
+        Self("now".into())
+    }
+    pub(crate) fn from_js(value: JsValue) -> Result<Self, Error> {
+        Ok(Self(value.into_serde()?))
+    }
+    pub(crate) fn to_js(&self) -> Result<JsValue, Error> {
+        Ok(JsValue::from_serde(&self.0)?)
+    }
+    /// The numeric part of the sequence, i.e. the sequence itself for local databases and
+    /// the part before the `-` for CouchDB.
+    pub fn number(&self) -> Option<u64> {
+        match &self.0 {
+            serde_json::Value::Number(number) => number.as_u64(),
+            serde_json::Value::String(seq) => seq.split('-').next()?.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl From<u64> for SequenceID {
+    fn from(seq: u64) -> Self {
+        Self(seq.into())
+    }
+}
+
+impl From<&str> for SequenceID {
+    fn from(seq: &str) -> Self {
+        Self(seq.into())
+    }
+}
+
+impl From<String> for SequenceID {
+    fn from(seq: String) -> Self {
+        Self(seq.into())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum EventName {
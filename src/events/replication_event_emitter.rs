@@ -3,16 +3,18 @@ use crate::document::SerializedDocument;
 use js_sys::{Function, Reflect};
 use wasm_bindgen::JsValue;
 
+/// The progress of a replication. `D` is the type of the replicated documents.
 #[derive(Debug)]
-pub struct ChangeEvent {
+pub struct ChangeEvent<D = SerializedDocument> {
     pub doc_write_failures: u32,
     pub docs_read: u32,
     pub docs_written: u32,
     pub errors: Vec<String>,
     pub last_seq: SequenceID,
     pub ok: bool,
-    pub start_time: std::time::Instant,
-    pub docs: Vec<SerializedDocument>,
+    /// `None` on targets without a clock, i.e. wasm.
+    pub start_time: Option<std::time::Instant>,
+    pub docs: Vec<D>,
 }
 
 impl ChangeEvent {
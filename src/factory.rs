@@ -0,0 +1,75 @@
+//! Creating databases with shared default options.
+
+use js_sys::{Array, Function, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::{
+    error::Error, fetch_hook::SharedAuth, options::create::CreateOptions, pouchdb_sys,
+    transform::Transforms, PouchDB,
+};
+
+/// Opens databases with preset options, using `PouchDB.defaults`.
+///
+/// The options given to [new](Self::new) apply to every database opened by the factory,
+/// e.g. a common adapter or the credentials of a server, so they only need to be
+/// configured once:
+///
+/// ```no_run
+/// # use pouchdb::{factory::PouchDBFactory, options::create::{Adapter, CreateOptions}};
+/// let factory =
+///     PouchDBFactory::new(CreateOptions::default().adapter(Adapter::IDB)).unwrap();
+/// let users = factory.open("users").unwrap();
+/// let orders = factory.open("orders").unwrap();
+/// ```
+pub struct PouchDBFactory {
+    /// The constructor returned by `PouchDB.defaults`.
+    constructor: Function,
+    options: CreateOptions,
+    /// The credentials of the default options, shared by the databases.
+    auth: Option<SharedAuth>,
+}
+
+impl PouchDBFactory {
+    pub fn new(options: CreateOptions) -> Result<Self, Error> {
+        let (defaults, auth) = options.to_js()?;
+        let constructor = pouchdb_sys::PouchDB::defaults(defaults)?;
+        Ok(Self {
+            constructor,
+            options,
+            auth,
+        })
+    }
+
+    /// The default options of the databases.
+    pub fn options(&self) -> &CreateOptions {
+        &self.options
+    }
+
+    /// Create or open the database `name` with the default options
+    pub fn open(&self, name: &str) -> Result<PouchDB, Error> {
+        self.construct(&JsValue::from_str(name), self.auth.clone())
+    }
+
+    /// Create or open a database with options, which override the defaults
+    pub fn open_with_options(&self, options: &CreateOptions) -> Result<PouchDB, Error> {
+        let (js_options, auth) = options.to_js()?;
+        // The `fetch` adding the default credentials is replaced by a custom one.
+        let auth = auth.or_else(|| self.auth.clone().filter(|_| options.fetch.is_none()));
+        self.construct(&js_options, auth)
+    }
+
+    fn construct(
+        &self,
+        name_or_options: &JsValue,
+        auth: Option<SharedAuth>,
+    ) -> Result<PouchDB, Error> {
+        let db = Reflect::construct(&self.constructor, &Array::of1(name_or_options))?;
+        Ok(PouchDB(db.unchecked_into(), auth, Transforms::default()))
+    }
+}
+
+impl std::fmt::Debug for PouchDBFactory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "PouchDBFactory")
+    }
+}
@@ -0,0 +1,148 @@
+//! Intercepting the HTTP requests of remote databases.
+
+use futures::Future;
+use js_sys::{Function, Object, Promise, Reflect};
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{Headers, Response};
+
+use crate::{error::Error, options::create::Auth, pouchdb_sys::PouchDB as JsPouchDB};
+
+/// A request of a remote database, passed to the hook given to
+/// [CreateOptions::fetch_hook](crate::options::create::CreateOptions::fetch_hook).
+///
+/// Change the fields as needed, then [send](Self::send) it.
+pub struct FetchRequest {
+    pub url: String,
+    pub method: String,
+    pub headers: Headers,
+    /// The body, usually a string of JSON, or a `Blob` for attachments. `undefined` for
+    /// requests without a body.
+    pub body: JsValue,
+    /// The other options of the request, e.g. `credentials`.
+    init: Object,
+}
+
+impl FetchRequest {
+    fn from_js(url: JsValue, init: JsValue) -> Result<Self, Error> {
+        let init: Object = if init.is_object() {
+            init.unchecked_into()
+        } else {
+            Object::new()
+        };
+        let get = |key: &str| Reflect::get(&init, &JsValue::from_str(key));
+        let headers = get("headers")?;
+        let headers = if headers.is_instance_of::<Headers>() {
+            headers.unchecked_into()
+        } else if headers.is_object() {
+            Headers::new_with_str_sequence_sequence(&headers)?
+        } else {
+            Headers::new()?
+        };
+        Ok(Self {
+            url: url.as_string().unwrap_or_default(),
+            method: get("method")?
+                .as_string()
+                .unwrap_or_else(|| "GET".to_owned()),
+            headers,
+            body: get("body")?,
+            init,
+        })
+    }
+
+    /// The body if it's a string.
+    pub fn body_text(&self) -> Option<String> {
+        self.body.as_string()
+    }
+
+    /// Send the request with the `fetch` PouchDB uses by default.
+    pub async fn send(self) -> Result<Response, Error> {
+        let init = self.to_init()?;
+        let response = JsFuture::from(JsPouchDB::fetch(&self.url, &init)).await?;
+        Ok(response.dyn_into()?)
+    }
+
+    /// Send the request with `fetch`, a function like the `fetch` of browsers.
+    async fn send_with(self, fetch: &Function) -> Result<Response, Error> {
+        let init = self.to_init()?;
+        let response = fetch.call2(&JsValue::NULL, &self.url.into(), &init)?;
+        let response = JsFuture::from(Promise::resolve(&response)).await?;
+        Ok(response.dyn_into()?)
+    }
+
+    fn to_init(&self) -> Result<Object, Error> {
+        let init = Object::assign(&Object::new(), &self.init);
+        Reflect::set(
+            &init,
+            &JsValue::from_str("method"),
+            &self.method.as_str().into(),
+        )?;
+        Reflect::set(&init, &JsValue::from_str("headers"), &self.headers)?;
+        Reflect::set(&init, &JsValue::from_str("body"), &self.body)?;
+        Ok(init)
+    }
+}
+
+impl std::fmt::Debug for FetchRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "FetchRequest {} {}", self.method, self.url)
+    }
+}
+
+/// Wraps a Rust hook into a function PouchDB calls as `fetch(url, options)`.
+///
+/// The function is owned by JavaScript, so the hook lives as long as the databases
+/// using it.
+pub(crate) fn fetch_function<F, Fut>(hook: F) -> Function
+where
+    F: Fn(FetchRequest) -> Fut + 'static,
+    Fut: Future<Output = Result<Response, Error>> + 'static,
+{
+    let closure = Closure::wrap(Box::new(move |url: JsValue, init: JsValue| {
+        let request = FetchRequest::from_js(url, init);
+        let response = request.map(&hook);
+        future_to_promise(async move {
+            match response {
+                Ok(response) => response.await.map(JsValue::from).map_err(to_js_error),
+                Err(err) => Err(to_js_error(err)),
+            }
+        })
+    }) as Box<dyn Fn(JsValue, JsValue) -> Promise>);
+    closure.into_js_value().unchecked_into()
+}
+
+/// The credentials of a remote database, shared with the `fetch` of the database so they
+/// can be replaced while it's in use.
+pub(crate) type SharedAuth = Rc<RefCell<Auth>>;
+
+/// A `fetch` adding the current credentials to each request, then sending it with `fetch`
+/// if given, or the default one.
+pub(crate) fn auth_function(auth: SharedAuth, fetch: Option<Function>) -> Function {
+    fetch_function(move |request: FetchRequest| {
+        let headers = auth.borrow().headers();
+        let fetch = fetch.clone();
+        async move {
+            for (name, value) in headers {
+                request.headers.set(name, &value)?;
+            }
+            Reflect::set(
+                &request.init,
+                &JsValue::from_str("credentials"),
+                &"include".into(),
+            )?;
+            match fetch {
+                Some(fetch) => request.send_with(&fetch).await,
+                None => request.send().await,
+            }
+        }
+    })
+}
+
+/// PouchDB expects the promise of `fetch` to be rejected with an `Error`.
+fn to_js_error(err: Error) -> JsValue {
+    match err {
+        Error::Js(err) => err,
+        err => js_sys::Error::new(&err.to_string()).into(),
+    }
+}
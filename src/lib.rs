@@ -1,6 +1,12 @@
-use std::convert::{AsRef, TryFrom, TryInto};
+use std::{
+    collections::HashMap,
+    convert::{AsRef, TryInto},
+    num::NonZeroU32,
+};
 
+use futures::Stream;
 use js_sys::{Array, Object, Reflect};
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::Blob;
@@ -10,21 +16,50 @@ use pouchdb_sys::PouchDB as JsPouchDB;
 
 pub mod options;
 use options::{
-    all_docs::AllDocsOptions, changes::Changes, create::CreateOptions, fetch::FetchOptions,
-    query::QueryOptions, replication::Replication,
+    all_docs::AllDocsOptions,
+    changes::Changes,
+    create::{Auth, CreateOptions},
+    fetch::FetchOptions,
+    find::{FindOptions, IndexOptions},
+    query::QueryOptions,
+    replication::Replication,
 };
 pub mod responses;
 use responses::*;
 pub mod error;
 use error::Error;
 pub mod document;
-use document::{Document, Revision, SerializedDocument};
+use document::{Document, LocalDocument, Revision, SerializedDocument};
+pub mod collate;
+pub mod compression;
+pub mod conflicts;
+#[cfg(all(feature = "couchdb", not(target_arch = "wasm32")))]
+pub mod couchdb;
+pub mod database;
+use conflicts::ConflictResolver;
+pub mod design;
+#[cfg(all(feature = "embedded", not(target_arch = "wasm32")))]
+pub mod embedded;
+use design::DesignDocument;
+pub mod encryption;
 pub mod events;
+pub mod factory;
+pub mod fetch_hook;
+use fetch_hook::SharedAuth;
+pub mod memory;
+mod pagination;
+pub mod replicator;
+mod rev_tree;
+pub mod session;
+pub mod transform;
+use transform::Transforms;
+pub mod view;
 use events::{
     changes_event_emitter::{ChangeEvent, ChangesEventEmitter},
     replication_event_emitter::ReplicationEventEmitter,
     SequenceID,
 };
+use view::Emitter;
 
 pub enum PouchDBOrStringRef<'a> {
     PouchDB(&'a PouchDB),
@@ -43,7 +78,8 @@ impl<'a> From<&'a str> for PouchDBOrStringRef<'a> {
     }
 }
 
-pub struct PouchDB(JsPouchDB);
+/// A database, the credentials of its requests if it's remote, and its transforms.
+pub struct PouchDB(JsPouchDB, Option<SharedAuth>, Transforms);
 
 impl PouchDB {
     /// Create a database
@@ -53,12 +89,68 @@ impl PouchDB {
     /// instance. Otherwise it will create a local database using whatever backend is present.
     pub fn new<T: Into<String>>(name: T) -> Self {
         let name: String = name.into();
-        Self(JsPouchDB::new(name.into()))
+        Self(JsPouchDB::new(name.into()), None, Transforms::default())
     }
     /// Create a database with options
-    pub fn new_with_options(options: CreateOptions) -> Self {
-        let opts = JsValue::from_serde(&options).unwrap();
-        Self(JsPouchDB::new(opts))
+    ///
+    /// Returns [Error::Unsupported] if the options don't match the adapter, see
+    /// [CreateOptions::validate].
+    pub fn new_with_options(options: CreateOptions) -> Result<Self, Error> {
+        let (options, auth) = options.to_js()?;
+        Ok(Self(JsPouchDB::new(options), auth, Transforms::default()))
+    }
+
+    /// Replace the credentials of a remote database, e.g. with a refreshed token
+    ///
+    /// They're used from the next request on, including the requests of running
+    /// replications and changes feeds. Returns [Error::Unsupported] if the database wasn't
+    /// created with [CreateOptions::auth].
+    pub fn set_auth(&self, auth: Auth) -> Result<(), Error> {
+        match &self.1 {
+            Some(current) => {
+                current.replace(auth);
+                Ok(())
+            }
+            None => Err(Error::Unsupported(
+                "The database was created without credentials.".to_owned(),
+            )),
+        }
+    }
+
+    /// Register a plugin
+    ///
+    /// Plugins like adapters or pouchdb-find add features to all databases, including
+    /// the ones created before. `plugin` is the default export of the plugin's module.
+    pub fn plugin(plugin: &JsValue) -> Result<(), Error> {
+        JsPouchDB::plugin(plugin)?;
+        Ok(())
+    }
+
+    /// Register pouchdb-find, which provides [find] and [create_index]
+    #[cfg(feature = "pouchdb-find")]
+    pub fn plugin_find() -> Result<(), Error> {
+        pouchdb_sys::FIND_PLUGIN.with(Self::plugin)
+    }
+
+    /// Register pouchdb-adapter-memory, which provides the `memory` adapter
+    #[cfg(feature = "pouchdb-adapter-memory")]
+    pub fn plugin_memory_adapter() -> Result<(), Error> {
+        pouchdb_sys::MEMORY_ADAPTER_PLUGIN.with(Self::plugin)
+    }
+
+    /// Whether the database has the method `name` (its JavaScript name), e.g. to check if
+    /// the plugin providing it is registered.
+    pub fn has_method(&self, name: &str) -> bool {
+        Reflect::get(&self.0, &JsValue::from_str(name)).is_ok_and(|method| method.is_function())
+    }
+
+    /// Returns [Error::PluginMissing] if `method` isn't available.
+    fn require_plugin(&self, method: &str, plugin: &str) -> Result<(), Error> {
+        if self.has_method(method) {
+            Ok(())
+        } else {
+            Err(Error::PluginMissing(plugin.to_owned()))
+        }
     }
 
     /// Delete a database
@@ -71,6 +163,14 @@ impl PouchDB {
             .map_err(Error::from)
     }
 
+    /// Get information about the database
+    pub async fn info(&self) -> Result<DatabaseInfo, Error> {
+        JsFuture::from(self.0.info())
+            .await?
+            .into_serde()
+            .map_err(Error::from)
+    }
+
     /// Create/update a document
     ///
     /// Create a new document or update an existing document. If the document already
@@ -87,7 +187,7 @@ impl PouchDB {
     where
         D: Document + ?Sized,
     {
-        let js_doc = document::serialize(doc)?;
+        let js_doc = self.2.incoming_js(document::serialize(doc)?).await?;
 
         JsFuture::from(if force {
             let options = js_sys::Object::new();
@@ -96,7 +196,8 @@ impl PouchDB {
         } else {
             self.0.put(js_doc)
         })
-        .await?
+        .await
+        .map_err(Error::from_status)?
         .try_into()
     }
 
@@ -112,8 +213,10 @@ impl PouchDB {
     where
         D: Document + ?Sized,
     {
-        JsFuture::from(self.0.post(document::serialize(doc)?))
-            .await?
+        let js_doc = self.2.incoming_js(document::serialize(doc)?).await?;
+        JsFuture::from(self.0.post(js_doc))
+            .await
+            .map_err(Error::from_status)?
             .try_into()
     }
 
@@ -132,8 +235,9 @@ impl PouchDB {
             Reflect::set(&options, &JsValue::from_str("binary"), &JsValue::TRUE)?;
         }
 
-        JsFuture::from(self.0.get_with_options(JsValue::from_str(doc_id), options))
+        let data = JsFuture::from(self.0.get_with_options(JsValue::from_str(doc_id), options))
             .await
+            .map_err(Error::from_status)
             .and_then(|data| {
                 if has_revs {
                     let array: Array = data.dyn_into()?;
@@ -143,7 +247,10 @@ impl PouchDB {
                 } else {
                     Ok(data)
                 }
-            })?
+            })?;
+        self.2
+            .outgoing_js(data)
+            .await?
             .try_into()
             .map_err(Error::from)
     }
@@ -165,11 +272,186 @@ impl PouchDB {
         Reflect::set(
             &value,
             &JsValue::from_str("_rev"),
-            &doc.rev().expect("Document does not have a revision").0,
+            &doc.rev()
+                .expect("Document does not have a revision")
+                .to_js(),
         )?;
         Reflect::set(&value, &JsValue::from_str("_deleted"), &JsValue::TRUE)?;
 
-        JsFuture::from(self.0.put(value.into())).await?.try_into()
+        JsFuture::from(self.0.put(value.into()))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Fetch a local document
+    ///
+    /// Local documents (with ids starting with `_local/`) are never replicated and keep
+    /// no revision history. `id` may be given with or without the `_local/` prefix.
+    ///
+    /// Returns [Error::NotFound] if the document doesn't exist.
+    pub async fn get_local<T>(&self, id: &str) -> Result<LocalDocument<T>, Error>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let id = document::local_id(id);
+        let doc = JsFuture::from(self.0.get(JsValue::from_str(&id)))
+            .await
+            .map_err(Error::from_status)?;
+        let rev = Reflect::get(&doc, &JsValue::from_str("_rev"))?;
+        let mut data: serde_json::Value = doc.into_serde()?;
+        if let Some(data) = data.as_object_mut() {
+            data.remove("_id");
+            data.remove("_rev");
+        }
+
+        Ok(LocalDocument {
+            id: id[document::LOCAL_PREFIX.len()..].to_owned(),
+            rev: Revision::from_js(rev)
+                .ok_or_else(|| JsValue::from_str("Local document does not have a revision."))?,
+            data: serde_json::from_value(data)?,
+        })
+    }
+
+    /// Create/update a local document
+    ///
+    /// To update an existing local document, `rev` must be its current revision
+    /// (as returned by [get_local] or a previous [put_local]), otherwise
+    /// [Error::Conflict] is returned. New documents get the revision `0-1`.
+    pub async fn put_local<T>(
+        &self,
+        id: &str,
+        rev: Option<&Revision>,
+        data: &T,
+    ) -> Result<ChangeResponse, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut doc = match serde_json::to_value(data)? {
+            serde_json::Value::Object(doc) => doc,
+            _ => {
+                return Err(Error::BadRequest(
+                    "Local documents must be JSON objects.".to_owned(),
+                ))
+            }
+        };
+        doc.insert("_id".to_owned(), document::local_id(id).into());
+        match rev {
+            Some(rev) => doc.insert("_rev".to_owned(), rev.as_str().into()),
+            None => doc.remove("_rev"),
+        };
+        let doc = JsValue::from_serde(&doc)?;
+
+        JsFuture::from(self.0.put(doc))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Delete a local document
+    ///
+    /// `rev` must be the current revision of the document.
+    pub async fn remove_local(&self, id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        let value = Object::new();
+        Reflect::set(
+            &value,
+            &JsValue::from_str("_id"),
+            &JsValue::from_str(&document::local_id(id)),
+        )?;
+        Reflect::set(&value, &JsValue::from_str("_rev"), &rev.to_js())?;
+
+        JsFuture::from(self.0.remove_doc(value.into()))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Compare revisions with the database
+    ///
+    /// Given a set of document ids and revisions, returns the subset of revisions the
+    /// database doesn't have, e.g. to find out what a replica lacks before pushing to it.
+    /// Documents for which all revisions are known aren't included in the result.
+    pub async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        let diff = Object::new();
+        for (id, revs) in revs {
+            let array = Array::new();
+            for rev in revs {
+                array.push(&rev.to_js());
+            }
+            Reflect::set(&diff, &JsValue::from_str(&id), &array)?;
+        }
+
+        let response = JsFuture::from(self.0.revs_diff(diff.into())).await?;
+        Reflect::own_keys(&response)?
+            .iter()
+            .filter_map(|id| id.as_string())
+            .map(|id| {
+                let entry = Reflect::get(&response, &JsValue::from_str(&id))?;
+                Ok((id, entry.try_into()?))
+            })
+            .collect()
+    }
+
+    /// Permanently remove a revision
+    ///
+    /// Unlike deleting a document, which creates a tombstone revision that's replicated,
+    /// purging removes the leaf revision `rev` and its ancestors from the local database
+    /// as if they never existed. Purges are not replicated.
+    ///
+    /// Only supported by the `indexeddb` adapter; other adapters return [Error::Unsupported].
+    pub async fn purge(&self, doc_id: &str, rev: &Revision) -> Result<PurgeResponse, Error> {
+        let purge = Reflect::get(&self.0, &JsValue::from_str("purge"))?;
+        let adapter_purge = Reflect::get(&self.0, &JsValue::from_str("_purge"))?;
+        if !purge.is_function() || !adapter_purge.is_function() {
+            let adapter = Reflect::get(&self.0, &JsValue::from_str("adapter"))?
+                .as_string()
+                .unwrap_or_default();
+            return Err(Error::Unsupported(format!(
+                "Purge is not implemented in the {} adapter.",
+                adapter
+            )));
+        }
+
+        JsFuture::from(self.0.purge(JsValue::from_str(doc_id), rev.to_js()))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Permanently remove a document
+    ///
+    /// Purges every leaf revision of the document (including deleted and conflicting
+    /// ones), see [purge].
+    pub async fn purge_document(&self, doc_id: &str) -> Result<Vec<PurgeResponse>, Error> {
+        let options = Object::new();
+        Reflect::set(
+            &options,
+            &JsValue::from_str("open_revs"),
+            &JsValue::from_str("all"),
+        )?;
+        let leaves: Array = JsFuture::from(
+            self.0
+                .get_with_options(JsValue::from_str(doc_id), options.into()),
+        )
+        .await
+        .map_err(Error::from_status)?
+        .dyn_into()?;
+
+        let mut responses = Vec::new();
+        for leaf in leaves.iter() {
+            let doc = Reflect::get(&leaf, &JsValue::from_str("ok"))?;
+            if doc.is_object() {
+                if let Some(rev) =
+                    Revision::from_js(Reflect::get(&doc, &JsValue::from_str("_rev"))?)
+                {
+                    responses.push(self.purge(doc_id, &rev).await?);
+                }
+            }
+        }
+        Ok(responses)
     }
 
     /// Create/update a batch of documents
@@ -179,7 +461,7 @@ impl PouchDB {
     ) -> Result<Vec<ChangeResponse>, Error> {
         let array = js_sys::Array::new();
         for doc in docs {
-            let object = document::serialize(&doc)?;
+            let object = self.2.incoming_js(document::serialize(&doc)?).await?;
             array.push(&object);
         }
         let response: Array = JsFuture::from(self.0.bulk_docs(array.into()))
@@ -189,54 +471,114 @@ impl PouchDB {
         response.iter().map(|doc: JsValue| doc.try_into()).collect()
     }
 
+    /// Resolve the conflicts of a document
+    ///
+    /// Fetches the winning revision and all conflicting leaf revisions of `doc_id` and
+    /// passes them to `resolver`. The merged body is written on top of the winning
+    /// revision and all losing revisions are deleted, in a single [bulk_docs] call.
+    ///
+    /// Returns an empty vector if the document has no conflicts.
+    pub async fn resolve_conflicts<R>(
+        &self,
+        doc_id: &str,
+        resolver: &R,
+    ) -> Result<Vec<ChangeResponse>, Error>
+    where
+        R: ConflictResolver + ?Sized,
+    {
+        let options = Object::new();
+        Reflect::set(&options, &JsValue::from_str("conflicts"), &JsValue::TRUE)?;
+        let winner = JsFuture::from(
+            self.0
+                .get_with_options(JsValue::from_str(doc_id), options.into()),
+        )
+        .await?;
+        let conflict_revs = Reflect::get(&winner, &JsValue::from_str("_conflicts"))?;
+        if !Array::is_array(&conflict_revs) || Array::from(&conflict_revs).length() == 0 {
+            return Ok(Vec::new());
+        }
+
+        let options = Object::new();
+        Reflect::set(&options, &JsValue::from_str("open_revs"), &conflict_revs)?;
+        let leaves: Array = JsFuture::from(
+            self.0
+                .get_with_options(JsValue::from_str(doc_id), options.into()),
+        )
+        .await?
+        .dyn_into()?;
+        let losers = leaves
+            .iter()
+            .filter_map(|leaf| {
+                Reflect::get(&leaf, &JsValue::from_str("ok"))
+                    .ok()
+                    .filter(|doc| doc.is_object())
+            })
+            .map(|doc| doc.into_serde())
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        let winner: serde_json::Value = winner.into_serde()?;
+
+        let merged = conflicts::merged_document(resolver.resolve(&winner, &losers), &winner);
+        let docs = Array::new();
+        docs.push(&JsValue::from_serde(&merged)?);
+        for loser in &losers {
+            docs.push(&JsValue::from_serde(&conflicts::tombstone(loser))?);
+        }
+        let response: Array = JsFuture::from(self.0.bulk_docs(docs.into()))
+            .await?
+            .dyn_into()?;
+
+        response.iter().map(|doc: JsValue| doc.try_into()).collect()
+    }
+
     /// Fetch multiple documents, indexed and sorted by the id. Deleted documents are only included
     /// if options.keys is specified.
-    /// Entries in the result vector are None when the key was not found (when options.keys is supplied).
-    pub async fn all_docs(
-        &self,
-        options: &AllDocsOptions,
-    ) -> Result<Vec<Option<SerializedDocument>>, Error> {
+    /// When options.keys is supplied, rows for missing documents say whether the document was
+    /// deleted ([AllDocsValue::Deleted]) or never existed ([AllDocsValue::NotFound]).
+    pub async fn all_docs(&self, options: &AllDocsOptions) -> Result<AllDocsResponse, Error> {
         let options = JsValue::from_serde(options)?;
         Reflect::set(&options, &JsValue::from_str("binary"), &JsValue::TRUE)?; // we don't want to support base64
 
         let response = JsFuture::from(self.0.all_docs_with_options(options)).await?;
-        let rows: js_sys::Array = Reflect::get(&response, &JsValue::from_str("rows"))?.into();
-        Ok(rows
-            .iter()
-            .map(|row| {
-                if !Reflect::has(&row, &JsValue::from_str("error")).unwrap_or(true) {
-                    if let Some(value) = Reflect::get(&row, &JsValue::from_str("value"))
-                        .ok()
-                        .filter(|value| !value.is_undefined())
-                    {
-                        if Reflect::get(&value, &JsValue::from_str("deleted"))
-                            .map(|deleted| deleted.is_truthy())
-                            .unwrap_or(false)
-                        {
-                            if let Some(rev) = Reflect::get(&value, &JsValue::from_str("rev"))
-                                .ok()
-                                .filter(|value| !value.is_undefined())
-                            {
-                                if let Some(id) = Reflect::get(&row, &JsValue::from_str("id"))
-                                    .ok()
-                                    .filter(|value| !value.is_undefined())
-                                {
-                                    if let Some(id) = id.as_string() {
-                                        return Some(SerializedDocument::new_deleted(&id, rev));
-                                    }
-                                }
-                            }
-                        } else if let Some(doc) = Reflect::get(&row, &JsValue::from_str("doc"))
-                            .ok()
-                            .filter(|value| !value.is_undefined())
-                        {
-                            return SerializedDocument::try_from(doc).ok();
-                        }
-                    }
-                }
-                None
-            })
-            .collect())
+        self.2.outgoing_in(&response, "rows", Some("doc")).await?;
+        response.try_into()
+    }
+
+    /// Iterate over all documents page by page
+    ///
+    /// Instead of [AllDocsOptions::skip], each page starts at the id following the last
+    /// row of the previous page (the startkey pattern), so memory usage and performance
+    /// stay constant for large databases. [AllDocsOptions::limit] limits the total number
+    /// of rows. If [AllDocsOptions::keys] is given, each page contains up to `page_size`
+    /// of the keys.
+    ///
+    /// If [AllDocsOptions::descending] is set, the range is iterated from [AllDocsOptions::endkey]
+    /// down to [AllDocsOptions::startkey]; ranges given in ascending order (e.g. by
+    /// [AllDocsOptions::set_prefix]) are swapped accordingly.
+    pub fn all_docs_pages(
+        &self,
+        options: AllDocsOptions,
+        page_size: NonZeroU32,
+    ) -> impl Stream<Item = Result<AllDocsResponse, Error>> + '_ {
+        pagination::all_docs_pages(self, options, page_size)
+    }
+
+    /// Iterate over the rows of a persisted view page by page
+    ///
+    /// Like [all_docs_pages], but since view keys aren't unique, each page starts at the
+    /// key and document id ([QueryOptions::startkey_docid]) of the row following the
+    /// previous page. Reduce is always disabled. Like there, ranges given in ascending order
+    /// are swapped if [QueryOptions::descending] is set.
+    pub fn query_pages<'a, K, V>(
+        &'a self,
+        view: &str,
+        options: QueryOptions,
+        page_size: NonZeroU32,
+    ) -> impl Stream<Item = Result<QueryResponse<K, V>, Error>> + 'a
+    where
+        K: Serialize + for<'de> Deserialize<'de> + 'a,
+        V: for<'de> Deserialize<'de> + 'a,
+    {
+        pagination::query_pages(self, view, options, page_size)
     }
 
     /// Get attachment data.
@@ -248,7 +590,7 @@ impl PouchDB {
     ) -> Result<Blob, JsValue> {
         let blob = if let Some(rev) = rev {
             let options = Object::new();
-            Reflect::set(&options, &JsValue::from_str("rev"), rev.as_ref())?;
+            Reflect::set(&options, &JsValue::from_str("rev"), &rev.to_js())?;
             JsFuture::from(self.0.get_attachment_with_options(
                 JsValue::from_str(doc_id),
                 JsValue::from_str(attachment_id),
@@ -291,13 +633,16 @@ impl PouchDB {
             ) {
                 Object::assign(js_options, query_params);
             }
-            if let Some(since) = &options.since {
-                Reflect::set(&js_options, &JsValue::from_str("since"), &since.0)?;
-            }
+        }
+        if let Some(since) = &options.since {
+            Reflect::set(&js_options, &JsValue::from_str("since"), &since.to_js()?)?;
         }
         Reflect::set(&js_options, &JsValue::from_str("live"), &JsValue::TRUE)?;
         Reflect::set(&js_options, &JsValue::from_str("binary"), &JsValue::TRUE)?;
-        Ok(ChangesEventEmitter::new(self.0.changes(js_options)))
+        Ok(ChangesEventEmitter::new(
+            self.0.changes(js_options),
+            self.2.clone(),
+        ))
     }
 
     /// If you use [changes_oneshot] instead of [changes], it will be treated as a
@@ -314,11 +659,12 @@ impl PouchDB {
             ) {
                 Object::assign(js_options, query_params);
             }
-            if let Some(since) = &options.since {
-                Reflect::set(&js_options, &JsValue::from_str("since"), &since.0)?;
-            }
+        }
+        if let Some(since) = &options.since {
+            Reflect::set(&js_options, &JsValue::from_str("since"), &since.to_js()?)?;
         }
         let info = JsFuture::from(self.0.changes_oneshot(js_options)).await?;
+        self.2.outgoing_in(&info, "results", Some("doc")).await?;
         if let Some(results) = Reflect::get(&info, &JsValue::from_str("results"))
             .ok()
             .filter(|results| Array::is_array(&results))
@@ -331,7 +677,7 @@ impl PouchDB {
                     .iter()
                     .map(|result| ChangeEvent::new(&result).map_err(|err| err.into()))
                     .collect::<Result<Vec<ChangeEvent>, Error>>()
-                    .map(|results| (results, SequenceID(last_seq)))
+                    .and_then(|results| Ok((results, SequenceID::from_js(last_seq)?)))
             } else {
                 Err(JsValue::from_str("Failed reading last_seq!").into())
             }
@@ -377,9 +723,9 @@ impl PouchDB {
             ) {
                 Object::assign(js_options, query_params);
             }
-            if let Some(since) = &options.since {
-                Reflect::set(&js_options, &JsValue::from_str("since"), &since.0)?;
-            }
+        }
+        if let Some(since) = &options.since {
+            Reflect::set(&js_options, &JsValue::from_str("since"), &since.to_js()?)?;
         }
         Reflect::set(&js_options, &JsValue::from_str("live"), &JsValue::TRUE)?;
         if retry {
@@ -425,9 +771,9 @@ impl PouchDB {
             ) {
                 Object::assign(js_options, query_params);
             }
-            if let Some(since) = &options.since {
-                Reflect::set(&js_options, &JsValue::from_str("since"), &since.0)?;
-            }
+        }
+        if let Some(since) = &options.since {
+            Reflect::set(&js_options, &JsValue::from_str("since"), &since.to_js()?)?;
         }
 
         // these are needed to keep the references alive
@@ -467,34 +813,216 @@ impl PouchDB {
 
     /// Query PouchDB with the given filter function (a string containing JavaScript!)
     /// This code can access the document using the `document` variable and a function via `emit`.
-    /// Whatever gets passed to the emit function is the resulting document.
-    pub async fn query(
+    /// Every call to `emit(key, value)` results in a row of the response.
+    ///
+    /// This builds a temporary view on every call, which is very slow for larger
+    /// databases. Use a [DesignDocument] and [query_view] instead. [QueryOptions::stale]
+    /// is ignored for temporary views.
+    pub async fn query<K, V>(
         &self,
         filter: &str,
         options: QueryOptions,
-    ) -> Result<Vec<SerializedDocument>, Error> {
+    ) -> Result<QueryResponse<K, V>, Error>
+    where
+        K: for<'a> Deserialize<'a>,
+        V: for<'a> Deserialize<'a>,
+    {
         let closure = js_sys::Function::new_with_args("document,emit", filter);
 
-        let options = JsValue::from_serde(&options).unwrap();
+        let options = JsValue::from_serde(&options)?;
         Reflect::set(&options, &JsValue::from_str("binary"), &JsValue::TRUE)?; // we don't want to support base64
+        Reflect::delete_property(
+            options.unchecked_ref::<Object>(),
+            &JsValue::from_str("stale"),
+        )?;
 
         let response =
             JsFuture::from(self.0.query_with_options(closure.unchecked_ref(), options)).await?;
-        let rows: js_sys::Array = Reflect::get(&response, &JsValue::from_str("rows"))?.into();
-        Ok(rows
-            .iter()
-            .filter_map(|row| {
-                if !Reflect::has(&row, &JsValue::from_str("error")).unwrap_or(true) {
-                    if let Some(doc) = Reflect::get(&row, &JsValue::from_str("doc"))
-                        .ok()
-                        .filter(|value| !value.is_undefined())
-                    {
-                        return SerializedDocument::try_from(doc).ok();
-                    }
-                }
-                None
+        self.2.outgoing_in(&response, "rows", Some("doc")).await?;
+        response.try_into()
+    }
+
+    /// Query PouchDB with a map function written in Rust
+    ///
+    /// Like [query], but the map function is a Rust closure receiving each document
+    /// and an [Emitter], so no JavaScript source is evaluated (which works with strict
+    /// Content Security Policies). Like [query], this builds a temporary view.
+    pub async fn query_with<K, V, M>(
+        &self,
+        map: M,
+        options: QueryOptions,
+    ) -> Result<QueryResponse<K, V>, Error>
+    where
+        K: for<'a> Deserialize<'a>,
+        V: for<'a> Deserialize<'a>,
+        M: Fn(&serde_json::Value, &mut Emitter) + 'static,
+    {
+        let map = view::map_closure(map);
+        let fun = pouchdb_sys::map_function(map.as_ref());
+
+        let options = JsValue::from_serde(&QueryOptions {
+            reduce: false,
+            stale: None,
+            ..options
+        })?;
+        Reflect::set(&options, &JsValue::from_str("binary"), &JsValue::TRUE)?; // we don't want to support base64
+
+        let response = JsFuture::from(self.0.query_fun_with_options(&fun, options)).await?;
+        self.2.outgoing_in(&response, "rows", Some("doc")).await?;
+        response.try_into()
+    }
+
+    /// Query PouchDB with map and reduce functions written in Rust
+    ///
+    /// The reduce function is called as `reduce(keys, values, rereduce)` once for each
+    /// group, with all its rows. `keys` contains `[key, id]` pairs of the emitted rows, and
+    /// `rereduce` is always false. Since PouchDB can't call Rust reduce functions, the rows
+    /// of [query_with] are reduced in Rust, and [QueryOptions::skip] and
+    /// [QueryOptions::limit] apply to the reduced rows.
+    pub async fn query_with_reduce<K, V, M, R>(
+        &self,
+        map: M,
+        reduce: R,
+        options: QueryOptions,
+    ) -> Result<ReduceResponse<K, V>, Error>
+    where
+        K: for<'a> Deserialize<'a>,
+        V: for<'a> Deserialize<'a>,
+        M: Fn(&serde_json::Value, &mut Emitter) + 'static,
+        R: Fn(&[serde_json::Value], &[serde_json::Value], bool) -> serde_json::Value + 'static,
+    {
+        let mapped: QueryResponse<serde_json::Value, serde_json::Value> = self
+            .query_with(
+                map,
+                QueryOptions {
+                    include_docs: false,
+                    limit: None,
+                    skip: None,
+                    ..options.clone()
+                },
+            )
+            .await?;
+
+        let rows = view::reduce_rows(mapped.rows, options.group, options.group_level, reduce)
+            .into_iter()
+            .skip(options.skip.unwrap_or(0) as usize)
+            .take(options.limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(|(key, value)| {
+                Ok(ReducedRow {
+                    key: serde_json::from_value(key)?,
+                    value: serde_json::from_value(value)?,
+                })
             })
-            .collect())
+            .collect::<Result<_, Error>>()?;
+        Ok(ReduceResponse {
+            update_seq: mapped.update_seq,
+            rows,
+        })
+    }
+
+    /// Query a persisted view of a design document
+    ///
+    /// `view` is given as `"design_doc_name/view_name"`, or just `"view_name"` as shorthand
+    /// for `"view_name/view_name"`. The view index is updated incrementally, unless
+    /// [QueryOptions::stale] says otherwise.
+    ///
+    /// The reduce function of the view, if any, is skipped, so [QueryOptions::reduce] is
+    /// ignored. Use [query_view_reduced] to get the output of the reduce function.
+    pub async fn query_view<K, V>(
+        &self,
+        view: &str,
+        options: QueryOptions,
+    ) -> Result<QueryResponse<K, V>, Error>
+    where
+        K: for<'a> Deserialize<'a>,
+        V: for<'a> Deserialize<'a>,
+    {
+        let options = JsValue::from_serde(&QueryOptions {
+            reduce: false,
+            ..options
+        })?;
+        Reflect::set(&options, &JsValue::from_str("binary"), &JsValue::TRUE)?; // we don't want to support base64
+
+        let response = JsFuture::from(self.0.query_view_with_options(view, options))
+            .await
+            .map_err(Error::from_status)?;
+        self.2.outgoing_in(&response, "rows", Some("doc")).await?;
+        response.try_into()
+    }
+
+    /// Query the reduce function of a persisted view
+    ///
+    /// Like [query_view], but returns the output of the reduce function, grouped
+    /// according to [QueryOptions::group] and [QueryOptions::group_level].
+    pub async fn query_view_reduced<K, V>(
+        &self,
+        view: &str,
+        options: QueryOptions,
+    ) -> Result<ReduceResponse<K, V>, Error>
+    where
+        K: for<'a> Deserialize<'a>,
+        V: for<'a> Deserialize<'a>,
+    {
+        let options = JsValue::from_serde(&QueryOptions {
+            reduce: true,
+            ..options
+        })?;
+
+        JsFuture::from(self.0.query_view_with_options(view, options))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Create/update a design document
+    ///
+    /// To update an existing design document, its revision must be set (e.g. by fetching
+    /// it with [get_design_doc] first). Changing a view invalidates its index.
+    pub async fn put_design_doc(&self, ddoc: &DesignDocument) -> Result<ChangeResponse, Error> {
+        JsFuture::from(self.0.put(document::serialize(ddoc)?))
+            .await
+            .map_err(Error::from_status)?
+            .try_into()
+    }
+
+    /// Fetch a design document by its name (without the `_design/` prefix)
+    pub async fn get_design_doc(&self, name: &str) -> Result<DesignDocument, Error> {
+        let id = format!("{}{}", design::DESIGN_PREFIX, name);
+        let doc = JsFuture::from(self.0.get(JsValue::from_str(&id)))
+            .await
+            .map_err(Error::from_status)?;
+        let rev = Reflect::get(&doc, &JsValue::from_str("_rev"))?;
+
+        Ok(DesignDocument {
+            name: name.to_owned(),
+            rev: Revision::from_js(rev),
+            ..doc.into_serde()?
+        })
+    }
+
+    /// Query documents with a Mango selector
+    ///
+    /// Requires the pouchdb-find plugin, otherwise [Error::PluginMissing] is returned.
+    pub async fn find(&self, options: &FindOptions) -> Result<FindResponse, Error> {
+        self.require_plugin("find", "pouchdb-find")?;
+        let response = JsFuture::from(self.0.find(JsValue::from_serde(options)?))
+            .await
+            .map_err(Error::from_status)?;
+        self.2.outgoing_in(&response, "docs", None).await?;
+        response.try_into()
+    }
+
+    /// Create a Mango index
+    ///
+    /// Requires the pouchdb-find plugin, otherwise [Error::PluginMissing] is returned.
+    pub async fn create_index(&self, options: &IndexOptions) -> Result<CreateIndexResponse, Error> {
+        self.require_plugin("createIndex", "pouchdb-find")?;
+        let index = serde_json::json!({ "index": options });
+        JsFuture::from(self.0.create_index(JsValue::from_serde(&index)?))
+            .await
+            .map_err(Error::from_status)?
+            .into_serde()
+            .map_err(Error::from)
     }
 }
 
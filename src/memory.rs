@@ -0,0 +1,528 @@
+//! A [Database] kept in memory, implemented in Rust.
+
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use crate::{
+    database::{Database, JsonDocument},
+    document::{self, Revision, LOCAL_PREFIX},
+    error::Error,
+    events::{changes_event_emitter::ChangeEvent, SequenceID},
+    options::{all_docs::AllDocsOptions, changes::Changes, fetch::FetchOptions},
+    responses::{
+        AllDocsResponse, AllDocsRow, AllDocsValue, ChangeResponse, DatabaseInfo, RevsDiffEntry,
+    },
+    rev_tree::{self, DocRecord},
+};
+
+#[derive(Default)]
+struct State {
+    docs: HashMap<String, DocRecord>,
+    /// The id of the document changed at each sequence.
+    by_seq: BTreeMap<u64, String>,
+    /// Local documents with their revision number.
+    local: HashMap<String, (u64, Value)>,
+    update_seq: u64,
+}
+
+impl State {
+    fn write(&mut self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        if doc.id.starts_with(LOCAL_PREFIX) {
+            return self.write_local(doc);
+        }
+        let mut record = self.docs.get(&doc.id).cloned().unwrap_or_default();
+        let rev = record.update(doc)?;
+        record.compact();
+        self.by_seq.remove(&record.seq);
+        self.update_seq += 1;
+        record.seq = self.update_seq;
+        self.by_seq.insert(record.seq, doc.id.clone());
+        self.docs.insert(doc.id.clone(), record);
+        Ok(ChangeResponse {
+            ok: true,
+            id: doc.id.clone(),
+            rev,
+        })
+    }
+
+    /// Adds a revision made elsewhere, with its history in `_revisions`.
+    fn merge(&mut self, doc: &JsonDocument) -> Result<(), Error> {
+        let mut doc = doc.clone();
+        let ancestry = rev_tree::take_revisions(&mut doc);
+        let mut record = self.docs.get(&doc.id).cloned().unwrap_or_default();
+        if record.merge(&doc, &ancestry)? {
+            record.compact();
+            self.by_seq.remove(&record.seq);
+            self.update_seq += 1;
+            record.seq = self.update_seq;
+            self.by_seq.insert(record.seq, doc.id.clone());
+            self.docs.insert(doc.id.clone(), record);
+        }
+        Ok(())
+    }
+
+    /// Local documents keep no history and only need the current revision to be updated.
+    fn write_local(&mut self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let current = self.local.get(&doc.id).map(|(rev, _)| *rev);
+        let rev = rev_tree::update_local(current, doc)?;
+        match rev {
+            Some(rev) => self.local.insert(doc.id.clone(), (rev, doc.data.clone())),
+            None => self.local.remove(&doc.id),
+        };
+        Ok(ChangeResponse {
+            ok: true,
+            id: doc.id.clone(),
+            rev: rev_tree::local_revision(rev.unwrap_or(0)),
+        })
+    }
+
+    fn doc_count(&self) -> u64 {
+        self.docs.values().filter(|doc| !doc.is_deleted()).count() as u64
+    }
+
+    fn row(&self, id: &str, options: &AllDocsOptions) -> Result<AllDocsRow<JsonDocument>, Error> {
+        let value = match self.docs.get(id) {
+            Some(record) => record.all_docs_value(id, options)?,
+            None => AllDocsValue::NotFound,
+        };
+        Ok(AllDocsRow {
+            key: id.to_owned(),
+            value,
+        })
+    }
+}
+
+/// A database kept in memory, e.g. for unit tests of code using [Database].
+///
+/// Implements the same revision and conflict semantics as PouchDB: every edit adds a
+/// revision to the revision tree of the document, conflicting edits are rejected with
+/// [Error::Conflict], and the winning revision is chosen like PouchDB does. Revision ids
+/// are deterministic, i.e. the same edits always result in the same revisions.
+///
+/// Filters, views and selectors in [Changes] aren't supported.
+pub struct MemoryDatabase {
+    name: String,
+    id: String,
+    state: Mutex<State>,
+}
+
+impl MemoryDatabase {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        let name = name.into();
+        Self {
+            id: document::generate_id(&name),
+            name,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl std::fmt::Debug for MemoryDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "MemoryDatabase {}", self.name)
+    }
+}
+
+impl Database for MemoryDatabase {
+    async fn info(&self) -> Result<DatabaseInfo, Error> {
+        let state = self.state();
+        Ok(DatabaseInfo {
+            db_name: self.name.clone(),
+            doc_count: state.doc_count(),
+            update_seq: state.update_seq.into(),
+        })
+    }
+
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        self.state().write(doc)
+    }
+
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        let doc = JsonDocument {
+            id: document::generate_id(&self.name),
+            ..doc.clone()
+        };
+        self.state().write(&doc)
+    }
+
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        let state = self.state();
+        if doc_id.starts_with(LOCAL_PREFIX) {
+            let (rev, data) = state.local.get(doc_id).ok_or_else(rev_tree::missing)?;
+            return Ok(JsonDocument {
+                id: doc_id.to_owned(),
+                rev: Some(rev_tree::local_revision(*rev)),
+                data: data.clone(),
+                ..JsonDocument::default()
+            });
+        }
+        state
+            .docs
+            .get(doc_id)
+            .ok_or_else(rev_tree::missing)?
+            .document(doc_id, options)
+    }
+
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        self.state()
+            .write(&JsonDocument::deleted(doc_id, rev.clone()))
+    }
+
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        let mut state = self.state();
+        Ok(docs
+            .iter()
+            .map(|doc| {
+                if doc.id.is_empty() {
+                    let doc = JsonDocument {
+                        id: document::generate_id(&self.name),
+                        ..doc.clone()
+                    };
+                    state.write(&doc)
+                } else {
+                    state.write(doc)
+                }
+            })
+            .collect())
+    }
+
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        let state = self.state();
+        let live = state
+            .docs
+            .iter()
+            .filter(|(_, record)| !record.is_deleted())
+            .map(|(id, _)| id);
+        let rows = options
+            .select(live)
+            .into_iter()
+            .map(|id| state.row(id, options))
+            .collect::<Result<_, _>>()?;
+
+        Ok(AllDocsResponse {
+            total_rows: state.doc_count(),
+            offset: options.skip.unwrap_or(0) as u64,
+            update_seq: Some(state.update_seq.into()).filter(|_| options.update_seq),
+            rows,
+        })
+    }
+
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        if options.filter.is_some() || options.view.is_some() || options.selector.is_some() {
+            return Err(Error::Unsupported(
+                "Filters are not supported by MemoryDatabase.".to_owned(),
+            ));
+        }
+        let state = self.state();
+        let since = options.since_number(state.update_seq)?;
+
+        let mut changes: Vec<(&u64, &String)> = state.by_seq.range(since + 1..).collect();
+        if options.descending {
+            changes.reverse();
+        }
+        let limit = options.limit.map_or(usize::MAX, |limit| limit as usize);
+        // The last sequence scanned, so that the next request continues after the changes
+        // that were filtered out.
+        let mut last_seq = since;
+        let mut results = Vec::new();
+        for (seq, id) in changes {
+            if results.len() == limit {
+                break;
+            }
+            if !options.descending {
+                last_seq = *seq;
+            }
+            if options.doc_ids.is_empty() || options.doc_ids.contains(id) {
+                results.push(state.docs[id].change_event(id, *seq, options)?);
+                last_seq = *seq;
+            }
+        }
+        Ok((results, last_seq.into()))
+    }
+
+    async fn id(&self) -> Result<String, Error> {
+        Ok(self.id.clone())
+    }
+
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        let state = self.state();
+        Ok(revs
+            .into_iter()
+            .map(|(id, revs)| {
+                let entry = match state.docs.get(&id) {
+                    Some(record) => record.revs_diff(&revs),
+                    None => RevsDiffEntry {
+                        missing: revs,
+                        possible_ancestors: Vec::new(),
+                    },
+                };
+                (id, entry)
+            })
+            .filter(|(_, entry)| !entry.missing.is_empty())
+            .collect())
+    }
+
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        let state = self.state();
+        Ok(docs
+            .iter()
+            .map(|(id, rev)| {
+                let options = FetchOptions::default().rev(rev.as_str()).revs(true);
+                state
+                    .docs
+                    .get(id)
+                    .ok_or_else(rev_tree::missing)?
+                    .document(id, &options)
+            })
+            .collect())
+    }
+
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        let mut state = self.state();
+        Ok(docs.iter().map(|doc| state.merge(doc)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use serde_json::json;
+
+    fn doc(id: &str, data: Value) -> JsonDocument {
+        JsonDocument::new(id, data)
+    }
+
+    #[test]
+    fn revisions_are_deterministic() {
+        let first = MemoryDatabase::new("first");
+        let second = MemoryDatabase::new("second");
+        for db in &[&first, &second] {
+            block_on(async {
+                let rev = db.put(&doc("a", json!({"n": 1}))).await.unwrap().rev;
+                assert_eq!(rev.generation(), 1);
+                db.put(&doc("a", json!({"n": 2})).rev(rev)).await.unwrap();
+            });
+        }
+        let fetch =
+            |db: &MemoryDatabase| block_on(db.fetch("a", &FetchOptions::default())).unwrap();
+        assert_eq!(fetch(&first), fetch(&second));
+        // md5 of JSON.stringify({"n":1,"_id":"a"})
+        assert_eq!(
+            block_on(first.fetch("a", &FetchOptions::default().revs(true)))
+                .unwrap()
+                .data["_revisions"]["ids"][1],
+            "0e9387f34d7c0c0241b49e39b3c8cacf"
+        );
+    }
+
+    #[test]
+    fn conflicting_updates_are_rejected() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            let rev = db.put(&doc("a", json!({}))).await.unwrap().rev;
+            assert!(matches!(
+                db.put(&doc("a", json!({}))).await,
+                Err(Error::Conflict(_))
+            ));
+            let update = db.put(&doc("a", json!({"x": 1})).rev(rev.clone())).await;
+            assert_eq!(update.unwrap().rev.generation(), 2);
+            assert!(matches!(
+                db.put(&doc("a", json!({"x": 2})).rev(rev)).await,
+                Err(Error::Conflict(_))
+            ));
+            assert!(matches!(
+                db.put(&doc("_a", json!({}))).await,
+                Err(Error::BadRequest(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn deleted_documents_can_be_recreated() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            let rev = db.put(&doc("a", json!({}))).await.unwrap().rev;
+            db.remove("a", &rev).await.unwrap();
+            assert!(matches!(
+                db.fetch("a", &FetchOptions::default()).await,
+                Err(Error::NotFound(reason)) if reason == "deleted"
+            ));
+            assert_eq!(db.info().await.unwrap().doc_count, 0);
+
+            let rev = db.put(&doc("a", json!({"y": 1}))).await.unwrap().rev;
+            assert_eq!(rev.generation(), 3);
+            let doc = db.fetch("a", &FetchOptions::default()).await.unwrap();
+            assert_eq!(doc.data, json!({"y": 1}));
+        });
+    }
+
+    #[test]
+    fn bulk_docs_reports_errors_per_document() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            let rev = db.put(&doc("a", json!({}))).await.unwrap().rev;
+            let results = db
+                .bulk_docs(&[doc("a", json!({})), doc("b", json!({})), doc("", json!({}))])
+                .await
+                .unwrap();
+            assert!(matches!(results[0], Err(Error::Conflict(_))));
+            assert_eq!(results[1].as_ref().unwrap().id, "b");
+            assert_eq!(results[2].as_ref().unwrap().id.len(), 32);
+
+            let update = JsonDocument::new("a", json!({"x": 1})).rev(rev);
+            assert!(db.bulk_docs(&[update]).await.unwrap()[0].is_ok());
+        });
+    }
+
+    #[test]
+    fn conflicts_pick_the_same_winner_as_pouchdb() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            let base = db.put(&doc("a", json!({}))).await.unwrap().rev;
+            let left = Revision::from("2-aaa");
+            let right = Revision::from("2-bbb");
+            {
+                let mut state = db.state();
+                let record = state.docs.get_mut("a").unwrap();
+                record.revs.insert(left.clone(), Some(base.clone()), false);
+                record.bodies.insert(left.clone(), json!({"side": "left"}));
+                record.revs.insert(right.clone(), Some(base), false);
+                record
+                    .bodies
+                    .insert(right.clone(), json!({"side": "right"}));
+            }
+
+            let options = FetchOptions::default().conflicts(Vec::<String>::new());
+            let winner = db.fetch("a", &options).await.unwrap();
+            assert_eq!(winner.rev, Some(right.clone()));
+            assert_eq!(winner.conflicts, vec![left.clone()]);
+
+            db.remove("a", &right).await.unwrap();
+            let winner = db.fetch("a", &options).await.unwrap();
+            assert_eq!(winner.rev, Some(left));
+            assert!(winner.conflicts.is_empty());
+        });
+    }
+
+    #[test]
+    fn all_docs_and_changes() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            for id in &["b", "a", "c", "_design/x"] {
+                db.put(&doc(id, json!({"id": id}))).await.unwrap();
+            }
+            let rev = db.fetch("c", &FetchOptions::default()).await.unwrap().rev;
+            db.remove("c", &rev.unwrap()).await.unwrap();
+            db.put(&doc("_local/x", json!({}))).await.unwrap();
+
+            let ids = |response: AllDocsResponse<JsonDocument>| {
+                response
+                    .rows
+                    .into_iter()
+                    .map(|row| row.key)
+                    .collect::<Vec<_>>()
+            };
+            let all = db.all_docs(&AllDocsOptions::default()).await.unwrap();
+            assert_eq!(all.total_rows, 3);
+            assert_eq!(ids(all), vec!["_design/x", "a", "b"]);
+            let options = AllDocsOptions {
+                descending: true,
+                startkey: Some("b".to_owned()),
+                endkey: Some("a".to_owned()),
+                inclusive_end: false,
+                ..AllDocsOptions::default()
+            };
+            assert_eq!(ids(db.all_docs(&options).await.unwrap()), vec!["b"]);
+            let options = AllDocsOptions {
+                keys: vec!["c".to_owned(), "x".to_owned()],
+                ..AllDocsOptions::default()
+            };
+            let rows = db.all_docs(&options).await.unwrap().rows;
+            assert!(matches!(rows[0].value, AllDocsValue::Deleted { .. }));
+            assert!(matches!(rows[1].value, AllDocsValue::NotFound));
+
+            let (changes, last_seq) = db.changes(&Changes::default()).await.unwrap();
+            let ids: Vec<_> = changes.iter().map(|change| change.id.as_str()).collect();
+            assert_eq!(ids, vec!["b", "a", "_design/x", "c"]);
+            assert!(changes[3].deleted);
+            assert_eq!(last_seq, SequenceID::from(5));
+            let options = Changes {
+                since: Some(SequenceID::from(3)),
+                include_docs: true,
+                ..Changes::default()
+            };
+            let (changes, _) = db.changes(&options).await.unwrap();
+            assert_eq!(changes.len(), 2);
+            assert_eq!(
+                changes[0].doc.as_ref().unwrap().data,
+                json!({"id": "_design/x"})
+            );
+
+            // Changes filtered out at the end still count as seen.
+            let options = Changes {
+                doc_ids: vec!["a".to_owned()],
+                ..Changes::default()
+            };
+            let (changes, last_seq) = db.changes(&options).await.unwrap();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(last_seq, SequenceID::from(5));
+            let options = Changes {
+                limit: Some(1),
+                ..Changes::default()
+            };
+            let (_, last_seq) = db.changes(&options).await.unwrap();
+            assert_eq!(last_seq, SequenceID::from(1));
+        });
+    }
+
+    #[test]
+    fn local_documents_keep_no_history() {
+        let db = MemoryDatabase::new("test");
+        block_on(async {
+            let id = format!("{}checkpoint", LOCAL_PREFIX);
+            let rev = db.put(&doc(&id, json!({"seq": 1}))).await.unwrap().rev;
+            assert_eq!(rev, Revision::from("0-1"));
+            assert!(matches!(
+                db.put(&doc(&id, json!({"seq": 2}))).await,
+                Err(Error::Conflict(_))
+            ));
+            let rev = db
+                .put(&doc(&id, json!({"seq": 2})).rev(rev))
+                .await
+                .unwrap()
+                .rev;
+            assert_eq!(rev, Revision::from("0-2"));
+            db.remove(&id, &rev).await.unwrap();
+            assert!(matches!(
+                db.fetch(&id, &FetchOptions::default()).await,
+                Err(Error::NotFound(_))
+            ));
+        });
+    }
+}
@@ -2,6 +2,7 @@ pub mod all_docs;
 pub mod changes;
 pub mod create;
 pub mod fetch;
+pub mod find;
 pub mod query;
 pub mod replication;
 pub mod selector;
@@ -1,10 +1,12 @@
 use serde::Serialize;
 
+use crate::collate::string_collate;
+
 /// All options default to false unless otherwise specified.
 ///
 /// Notes: For pagination, [limit] and [skip] are also available, but the same performance
 /// concerns as in CouchDB apply. Use the [startkey/endkey pattern](http://docs.couchdb.org/en/latest/couchapp/views/pagination.html) instead.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct AllDocsOptions {
     /// Include the document itself in each row in the doc field.
     /// Otherwise by default you only get the id and rev properties.
@@ -78,4 +80,71 @@ impl AllDocsOptions {
         self.startkey = Some(prefix.to_owned());
         self.endkey = Some(format!("{}\u{fff0}", prefix));
     }
+
+    /// The ids to return for these options, given the ids of all live documents. Used by
+    /// the backends implemented in Rust.
+    pub(crate) fn select<'a, I>(&'a self, ids: I) -> Vec<&'a String>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let ids = if !self.keys.is_empty() {
+            if self.descending {
+                self.keys.iter().rev().collect()
+            } else {
+                self.keys.iter().collect()
+            }
+        } else {
+            self.ids_in_range(ids)
+        };
+        ids.into_iter()
+            .skip(self.skip.unwrap_or(0) as usize)
+            .take(self.limit.map_or(usize::MAX, |limit| limit as usize))
+            .collect()
+    }
+
+    /// The ids in the range given by the options, in the requested order.
+    fn ids_in_range<'a, I>(&self, ids: I) -> Vec<&'a String>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let mut ids: Vec<&String> = ids.into_iter().collect();
+        ids.sort_by(|a, b| string_collate(a, b));
+        if self.descending {
+            ids.reverse();
+        }
+        ids.into_iter()
+            .filter(|id| self.after_start(id) && self.before_end(id))
+            .collect()
+    }
+
+    /// The first id of the range, [key] or [startkey].
+    pub(crate) fn start(&self) -> Option<&String> {
+        self.key.as_ref().or(self.startkey.as_ref())
+    }
+
+    /// Whether `id` doesn't come before the start of the range, in the requested order.
+    pub(crate) fn after_start(&self, id: &str) -> bool {
+        self.start().is_none_or(|startkey| {
+            let ordering = string_collate(id, startkey);
+            if self.descending {
+                ordering.is_le()
+            } else {
+                ordering.is_ge()
+            }
+        })
+    }
+
+    /// Whether `id` doesn't come after the end of the range, in the requested order.
+    pub(crate) fn before_end(&self, id: &str) -> bool {
+        let endkey = self.key.as_ref().or(self.endkey.as_ref());
+        endkey.is_none_or(|endkey| {
+            let ordering = string_collate(id, endkey);
+            match (self.descending, self.inclusive_end || self.key.is_some()) {
+                (false, true) => ordering.is_le(),
+                (false, false) => ordering.is_lt(),
+                (true, true) => ordering.is_ge(),
+                (true, false) => ordering.is_gt(),
+            }
+        })
+    }
 }
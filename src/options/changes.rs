@@ -2,7 +2,7 @@ use serde::{Serialize, Serializer};
 use wasm_bindgen::JsValue;
 
 use super::selector::Selector;
-use crate::events::SequenceID;
+use crate::{error::Error, events::SequenceID};
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Timeout {
@@ -36,6 +36,23 @@ impl Serialize for Timeout {
     }
 }
 
+/// Which revisions are listed in the `changes` of a change.
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    /// Only the winning revision.
+    #[default]
+    MainOnly,
+    /// All leaf revisions, including conflicts and deleted ones.
+    AllDocs,
+}
+
+impl Style {
+    pub(crate) fn is_default(&self) -> bool {
+        self == &Self::MainOnly
+    }
+}
+
 /// All options default to false unless otherwise specified.
 #[derive(Serialize, Default, Debug)]
 pub struct Changes {
@@ -93,5 +110,22 @@ pub struct Changes {
     /// at a time. Increasing this can reduce the number of requests made. Default is 25.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub batch_size: Option<u32>,
+    /// Whether to list only the winning revision or all leaf revisions in each change.
+    #[serde(skip_serializing_if = "Style::is_default")]
+    pub style: Style,
     // some options are skipped, because they're not useful right now.
 }
+
+impl Changes {
+    /// [since](Changes::since) as a number, for the backends implemented in Rust, which
+    /// number their changes.
+    pub(crate) fn since_number(&self, update_seq: u64) -> Result<u64, Error> {
+        match &self.since {
+            None => Ok(0),
+            Some(since) if since.0 == "now" => Ok(update_seq),
+            Some(since) => since
+                .number()
+                .ok_or_else(|| Error::BadRequest(format!("Invalid since: {:?}", since.0))),
+        }
+    }
+}
@@ -1,13 +1,59 @@
-use serde::Serialize;
+use base64::prelude::*;
+use futures::Future;
+use hmac::{Hmac, Mac};
+use js_sys::{Function, Reflect};
+use serde::{Serialize, Serializer};
+use sha1::Sha1;
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::JsValue;
+use web_sys::Response;
 
-#[derive(Serialize, Copy, Clone)]
+use crate::{
+    error::Error,
+    fetch_hook::{self, FetchRequest, SharedAuth},
+};
+
+/// The storage backend of a database.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Adapter {
-    #[serde(rename = "idb")]
+    /// IndexedDB, the default in browsers.
     IDB,
-    #[serde(rename = "leveldb")]
+    /// The newer IndexedDB adapter, from pouchdb-adapter-indexeddb.
+    IndexedDB,
+    /// LevelDB, the default in Node.js.
     LevelDB,
-    #[serde(rename = "http")]
+    /// In-memory storage, from pouchdb-adapter-memory.
+    Memory,
+    /// WebSQL emulated on SQLite in Node.js, from pouchdb-adapter-node-websql.
+    NodeWebSql,
+    /// A remote CouchDB database.
     HTTP,
+    /// An adapter registered by a plugin, by its name.
+    Custom(String),
+}
+
+impl Adapter {
+    /// The name PouchDB knows the adapter by.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::IDB => "idb",
+            Self::IndexedDB => "indexeddb",
+            Self::LevelDB => "leveldb",
+            Self::Memory => "memory",
+            Self::NodeWebSql => "websql",
+            Self::HTTP => "http",
+            Self::Custom(name) => name,
+        }
+    }
+}
+
+impl Serialize for Adapter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
 }
 
 #[derive(Serialize, Clone, Default)]
@@ -16,14 +62,34 @@ pub struct CreateOptions {
     pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub adapter: Option<Adapter>,
+    /// A string prepended to the name of the database, e.g. a directory for LevelDB or
+    /// the URL of a server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub revs_limit: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deterministic_revs: Option<bool>,
+    /// Compact the database after every change. Local databases only.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub auto_compaction: bool,
+    /// The adapter used for the indexes of views. Defaults to the adapter of the database.
+    /// Local databases only.
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_adapter: Option<Adapter>,
+    /// The number of purges kept for replication. Local databases only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purged_infos_limit: Option<u32>,
+    /// The credentials of a remote database. They can be changed later with
+    /// [PouchDB::set_auth](crate::PouchDB::set_auth).
+    #[serde(skip_serializing)]
     pub auth: Option<Auth>,
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub skip_setup: bool,
+    /// Replaces `fetch` for the requests of a remote database. Called with the URL and
+    /// the options of each request, and must return a promise of a `Response`.
+    #[serde(skip_serializing)]
+    pub fetch: Option<Function>,
 }
 
 impl CreateOptions {
@@ -39,6 +105,12 @@ impl CreateOptions {
             ..self
         }
     }
+    pub fn prefix<T: Into<String>>(self, prefix: T) -> Self {
+        Self {
+            prefix: Some(prefix.into()),
+            ..self
+        }
+    }
     pub fn revs_limit<T: Into<i64>>(self, revs_limit: T) -> Self {
         Self {
             revs_limit: Some(revs_limit.into()),
@@ -51,6 +123,24 @@ impl CreateOptions {
             ..self
         }
     }
+    pub fn auto_compaction(self, auto_compaction: bool) -> Self {
+        Self {
+            auto_compaction,
+            ..self
+        }
+    }
+    pub fn view_adapter<T: Into<Adapter>>(self, view_adapter: T) -> Self {
+        Self {
+            view_adapter: Some(view_adapter.into()),
+            ..self
+        }
+    }
+    pub fn purged_infos_limit(self, purged_infos_limit: u32) -> Self {
+        Self {
+            purged_infos_limit: Some(purged_infos_limit),
+            ..self
+        }
+    }
     pub fn auth(self, auth: Auth) -> Self {
         Self {
             auth: Some(auth),
@@ -60,10 +150,252 @@ impl CreateOptions {
     pub fn skip_setup(self, skip_setup: bool) -> Self {
         Self { skip_setup, ..self }
     }
+    pub fn fetch(self, fetch: Function) -> Self {
+        Self {
+            fetch: Some(fetch),
+            ..self
+        }
+    }
+    /// Send the requests of a remote database through `hook`, which can change a
+    /// [FetchRequest] before [sending](FetchRequest::send) it, and change the response
+    /// before returning it, e.g. to add headers with a refreshed token:
+    ///
+    /// ```no_run
+    /// # use pouchdb::options::create::CreateOptions;
+    /// let options = CreateOptions::default()
+    ///     .name("https://example.com/db")
+    ///     .fetch_hook(|request| async move {
+    ///         request.headers.set("Authorization", "Bearer token")?;
+    ///         request.send().await
+    ///     });
+    /// ```
+    pub fn fetch_hook<F, Fut>(self, hook: F) -> Self
+    where
+        F: Fn(FetchRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<Response, Error>> + 'static,
+    {
+        self.fetch(fetch_hook::fetch_function(hook))
+    }
+
+    /// Whether the options are for a remote database, i.e. the adapter is
+    /// [Adapter::HTTP] or the name is a URL.
+    pub fn is_remote(&self) -> bool {
+        let is_url = |name: &str| name.starts_with("http://") || name.starts_with("https://");
+        self.adapter == Some(Adapter::HTTP)
+            || self.prefix.as_deref().is_some_and(is_url)
+            || (self.prefix.is_none() && self.name.as_deref().is_some_and(is_url))
+    }
+
+    /// Whether the options are for a local database. Options without a name, prefix or
+    /// adapter, like the defaults of a [PouchDBFactory](crate::factory::PouchDBFactory),
+    /// can be used for both.
+    fn is_local(&self) -> bool {
+        !self.is_remote()
+            && (self.name.is_some() || self.prefix.is_some() || self.adapter.is_some())
+    }
+
+    /// Check that the options are supported by the adapter, returning
+    /// [Error::Unsupported] otherwise.
+    pub fn validate(&self) -> Result<(), Error> {
+        let unsupported = |option: &str, kind: &str| {
+            Err(Error::Unsupported(format!(
+                "{} is not supported by {} databases.",
+                option, kind
+            )))
+        };
+        if self.is_remote() {
+            if let Some(adapter) = self.adapter.as_ref().filter(|a| **a != Adapter::HTTP) {
+                return Err(Error::Unsupported(format!(
+                    "The {} adapter can't open a URL.",
+                    adapter.name()
+                )));
+            }
+            if self.auto_compaction {
+                return unsupported("auto_compaction", "remote");
+            }
+            if self.view_adapter.is_some() {
+                return unsupported("view_adapter", "remote");
+            }
+            if self.purged_infos_limit.is_some() {
+                return unsupported("purged_infos_limit", "remote");
+            }
+        } else if self.is_local() {
+            if self.auth.is_some() {
+                return unsupported("auth", "local");
+            }
+            if self.skip_setup {
+                return unsupported("skip_setup", "local");
+            }
+            if self.fetch.is_some() {
+                return unsupported("fetch", "local");
+            }
+            if self.view_adapter == Some(Adapter::HTTP) {
+                return Err(Error::Unsupported(
+                    "Views of local databases can't be stored remotely.".to_owned(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The validated options as a JavaScript object, and the credentials if set, which
+    /// are added to the requests by a `fetch` wrapping [fetch](Self::fetch).
+    pub(crate) fn to_js(&self) -> Result<(JsValue, Option<SharedAuth>), Error> {
+        self.validate()?;
+        let options = JsValue::from_serde(self)?;
+        let auth = self.auth.clone().map(|auth| Rc::new(RefCell::new(auth)));
+        let fetch = match &auth {
+            Some(auth) => Some(fetch_hook::auth_function(auth.clone(), self.fetch.clone())),
+            None => self.fetch.clone(),
+        };
+        if let Some(fetch) = fetch {
+            Reflect::set(&options, &JsValue::from_str("fetch"), &fetch)?;
+        }
+        Ok((options, auth))
+    }
 }
 
-#[derive(Serialize, Clone)]
-pub struct Auth {
-    pub username: String,
-    pub password: String,
+/// How a remote database authenticates its requests. The credentials are sent with every
+/// request, including the ones of replications and changes feeds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Auth {
+    /// HTTP basic authentication.
+    Basic { username: String, password: String },
+    /// A JSON Web Token, sent as `Authorization: Bearer`.
+    Bearer(String),
+    /// Proxy authentication, for requests going through a proxy that authenticated the
+    /// user. `secret` is the `[chttpd_auth] secret` of CouchDB, which signs the name of the
+    /// user with HMAC-SHA1 if the server requires it.
+    Proxy {
+        username: String,
+        roles: Vec<String>,
+        secret: Option<String>,
+    },
+    /// The cookie of a session started by `log_in`, so no credentials are sent.
+    Cookie,
+}
+
+impl Auth {
+    pub fn basic<U: Into<String>, P: Into<String>>(username: U, password: P) -> Self {
+        Self::Basic {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+    pub fn bearer<T: Into<String>>(token: T) -> Self {
+        Self::Bearer(token.into())
+    }
+    pub fn proxy<U, R, I>(username: U, roles: I, secret: Option<String>) -> Self
+    where
+        U: Into<String>,
+        R: Into<String>,
+        I: IntoIterator<Item = R>,
+    {
+        Self::Proxy {
+            username: username.into(),
+            roles: roles.into_iter().map(Into::into).collect(),
+            secret,
+        }
+    }
+
+    /// The headers to add to each request.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Basic { username, password } => {
+                let credentials = BASE64_STANDARD.encode(format!("{}:{}", username, password));
+                vec![("Authorization", format!("Basic {}", credentials))]
+            }
+            Self::Bearer(token) => vec![("Authorization", format!("Bearer {}", token))],
+            Self::Proxy {
+                username,
+                roles,
+                secret,
+            } => {
+                let mut headers = vec![
+                    ("X-Auth-CouchDB-UserName", username.clone()),
+                    ("X-Auth-CouchDB-Roles", roles.join(",")),
+                ];
+                if let Some(secret) = secret {
+                    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
+                        .expect("HMAC takes keys of any length");
+                    mac.update(username.as_bytes());
+                    let token = mac.finalize().into_bytes();
+                    let token = token.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    headers.push(("X-Auth-CouchDB-Token", token));
+                }
+                headers
+            }
+            Self::Cookie => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_must_match_the_adapter() {
+        let auth = Auth::basic("user", "secret");
+        let remote = CreateOptions::default().name("https://example.com/db");
+        assert!(remote.is_remote());
+        assert!(remote.clone().auth(auth.clone()).validate().is_ok());
+        assert!(matches!(
+            remote.clone().auto_compaction(true).validate(),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            remote.adapter(Adapter::LevelDB).validate(),
+            Err(Error::Unsupported(_))
+        ));
+
+        let local = CreateOptions::default()
+            .name("db")
+            .adapter(Adapter::Custom("custom".to_owned()))
+            .auto_compaction(true)
+            .purged_infos_limit(10);
+        assert!(!local.is_remote());
+        assert!(local.validate().is_ok());
+        assert!(matches!(
+            local.clone().auth(auth).validate(),
+            Err(Error::Unsupported(_))
+        ));
+        let defaults = CreateOptions::default().skip_setup(true);
+        assert!(defaults.validate().is_ok());
+        assert!(defaults.name("db").validate().is_err());
+        assert!(CreateOptions::default()
+            .prefix("http://localhost:5984/")
+            .name("db")
+            .is_remote());
+        assert_eq!(
+            serde_json::to_value(local.view_adapter(Adapter::Memory)).unwrap(),
+            serde_json::json!({
+                "name": "db",
+                "adapter": "custom",
+                "auto_compaction": true,
+                "view_adapter": "memory",
+                "purged_infos_limit": 10,
+            })
+        );
+    }
+
+    #[test]
+    fn auth_headers() {
+        assert_eq!(
+            Auth::basic("alice", "pw").headers(),
+            [("Authorization", "Basic YWxpY2U6cHc=".to_owned())]
+        );
+        assert_eq!(
+            Auth::proxy("bob", ["admin"], Some("secret".to_owned())).headers(),
+            [
+                ("X-Auth-CouchDB-UserName", "bob".to_owned()),
+                ("X-Auth-CouchDB-Roles", "admin".to_owned()),
+                (
+                    "X-Auth-CouchDB-Token",
+                    "dcd244bed8f9dffffa806d4c9523d744d236df13".to_owned()
+                ),
+            ]
+        );
+        assert!(Auth::Cookie.headers().is_empty());
+    }
 }
@@ -1,6 +1,6 @@
 use serde::Serialize;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Debug)]
 #[serde(untagged)]
 pub enum OpenRevs {
     #[serde(serialize_with = "OpenRevs::serialize_all")]
@@ -28,7 +28,7 @@ impl<T: Into<String>> From<Vec<T>> for OpenRevs {
     }
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone, Debug)]
 pub struct FetchOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rev: Option<String>,
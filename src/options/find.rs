@@ -0,0 +1,148 @@
+use serde::{Serialize, Serializer};
+
+use super::selector::Selector;
+
+/// The sort order of a field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sort {
+    Asc(String),
+    Desc(String),
+}
+
+impl Serialize for Sort {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            Self::Asc(field) => map.serialize_entry(field, "asc")?,
+            Self::Desc(field) => map.serialize_entry(field, "desc")?,
+        }
+        map.end()
+    }
+}
+
+impl From<&str> for Sort {
+    fn from(field: &str) -> Self {
+        Self::Asc(field.to_owned())
+    }
+}
+
+/// Options for a Mango query.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct FindOptions {
+    /// The documents to return.
+    pub selector: Selector,
+    /// Only return these fields of each document. All fields are returned if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    /// The fields to sort by. An index on these fields must exist.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sort: Vec<Sort>,
+    /// Maximum number of documents to return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// Number of documents to skip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<u32>,
+    /// The design document (`"ddoc"` or `"ddoc/index_name"`) of the index to use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_index: Option<String>,
+    /// Continue a previous query from the bookmark of its response (CouchDB only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bookmark: Option<String>,
+    /// Include conflict information in the _conflicts field of each document.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub conflicts: bool,
+}
+
+impl FindOptions {
+    pub fn new(selector: Selector) -> Self {
+        Self {
+            selector,
+            ..Self::default()
+        }
+    }
+    pub fn fields<T: Into<String>, I: IntoIterator<Item = T>>(self, fields: I) -> Self {
+        Self {
+            fields: fields.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+    pub fn sort<I: IntoIterator<Item = Sort>>(self, sort: I) -> Self {
+        Self {
+            sort: sort.into_iter().collect(),
+            ..self
+        }
+    }
+    pub fn limit(self, limit: u32) -> Self {
+        Self {
+            limit: Some(limit),
+            ..self
+        }
+    }
+    pub fn skip(self, skip: u32) -> Self {
+        Self {
+            skip: Some(skip),
+            ..self
+        }
+    }
+    pub fn use_index<T: Into<String>>(self, use_index: T) -> Self {
+        Self {
+            use_index: Some(use_index.into()),
+            ..self
+        }
+    }
+    pub fn bookmark<T: Into<String>>(self, bookmark: T) -> Self {
+        Self {
+            bookmark: Some(bookmark.into()),
+            ..self
+        }
+    }
+}
+
+/// Options for creating a Mango index.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct IndexOptions {
+    /// The fields to index.
+    pub fields: Vec<Sort>,
+    /// The name of the index. Generated if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The design document to store the index in (without the `_design/` prefix).
+    /// Generated if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ddoc: Option<String>,
+    /// Only index documents matching this selector.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_filter_selector: Option<Selector>,
+}
+
+impl IndexOptions {
+    pub fn new<I: IntoIterator<Item = Sort>>(fields: I) -> Self {
+        Self {
+            fields: fields.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+    pub fn name<T: Into<String>>(self, name: T) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..self
+        }
+    }
+    pub fn ddoc<T: Into<String>>(self, ddoc: T) -> Self {
+        Self {
+            ddoc: Some(ddoc.into()),
+            ..self
+        }
+    }
+    pub fn partial_filter_selector(self, selector: Selector) -> Self {
+        Self {
+            partial_filter_selector: Some(selector),
+            ..self
+        }
+    }
+}
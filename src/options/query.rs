@@ -1,6 +1,7 @@
-use super::all_docs::AllDocsOptions;
-use serde::Serialize;
-#[derive(Serialize, Debug)]
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum StaleOption {
     /// Returns results immediately, even if they’re out-of-date.
@@ -9,13 +10,90 @@ pub enum StaleOption {
     UpdateAfter,
 }
 
+/// A key emitted by a view. Can be any JSON value; keys are sorted using
+/// [CouchDB collation](https://docs.couchdb.org/en/stable/ddocs/views/collation.html):
+/// `null`, booleans, numbers, strings, arrays, objects.
+///
+/// Use [ViewKey::min] and [ViewKey::max] for open-ended ranges, e.g. `["user", 42]` to
+/// `["user", 42, ViewKey::max()]` to get all keys starting with `"user", 42`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct ViewKey(pub Value);
+
+impl ViewKey {
+    /// A key sorting before all other keys (`null`).
+    pub fn min() -> Self {
+        Self(Value::Null)
+    }
+    /// A key sorting after all other keys that can reasonably be emitted (`{"\uffff": {}}`).
+    pub fn max() -> Self {
+        let mut max = serde_json::Map::new();
+        max.insert("\u{ffff}".to_owned(), Value::Object(serde_json::Map::new()));
+        Self(Value::Object(max))
+    }
+}
+
+impl<T: Into<Value>> From<T> for ViewKey {
+    fn from(value: T) -> Self {
+        Self(value.into())
+    }
+}
+
 /// All options default to false unless otherwise specified.
-#[derive(Serialize, Default, Debug)]
+///
+/// Notes: For pagination, [limit] and [skip] are also available, but the same performance
+/// concerns as in CouchDB apply. Use the [startkey/endkey pattern](http://docs.couchdb.org/en/latest/couchapp/views/pagination.html) instead.
+#[derive(Serialize, Debug, Clone)]
 pub struct QueryOptions {
-    /// Query has the same options that all_docs has
-    #[serde(flatten)]
-    pub all_docs: AllDocsOptions,
+    /// Include the document itself in each row in the doc field.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub include_docs: bool,
+    /// Include conflict information in the _conflicts field of a doc.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub conflicts: bool,
+    /// Include attachment data.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub attachments: bool,
+    /// Get rows with keys in a certain range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startkey: Option<ViewKey>,
+    /// Get rows with keys in a certain range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endkey: Option<ViewKey>,
+    /// Start at the row of this document among the rows with a key equal to [startkey].
+    /// Used for paginating views, whose keys aren't unique.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startkey_docid: Option<String>,
+    /// Stop at the row of this document among the rows with a key equal to [endkey].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endkey_docid: Option<String>,
+    /// Include rows having a key equal to the given [endkey]. Default: true
+    #[serde(skip_serializing_if = "Clone::clone")]
+    pub inclusive_end: bool,
+    /// Maximum number of rows to return.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// Number of rows to skip before returning (warning: poor performance on IndexedDB/LevelDB!).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<u32>,
+    /// Reverse the order of the output rows. Note that the order of [startkey] and [endkey]
+    /// is reversed when [descending] `== true`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub descending: bool,
+    /// Only return rows matching this key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<ViewKey>,
+    /// Array of keys to fetch in a single shot. Neither [startkey] nor [endkey] can be
+    /// specified with this option. The rows are returned in the same order as the supplied keys.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<ViewKey>,
+    /// Include an update_seq value indicating which sequence id of the underlying database the view reflects.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub update_seq: bool,
 
+    /// Run the reduce function of the view, if it has one. Default: true
+    #[serde(skip_serializing_if = "Clone::clone")]
+    pub reduce: bool,
     /// True if you want the reduce function to group results by keys, rather than returning a single result.
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     pub group: bool,
@@ -30,3 +108,54 @@ pub struct QueryOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stale: Option<StaleOption>,
 }
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            include_docs: false,
+            conflicts: false,
+            attachments: false,
+            startkey: None,
+            endkey: None,
+            startkey_docid: None,
+            endkey_docid: None,
+            inclusive_end: true,
+            limit: None,
+            skip: None,
+            descending: false,
+            key: None,
+            keys: Vec::new(),
+            update_seq: false,
+            reduce: true,
+            group: false,
+            group_level: None,
+            stale: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryOptions, ViewKey};
+    use serde_json::json;
+
+    #[test]
+    fn view_keys_serialize_as_json() {
+        let options = QueryOptions {
+            startkey: Some(json!(["user", 42]).into()),
+            endkey: Some(json!(["user", 42, ViewKey::max()]).into()),
+            keys: vec![ViewKey::min(), 1.5.into(), "a".into()],
+            reduce: false,
+            ..QueryOptions::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&options).unwrap(),
+            json!({
+                "startkey": ["user", 42],
+                "endkey": ["user", 42, {"\u{ffff}": {}}],
+                "keys": [null, 1.5, "a"],
+                "reduce": false,
+            })
+        );
+    }
+}
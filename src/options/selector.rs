@@ -1,5 +1,21 @@
 use serde::Serialize;
 use serde_json::{Map, Value};
 
-#[derive(Default, Serialize, Debug)]
+/// A Mango selector, e.g. `{"type": "user", "age": {"$gt": 20}}`.
+#[derive(Default, Serialize, Debug, Clone, PartialEq)]
 pub struct Selector(Map<String, Value>);
+
+impl Selector {
+    /// Add a condition for a field, either a value it has to equal or an object of
+    /// operators like `{"$gt": 20}`.
+    pub fn field<T: Into<String>, V: Into<Value>>(mut self, name: T, condition: V) -> Self {
+        self.0.insert(name.into(), condition.into());
+        self
+    }
+}
+
+impl From<Map<String, Value>> for Selector {
+    fn from(selector: Map<String, Value>) -> Self {
+        Self(selector)
+    }
+}
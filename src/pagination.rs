@@ -0,0 +1,168 @@
+use std::{cmp::Ordering, num::NonZeroU32};
+
+use futures::{stream, Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collate::collate,
+    error::Error,
+    options::{all_docs::AllDocsOptions, query::QueryOptions, query::ViewKey},
+    responses::{AllDocsResponse, QueryResponse},
+    PouchDB,
+};
+
+/// The number of rows to request for the next page, or `None` if the overall limit
+/// has been reached.
+fn next_limit(page_size: u32, remaining: Option<u32>) -> Option<u32> {
+    match remaining {
+        Some(0) => None,
+        Some(remaining) => Some(page_size.min(remaining)),
+        None => Some(page_size),
+    }
+}
+
+struct AllDocsPager<'a> {
+    db: &'a PouchDB,
+    options: AllDocsOptions,
+    keys: Vec<String>,
+    page_size: u32,
+    remaining: Option<u32>,
+}
+
+impl AllDocsPager<'_> {
+    async fn next_page(mut self) -> Option<(Result<AllDocsResponse, Error>, Option<Self>)> {
+        let limit = next_limit(self.page_size, self.remaining)?;
+        if !self.keys.is_empty() {
+            let rest = self.keys.split_off((limit as usize).min(self.keys.len()));
+            self.options.keys = std::mem::replace(&mut self.keys, rest);
+            self.remaining = self.remaining.map(|remaining| remaining - limit);
+            let response = self.db.all_docs(&self.options).await;
+            let more = response.is_ok() && !self.keys.is_empty();
+            return Some((response, Some(self).filter(|_| more)));
+        }
+
+        self.options.limit = Some(limit + 1);
+        let mut response = match self.db.all_docs(&self.options).await {
+            Ok(response) => response,
+            Err(err) => return Some((Err(err), None)),
+        };
+        let next = if response.rows.len() > limit as usize {
+            response.rows.pop()
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            self.options.startkey = Some(next.key);
+            self.options.skip = None;
+            self.remaining = self.remaining.map(|remaining| remaining - limit);
+            Some((Ok(response), Some(self)))
+        } else {
+            Some((Ok(response), None))
+        }
+    }
+}
+
+pub(crate) fn all_docs_pages(
+    db: &PouchDB,
+    mut options: AllDocsOptions,
+    page_size: NonZeroU32,
+) -> impl Stream<Item = Result<AllDocsResponse, Error>> + '_ {
+    if let (Some(startkey), Some(endkey)) = (&options.startkey, &options.endkey) {
+        // A range given in ascending order (e.g. by `set_prefix`) wouldn't return anything.
+        if options.descending && startkey < endkey {
+            std::mem::swap(&mut options.startkey, &mut options.endkey);
+        }
+    }
+    let pager = AllDocsPager {
+        db,
+        remaining: options.limit,
+        keys: std::mem::take(&mut options.keys),
+        options,
+        page_size: page_size.get(),
+    };
+    stream::unfold(Some(pager), |pager| async move { pager?.next_page().await })
+}
+
+struct QueryPager<'a> {
+    db: &'a PouchDB,
+    view: String,
+    options: QueryOptions,
+    keys: Vec<ViewKey>,
+    page_size: u32,
+    remaining: Option<u32>,
+}
+
+impl<'a> QueryPager<'a> {
+    async fn next_page<K, V>(
+        mut self,
+    ) -> Option<(Result<QueryResponse<K, V>, Error>, Option<QueryPager<'a>>)>
+    where
+        K: Serialize + for<'de> Deserialize<'de>,
+        V: for<'de> Deserialize<'de>,
+    {
+        let limit = next_limit(self.page_size, self.remaining)?;
+        if !self.keys.is_empty() {
+            let rest = self.keys.split_off((limit as usize).min(self.keys.len()));
+            self.options.keys = std::mem::replace(&mut self.keys, rest);
+            self.remaining = self.remaining.map(|remaining| remaining - limit);
+            let response = self.db.query_view(&self.view, self.options.clone()).await;
+            let more = response.is_ok() && !self.keys.is_empty();
+            return Some((response, Some(self).filter(|_| more)));
+        }
+
+        self.options.limit = Some(limit + 1);
+        let mut response = match self.db.query_view(&self.view, self.options.clone()).await {
+            Ok(response) => response,
+            Err(err) => return Some((Err(err), None)),
+        };
+        let next = if response.rows.len() > limit as usize {
+            response.rows.pop()
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            match serde_json::to_value(&next.key) {
+                Ok(key) => self.options.startkey = Some(ViewKey(key)),
+                Err(err) => return Some((Err(err.into()), None)),
+            }
+            self.options.startkey_docid = Some(next.id);
+            self.options.skip = None;
+            self.remaining = self.remaining.map(|remaining| remaining - limit);
+            Some((Ok(response), Some(self)))
+        } else {
+            Some((Ok(response), None))
+        }
+    }
+}
+
+pub(crate) fn query_pages<'a, K, V>(
+    db: &'a PouchDB,
+    view: &str,
+    mut options: QueryOptions,
+    page_size: NonZeroU32,
+) -> impl Stream<Item = Result<QueryResponse<K, V>, Error>> + 'a
+where
+    K: Serialize + for<'de> Deserialize<'de> + 'a,
+    V: for<'de> Deserialize<'de> + 'a,
+{
+    if let Some(key) = options.key.take() {
+        options.startkey = Some(key.clone());
+        options.endkey = Some(key);
+    }
+    if let (Some(startkey), Some(endkey)) = (&options.startkey, &options.endkey) {
+        // Like in `all_docs_pages`, but view keys are ordered by collation.
+        if options.descending && collate(&startkey.0, &endkey.0) == Ordering::Less {
+            std::mem::swap(&mut options.startkey, &mut options.endkey);
+        }
+    }
+    options.reduce = false;
+    let pager = QueryPager {
+        db,
+        view: view.to_owned(),
+        keys: std::mem::take(&mut options.keys),
+        remaining: options.limit,
+        options,
+        page_size: page_size.get(),
+    };
+    stream::unfold(Some(pager), |pager| async move { pager?.next_page().await })
+}
@@ -4,6 +4,7 @@ use wasm_bindgen::prelude::*;
 #[wasm_bindgen(module = "pouchdb")]
 extern "C" {
     #[wasm_bindgen(js_name = default)]
+    #[derive(Clone)]
     pub type PouchDB;
 
     #[wasm_bindgen(constructor, js_class = default)]
@@ -65,6 +66,20 @@ extern "C" {
     #[wasm_bindgen(method, js_class = default, js_name = changes)]
     pub fn changes_oneshot(this: &PouchDB, options: JsValue) -> Promise;
 
+    #[wasm_bindgen(catch, static_method_of = PouchDB, js_class = default)]
+    pub fn defaults(options: JsValue) -> Result<Function, JsValue>;
+
+    #[wasm_bindgen(static_method_of = PouchDB, js_class = default)]
+    pub fn fetch(url: &str, options: &JsValue) -> Promise;
+
+    // Sends a request with the `fetch` and the credentials of a remote database. Paths
+    // starting with `/` are relative to the server.
+    #[wasm_bindgen(catch, method, js_class = default, js_name = fetch)]
+    pub fn fetch_path(this: &PouchDB, path: &str, options: &JsValue) -> Result<Promise, JsValue>;
+
+    #[wasm_bindgen(catch, static_method_of = PouchDB, js_class = default)]
+    pub fn plugin(plugin: &JsValue) -> Result<JsValue, JsValue>;
+
     #[wasm_bindgen(static_method_of = PouchDB, js_class = default)]
     pub fn replicate(source: &JsValue, target: &JsValue) -> JsValue;
 
@@ -129,6 +144,12 @@ extern "C" {
     #[wasm_bindgen(method, js_class = default, js_name = query)]
     pub fn query_with_options(this: &PouchDB, fun: &Function, options: JsValue) -> Promise;
 
+    #[wasm_bindgen(method, js_class = default, js_name = query)]
+    pub fn query_fun_with_options(this: &PouchDB, fun: &JsValue, options: JsValue) -> Promise;
+
+    #[wasm_bindgen(method, js_class = default, js_name = query)]
+    pub fn query_view_with_options(this: &PouchDB, view: &str, options: JsValue) -> Promise;
+
     #[wasm_bindgen(method, js_class = default, js_name = viewCleanup)]
     pub fn view_Cleanup(this: &PouchDB) -> Promise;
 
@@ -146,4 +167,35 @@ extern "C" {
 
     #[wasm_bindgen(method, js_class = default)]
     pub fn close(this: &PouchDB) -> Promise;
+
+    #[wasm_bindgen(method, js_class = default, js_name = revsDiff)]
+    pub fn revs_diff(this: &PouchDB, diff: JsValue) -> Promise;
+
+    #[wasm_bindgen(method, js_class = default)]
+    pub fn purge(this: &PouchDB, doc_id: JsValue, rev: JsValue) -> Promise;
+}
+
+#[cfg(feature = "pouchdb-find")]
+#[wasm_bindgen(module = "pouchdb-find")]
+extern "C" {
+    #[wasm_bindgen(thread_local_v2, js_name = default)]
+    pub static FIND_PLUGIN: JsValue;
+}
+
+#[cfg(feature = "pouchdb-adapter-memory")]
+#[wasm_bindgen(module = "pouchdb-adapter-memory")]
+extern "C" {
+    #[wasm_bindgen(thread_local_v2, js_name = default)]
+    pub static MEMORY_ADAPTER_PLUGIN: JsValue;
+}
+
+// PouchDB only passes `emit` to map functions declaring two parameters, which closures
+// created by wasm-bindgen don't.
+#[wasm_bindgen(inline_js = "
+export function map_function(map) {
+    return function (doc, emit) { map(doc, emit); };
+}
+")]
+extern "C" {
+    pub fn map_function(map: &JsValue) -> Function;
 }
@@ -0,0 +1,794 @@
+//! The CouchDB replication protocol, implemented in Rust.
+//!
+//! Unlike [PouchDB::replicate](crate::PouchDB::replicate), which uses the replicator of
+//! PouchDB, [Replicator] works with any [Database], e.g. to replicate between an
+//! [EmbeddedDatabase](crate::embedded::EmbeddedDatabase) and a
+//! [CouchDB](crate::couchdb::CouchDB) server. Replication ids and checkpoints are the same
+//! as PouchDB's, so a replication started by PouchDB can be resumed here and vice versa.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, HashMap},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::{
+    collate,
+    database::{Database, JsonDocument},
+    document::{self, Revision, LOCAL_PREFIX},
+    error::Error,
+    events::{
+        changes_event_emitter::ChangeEvent as Change, replication_event_emitter::ChangeEvent,
+        SequenceID,
+    },
+    options::{
+        changes::{Changes, Style},
+        fetch::FetchOptions,
+        replication::Replication,
+    },
+    rev_tree,
+};
+
+const CHECKPOINT_VERSION: u64 = 1;
+const REPLICATOR: &str = "pouchdb";
+/// The number of checkpoints of previous sessions kept in the replication log.
+const CHECKPOINT_HISTORY_SIZE: usize = 5;
+const DEFAULT_BATCH_SIZE: u32 = 100;
+const MAX_BACK_OFF: Duration = Duration::from_secs(600);
+
+/// The events of a replication, like the ones of
+/// [ReplicationEventEmitter](crate::events::replication_event_emitter::ReplicationEventEmitter).
+#[derive(Debug)]
+pub enum ReplicationEvent<'a> {
+    /// The replication started processing changes, or resumed after an error.
+    Active,
+    /// A batch of documents was written. Contains the progress so far and the documents
+    /// of the batch.
+    Change(&'a ChangeEvent<JsonDocument>),
+    /// The replication failed and will be retried after a back-off.
+    Paused(Option<&'a Error>),
+    /// A document couldn't be written because the target rejected it.
+    Denied { id: &'a str, error: &'a Error },
+    /// All changes were replicated.
+    Complete(&'a ChangeEvent<JsonDocument>),
+    /// The replication failed and won't be retried.
+    Error(&'a Error),
+}
+
+/// A one-shot replication from `source` to `target`.
+///
+/// Supports the [Replication] options `doc_ids`, `batch_size` and `since`. `filter`,
+/// `view`, `query_params` and `selector` are passed on to the changes feed of the source,
+/// so they only work if the source supports them.
+pub struct Replicator<'a, S: Database, T: Database> {
+    source: &'a S,
+    target: &'a T,
+    options: &'a Replication,
+    back_off: fn(Duration) -> Duration,
+}
+
+impl<'a, S: Database, T: Database> Replicator<'a, S, T> {
+    pub fn new(source: &'a S, target: &'a T, options: &'a Replication) -> Self {
+        Self {
+            source,
+            target,
+            options,
+            back_off: default_back_off,
+        }
+    }
+
+    /// Set the function that returns the delay before the next retry of
+    /// [run_with_retry](Self::run_with_retry), given the previous delay (which is zero
+    /// for the first retry). Defaults to [default_back_off].
+    pub fn back_off(self, back_off: fn(Duration) -> Duration) -> Self {
+        Self { back_off, ..self }
+    }
+
+    /// The id of the `_local` documents that keep the checkpoints of this replication.
+    pub async fn replication_id(&self) -> Result<String, Error> {
+        let source = self.source.id().await?;
+        let target = self.target.id().await?;
+        generate_replication_id(&source, &target, self.options)
+    }
+
+    /// Replicate all changes, starting at the last checkpoint.
+    ///
+    /// `listener` is called with the events of the replication. Returns the final
+    /// progress, which is also passed to [ReplicationEvent::Complete].
+    pub async fn run<F>(&self, mut listener: F) -> Result<ChangeEvent<JsonDocument>, Error>
+    where
+        F: FnMut(ReplicationEvent),
+    {
+        let result = self.replicate(&mut listener).await;
+        if let Err(err) = &result {
+            listener(ReplicationEvent::Error(err));
+        }
+        result
+    }
+
+    /// Like [run](Self::run), but retries after errors like PouchDB with `retry: true`.
+    ///
+    /// Before each retry, [ReplicationEvent::Paused] is emitted and `sleep` is awaited
+    /// with the delay from the [back_off](Self::back_off) function, which starts over
+    /// once an attempt has written a batch. Errors with
+    /// authorization ([Error::Unauthorized]) or unsupported options
+    /// ([Error::Unsupported]) aren't retried.
+    pub async fn run_with_retry<F, Sleep, Fut>(
+        &self,
+        sleep: Sleep,
+        mut listener: F,
+    ) -> Result<ChangeEvent<JsonDocument>, Error>
+    where
+        F: FnMut(ReplicationEvent),
+        Sleep: Fn(Duration) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut delay = Duration::from_secs(0);
+        loop {
+            let mut written = false;
+            let mut on_event = |event: ReplicationEvent| {
+                written |= matches!(event, ReplicationEvent::Change(_));
+                listener(event);
+            };
+            let result = self.replicate(&mut on_event).await;
+            if written {
+                delay = Duration::from_secs(0);
+            }
+            match result {
+                Ok(result) => return Ok(result),
+                Err(err @ (Error::Unauthorized(_) | Error::Unsupported(_))) => {
+                    listener(ReplicationEvent::Error(&err));
+                    return Err(err);
+                }
+                Err(err) => {
+                    listener(ReplicationEvent::Paused(Some(&err)));
+                    delay = (self.back_off)(delay);
+                    sleep(delay).await;
+                }
+            }
+        }
+    }
+
+    async fn replicate<F>(&self, listener: &mut F) -> Result<ChangeEvent<JsonDocument>, Error>
+    where
+        F: FnMut(ReplicationEvent),
+    {
+        let id = self.replication_id().await?;
+        let session = document::generate_id(&id);
+        let since = match &self.options.since {
+            Some(since) => since.clone(),
+            None => self.checkpoint(&id).await?,
+        };
+        let mut result = ChangeEvent {
+            doc_write_failures: 0,
+            docs_read: 0,
+            docs_written: 0,
+            errors: Vec::new(),
+            last_seq: since,
+            ok: true,
+            start_time: now(),
+            docs: Vec::new(),
+        };
+        let batch_size = self.options.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        let mut write_source = true;
+        listener(ReplicationEvent::Active);
+
+        loop {
+            let options = Changes {
+                since: Some(result.last_seq.clone()),
+                limit: Some(batch_size),
+                style: Style::AllDocs,
+                filter: self.options.filter.clone(),
+                doc_ids: self.options.doc_ids.clone(),
+                query_params: self.options.query_params.clone(),
+                view: self.options.view.clone(),
+                selector: self.options.selector.clone(),
+                ..Changes::default()
+            };
+            let (changes, last_seq) = self.source.changes(&options).await?;
+            if changes.is_empty() {
+                break;
+            }
+            result.docs = self
+                .replicate_batch(&changes, &mut result, listener)
+                .await?;
+            result.last_seq = last_seq;
+            if !result.docs.is_empty() {
+                listener(ReplicationEvent::Change(&result));
+            }
+            self.write_checkpoint(&id, &result.last_seq, &session, &mut write_source)
+                .await?;
+            if changes.len() < batch_size as usize {
+                break;
+            }
+        }
+
+        result.docs = Vec::new();
+        listener(ReplicationEvent::Complete(&result));
+        Ok(result)
+    }
+
+    /// Copies the revisions of `changes` missing in the target and returns the written
+    /// documents.
+    async fn replicate_batch<F>(
+        &self,
+        changes: &[Change<JsonDocument>],
+        result: &mut ChangeEvent<JsonDocument>,
+        listener: &mut F,
+    ) -> Result<Vec<JsonDocument>, Error>
+    where
+        F: FnMut(ReplicationEvent),
+    {
+        let mut revs: HashMap<String, Vec<Revision>> = HashMap::new();
+        for change in changes {
+            revs.entry(change.id.clone())
+                .or_default()
+                .extend(change.changes.iter().cloned());
+        }
+        let mut diff = self.target.revs_diff(revs).await?;
+        let missing: Vec<(String, Revision)> = changes
+            .iter()
+            .filter_map(|change| Some((change.id.clone(), diff.remove(&change.id)?)))
+            .flat_map(|(id, entry)| entry.missing.into_iter().map(move |rev| (id.clone(), rev)))
+            .collect();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut docs = Vec::new();
+        for doc in self.source.bulk_get(&missing).await? {
+            match doc {
+                Ok(doc) => docs.push(doc),
+                // The revision was compacted since the change was read.
+                Err(Error::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        result.docs_read += docs.len() as u32;
+
+        let results = self.target.bulk_docs_replicated(&docs).await?;
+        let mut written = Vec::new();
+        for (doc, write) in docs.into_iter().zip(results) {
+            match write {
+                Ok(()) => {
+                    result.docs_written += 1;
+                    written.push(doc);
+                }
+                Err(err) => {
+                    result.doc_write_failures += 1;
+                    result.errors.push(err.to_string());
+                    match err {
+                        Error::Unauthorized(_) => listener(ReplicationEvent::Denied {
+                            id: &doc.id,
+                            error: &err,
+                        }),
+                        err => return Err(err),
+                    }
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    /// The sequence to start at, from the replication logs of source and target.
+    async fn checkpoint(&self, id: &str) -> Result<SequenceID, Error> {
+        let target = match read_log(self.target, id).await? {
+            Some(target) => target,
+            None => return Ok(SequenceID::from(0)),
+        };
+        let source = match read_log(self.source, id).await? {
+            Some(source) => source,
+            None => return Ok(SequenceID::from(0)),
+        };
+        Ok(match (source.version, target.version) {
+            (Some(CHECKPOINT_VERSION), Some(CHECKPOINT_VERSION)) => {
+                compare_replication_logs(&source, &target)
+            }
+            // The format written by PouchDB before the version was added.
+            (None, None) => match (source.last_seq, target.last_seq) {
+                (Some(source), Some(target))
+                    if collate::collate(&source.0, &target.0) == Ordering::Equal =>
+                {
+                    source
+                }
+                _ => SequenceID::from(0),
+            },
+            _ => SequenceID::from(0),
+        })
+    }
+
+    /// Writes the checkpoint to the target, and to the source unless it doesn't allow it.
+    async fn write_checkpoint(
+        &self,
+        id: &str,
+        seq: &SequenceID,
+        session: &str,
+        write_source: &mut bool,
+    ) -> Result<(), Error> {
+        update_log(self.target, id, seq, session).await?;
+        if *write_source {
+            match update_log(self.source, id, seq, session).await {
+                Err(Error::Unauthorized(_)) => *write_source = false,
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The back-off of PouchDB: a random delay of up to 2 seconds for the first retry, then
+/// between the previous delay and twice the previous delay, at most 10 minutes.
+pub fn default_back_off(previous: Duration) -> Duration {
+    let previous = previous.as_millis() as u64;
+    let (min, max) = match previous {
+        0 => (0, 2001),
+        previous if previous * 2 > MAX_BACK_OFF.as_millis() as u64 => {
+            let max = MAX_BACK_OFF.as_millis() as u64;
+            (max / 2, max)
+        }
+        previous => (previous, previous * 2),
+    };
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(min + random % (max - min))
+}
+
+/// The replication id PouchDB uses for replicating from the database with id `source` to
+/// the one with id `target`.
+fn generate_replication_id(
+    source: &str,
+    target: &str,
+    options: &Replication,
+) -> Result<String, Error> {
+    let mut doc_ids: Vec<&str> = options.doc_ids.iter().map(String::as_str).collect();
+    doc_ids.sort_by(|a, b| collate::string_collate(a, b));
+    let filter = options.filter.as_deref().unwrap_or_default();
+    let view = match &options.view {
+        Some(view) if filter == "_view" => view.as_str(),
+        _ => "",
+    };
+    let query_params = match &options.query_params {
+        Some(query_params) if options.filter.is_some() => {
+            let query_params: Value = query_params.into_serde()?;
+            let sorted = match query_params {
+                Value::Object(params) => {
+                    let mut keys: Vec<&String> = params.keys().collect();
+                    keys.sort_by(|a, b| collate::string_collate(a, b));
+                    let sorted: Map<String, Value> = keys
+                        .into_iter()
+                        .map(|key| (key.clone(), params[key].clone()))
+                        .collect();
+                    Value::Object(sorted)
+                }
+                params => params,
+            };
+            rev_tree::js_stringify(&sorted)
+        }
+        _ => String::new(),
+    };
+    let selector = match &options.selector {
+        Some(selector) => rev_tree::js_stringify(&serde_json::to_value(selector)?),
+        None => String::new(),
+    };
+    let data = [
+        source,
+        target,
+        filter,
+        view,
+        &query_params,
+        &doc_ids.join(","),
+        &selector,
+    ]
+    .concat();
+
+    // PouchDB hashes the string as a "binary" string, i.e. only the low byte of every
+    // UTF-16 code unit.
+    let bytes: Vec<u8> = data.encode_utf16().map(|unit| unit as u8).collect();
+    let digest = BASE64_STANDARD.encode(Md5::digest(&bytes));
+    Ok(format!(
+        "{}{}",
+        LOCAL_PREFIX,
+        digest.replace('/', ".").replace('+', "_")
+    ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Option<std::time::Instant> {
+    Some(std::time::Instant::now())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> Option<std::time::Instant> {
+    None
+}
+
+/// A checkpoint of a previous replication session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HistoryEntry {
+    last_seq: SequenceID,
+    session_id: String,
+}
+
+/// The replication log, as stored in the `_local` document of the replication id.
+#[derive(Debug, Default, Deserialize)]
+struct ReplicationLog {
+    #[serde(default)]
+    last_seq: Option<SequenceID>,
+    #[serde(default)]
+    session_id: Option<String>,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+    #[serde(default)]
+    version: Option<u64>,
+}
+
+async fn read_log<D: Database>(db: &D, id: &str) -> Result<Option<ReplicationLog>, Error> {
+    match db.fetch(id, &FetchOptions::default()).await {
+        Ok(doc) => Ok(Some(doc.deserialize()?)),
+        Err(Error::NotFound(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Adds the checkpoint `seq` of `session` to the replication log `id`, like PouchDB does.
+async fn update_log<D: Database>(
+    db: &D,
+    id: &str,
+    seq: &SequenceID,
+    session: &str,
+) -> Result<(), Error> {
+    loop {
+        let mut doc = match db.fetch(id, &FetchOptions::default()).await {
+            Ok(doc) => doc,
+            Err(Error::NotFound(_)) => JsonDocument::new(
+                id,
+                json!({
+                    "session_id": session,
+                    "history": [],
+                    "replicator": REPLICATOR,
+                    "version": CHECKPOINT_VERSION,
+                }),
+            ),
+            Err(err) => return Err(err),
+        };
+        if doc.data["last_seq"] == seq.0 {
+            return Ok(());
+        }
+        let mut history: Vec<HistoryEntry> =
+            serde_json::from_value(doc.data["history"].take()).unwrap_or_default();
+        history.retain(|entry| entry.session_id != session);
+        history.insert(
+            0,
+            HistoryEntry {
+                last_seq: seq.clone(),
+                session_id: session.to_owned(),
+            },
+        );
+        history.truncate(CHECKPOINT_HISTORY_SIZE);
+        if let Some(data) = doc.data.as_object_mut() {
+            data.insert("history".to_owned(), serde_json::to_value(history)?);
+            data.insert("version".to_owned(), CHECKPOINT_VERSION.into());
+            data.insert("replicator".to_owned(), REPLICATOR.into());
+            data.insert("session_id".to_owned(), session.into());
+            data.insert("last_seq".to_owned(), seq.0.clone());
+        }
+        match db.put(&doc).await {
+            // Someone else wrote a checkpoint at the same time.
+            Err(Error::Conflict(_)) => continue,
+            result => return result.map(drop),
+        }
+    }
+}
+
+/// The sequence both logs agree on, like CouchDB's `compare_replication_logs`.
+fn compare_replication_logs(source: &ReplicationLog, target: &ReplicationLog) -> SequenceID {
+    if source.session_id == target.session_id {
+        return source
+            .last_seq
+            .clone()
+            .unwrap_or_else(|| SequenceID::from(0));
+    }
+    compare_replication_history(&source.history, &target.history)
+}
+
+fn compare_replication_history(source: &[HistoryEntry], target: &[HistoryEntry]) -> SequenceID {
+    let has_session = |session: &str, history: &[HistoryEntry]| {
+        !session.is_empty() && history.iter().any(|entry| entry.session_id == session)
+    };
+    for i in 0..source.len().min(target.len()) {
+        if has_session(&source[i].session_id, &target[i..]) {
+            return source[i].last_seq.clone();
+        }
+        if has_session(&target[i].session_id, &source[i + 1..]) {
+            return target[i].last_seq.clone();
+        }
+    }
+    SequenceID::from(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::MemoryDatabase, options::selector::Selector};
+    use futures::executor::block_on;
+    use std::cell::Cell;
+
+    #[test]
+    fn replication_ids_match_pouchdb() {
+        // Computed with the replication id algorithm of PouchDB in node.
+        let options = Replication::default();
+        assert_eq!(
+            generate_replication_id("source-id", "target-id", &options).unwrap(),
+            "_local/VhViJx.JJQlXm2BL8Ax_WA=="
+        );
+        let options = Replication {
+            doc_ids: vec!["b".into(), "€".into(), "a".into(), "é".into()],
+            selector: Some(Selector::default().field("type", "user")),
+            ..Replication::default()
+        };
+        assert_eq!(
+            generate_replication_id("source-id", "target-id", &options).unwrap(),
+            "_local/rEpr3EBwbd5Q7jUWQWerEg=="
+        );
+    }
+
+    #[test]
+    fn replication_history_is_compared_like_couchdb() {
+        let entry = |session: &str, seq: u64| HistoryEntry {
+            last_seq: SequenceID::from(seq),
+            session_id: session.to_owned(),
+        };
+        let log = |session: &str, history: Vec<HistoryEntry>| ReplicationLog {
+            last_seq: Some(history[0].last_seq.clone()),
+            session_id: Some(session.to_owned()),
+            history,
+            version: Some(CHECKPOINT_VERSION),
+        };
+        let source = log("c", vec![entry("c", 30), entry("b", 20), entry("a", 10)]);
+        let target = log("c", vec![entry("c", 30), entry("b", 20), entry("a", 10)]);
+        assert_eq!(
+            compare_replication_logs(&source, &target),
+            SequenceID::from(30)
+        );
+        // The source checkpoint of session c was never written to the target.
+        let target = log("b", vec![entry("b", 20), entry("a", 10)]);
+        assert_eq!(
+            compare_replication_logs(&source, &target),
+            SequenceID::from(20)
+        );
+        let target = log("d", vec![entry("d", 40), entry("a", 10)]);
+        assert_eq!(
+            compare_replication_logs(&source, &target),
+            SequenceID::from(10)
+        );
+        let target = log("e", vec![entry("e", 50)]);
+        assert_eq!(
+            compare_replication_logs(&source, &target),
+            SequenceID::from(0)
+        );
+    }
+
+    #[test]
+    fn replicates_conflicts_and_resumes_from_checkpoints() {
+        let source = MemoryDatabase::new("source");
+        let target = MemoryDatabase::new("target");
+        let options = Replication {
+            batch_size: Some(2),
+            ..Replication::default()
+        };
+        let replicator = Replicator::new(&source, &target, &options);
+        block_on(async {
+            let base = source
+                .put(&JsonDocument::new("a", json!({"n": 0})))
+                .await
+                .unwrap()
+                .rev;
+            for id in &["b", "c"] {
+                source
+                    .put(&JsonDocument::new(*id, json!({})))
+                    .await
+                    .unwrap();
+            }
+            let mut events = Vec::new();
+            let result = replicator
+                .run(|event| {
+                    events.push(match event {
+                        ReplicationEvent::Active => "active".to_owned(),
+                        ReplicationEvent::Change(change) => format!("change {}", change.docs.len()),
+                        ReplicationEvent::Complete(_) => "complete".to_owned(),
+                        event => format!("{:?}", event),
+                    })
+                })
+                .await
+                .unwrap();
+            assert_eq!(events, vec!["active", "change 2", "change 1", "complete"]);
+            assert_eq!(result.docs_written, 3);
+            assert_eq!(result.last_seq, SequenceID::from(3));
+
+            // Edit both sides, so "a" is in conflict after the next replication.
+            let doc = JsonDocument::new("a", json!({"n": 1})).rev(base.clone());
+            target.put(&doc).await.unwrap();
+            let doc = JsonDocument::new("a", json!({"n": 2})).rev(base);
+            source.put(&doc).await.unwrap();
+            let b = source.fetch("b", &FetchOptions::default()).await.unwrap();
+            source.remove("b", &b.rev.unwrap()).await.unwrap();
+
+            let result = replicator.run(|_| {}).await.unwrap();
+            // Only the changes after the checkpoint are read.
+            assert_eq!(result.docs_read, 2);
+            let options = FetchOptions::default().conflicts(Vec::<String>::new());
+            let a = target.fetch("a", &options).await.unwrap();
+            assert_eq!(a.conflicts.len(), 1);
+            // Both sides pick the same winner once the source has the conflict, too.
+            Replicator::new(&target, &source, &Replication::default())
+                .run(|_| {})
+                .await
+                .unwrap();
+            assert_eq!(source.fetch("a", &options).await.unwrap(), a);
+            assert!(matches!(
+                target.fetch("b", &FetchOptions::default()).await,
+                Err(Error::NotFound(_))
+            ));
+
+            // The replication log has the format of PouchDB.
+            let id = replicator.replication_id().await.unwrap();
+            let log = target.fetch(&id, &FetchOptions::default()).await.unwrap();
+            let session = log.data["session_id"].clone();
+            assert_eq!(
+                log.data,
+                json!({
+                    "session_id": session,
+                    "history": [
+                        {"last_seq": 5, "session_id": session},
+                        {"last_seq": 3, "session_id": log.data["history"][1]["session_id"]},
+                    ],
+                    "replicator": "pouchdb",
+                    "version": 1,
+                    "last_seq": 5,
+                })
+            );
+            assert_eq!(
+                source
+                    .fetch(&id, &FetchOptions::default())
+                    .await
+                    .unwrap()
+                    .data,
+                log.data
+            );
+        });
+    }
+
+    /// Fails the first write of replicated documents.
+    struct Flaky {
+        db: MemoryDatabase,
+        /// The calls of `bulk_docs_replicated` so far.
+        calls: Cell<usize>,
+        /// The calls of `bulk_docs_replicated` that fail.
+        failing: &'static [usize],
+    }
+
+    impl Database for Flaky {
+        async fn info(&self) -> Result<crate::responses::DatabaseInfo, Error> {
+            self.db.info().await
+        }
+        async fn put(&self, doc: &JsonDocument) -> Result<crate::responses::ChangeResponse, Error> {
+            self.db.put(doc).await
+        }
+        async fn post(
+            &self,
+            doc: &JsonDocument,
+        ) -> Result<crate::responses::ChangeResponse, Error> {
+            self.db.post(doc).await
+        }
+        async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+            self.db.fetch(doc_id, options).await
+        }
+        async fn remove(
+            &self,
+            doc_id: &str,
+            rev: &Revision,
+        ) -> Result<crate::responses::ChangeResponse, Error> {
+            self.db.remove(doc_id, rev).await
+        }
+        async fn bulk_docs(
+            &self,
+            docs: &[JsonDocument],
+        ) -> Result<Vec<Result<crate::responses::ChangeResponse, Error>>, Error> {
+            self.db.bulk_docs(docs).await
+        }
+        async fn all_docs(
+            &self,
+            options: &crate::options::all_docs::AllDocsOptions,
+        ) -> Result<crate::responses::AllDocsResponse<JsonDocument>, Error> {
+            self.db.all_docs(options).await
+        }
+        async fn changes(
+            &self,
+            options: &Changes,
+        ) -> Result<(Vec<Change<JsonDocument>>, SequenceID), Error> {
+            self.db.changes(options).await
+        }
+        async fn id(&self) -> Result<String, Error> {
+            self.db.id().await
+        }
+        async fn revs_diff(
+            &self,
+            revs: HashMap<String, Vec<Revision>>,
+        ) -> Result<HashMap<String, crate::responses::RevsDiffEntry>, Error> {
+            self.db.revs_diff(revs).await
+        }
+        async fn bulk_get(
+            &self,
+            docs: &[(String, Revision)],
+        ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+            self.db.bulk_get(docs).await
+        }
+        async fn bulk_docs_replicated(
+            &self,
+            docs: &[JsonDocument],
+        ) -> Result<Vec<Result<(), Error>>, Error> {
+            let call = self.calls.replace(self.calls.get() + 1);
+            if self.failing.contains(&call) {
+                return Err(Error::Status(503, "unavailable".to_owned()));
+            }
+            self.db.bulk_docs_replicated(docs).await
+        }
+    }
+
+    #[test]
+    fn failed_replications_are_retried() {
+        let source = MemoryDatabase::new("source");
+        let target = Flaky {
+            db: MemoryDatabase::new("target"),
+            calls: Cell::new(0),
+            // The second failure comes after a batch was written.
+            failing: &[0, 2],
+        };
+        let options = Replication {
+            batch_size: Some(1),
+            ..Replication::default()
+        };
+        let delays = std::cell::RefCell::new(Vec::new());
+        let replicator = Replicator::new(&source, &target, &options)
+            .back_off(|delay| delay + Duration::from_millis(5));
+        block_on(async {
+            for id in &["a", "b"] {
+                source
+                    .put(&JsonDocument::new(*id, json!({})))
+                    .await
+                    .unwrap();
+            }
+            let mut paused = 0;
+            let result = replicator
+                .run_with_retry(
+                    |delay| {
+                        delays.borrow_mut().push(delay);
+                        futures::future::ready(())
+                    },
+                    |event| {
+                        if let ReplicationEvent::Paused(Some(Error::Status(503, _))) = event {
+                            paused += 1;
+                        }
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(result.docs_written, 1);
+            assert_eq!(paused, 2);
+        });
+        assert_eq!(delays.into_inner(), vec![Duration::from_millis(5); 2]);
+
+        for _ in 0..100 {
+            let delay = default_back_off(Duration::from_secs(0));
+            assert!(delay <= Duration::from_millis(2000));
+            let delay = default_back_off(Duration::from_secs(1));
+            assert!(delay >= Duration::from_secs(1) && delay < Duration::from_secs(2));
+            assert!(default_back_off(MAX_BACK_OFF) <= MAX_BACK_OFF);
+        }
+    }
+}
@@ -1,15 +1,88 @@
-use crate::document::Revision;
-use js_sys::Reflect;
+use crate::document::{Revision, SerializedDocument};
+use crate::events::SequenceID;
+use js_sys::{Array, Reflect};
 use serde::Deserialize;
 use std::convert::TryFrom;
-use wasm_bindgen::JsValue;
+use wasm_bindgen::{JsCast, JsValue};
 
 #[derive(Deserialize, Debug)]
 pub struct DestroyResponse {
     pub ok: bool,
 }
 
+/// The result of [PouchDB::info](crate::PouchDB::info).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DatabaseInfo {
+    pub db_name: String,
+    /// The number of non-deleted documents.
+    pub doc_count: u64,
+    pub update_seq: SequenceID,
+}
+
+/// The result of [PouchDB::purge](crate::PouchDB::purge).
 #[derive(Debug)]
+pub struct PurgeResponse {
+    pub ok: bool,
+    /// The purged revisions: the given leaf and all of its ancestors that weren't shared
+    /// with other branches.
+    pub deleted_revs: Vec<Revision>,
+    /// True if no revisions of the document are left.
+    pub document_was_removed_completely: bool,
+}
+
+impl TryFrom<JsValue> for PurgeResponse {
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let deleted_revs = get(&value, "deletedRevs")?;
+        Ok(Self {
+            ok: get(&value, "ok")?.is_truthy(),
+            deleted_revs: if Array::is_array(&deleted_revs) {
+                Array::from(&deleted_revs)
+                    .iter()
+                    .filter_map(Revision::from_js)
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            document_was_removed_completely: get(&value, "documentWasRemovedCompletely")?
+                .is_truthy(),
+        })
+    }
+}
+
+/// An entry of the result of [PouchDB::revs_diff](crate::PouchDB::revs_diff).
+#[derive(Deserialize, Debug, Default)]
+pub struct RevsDiffEntry {
+    /// The given revisions the database doesn't have.
+    #[serde(default)]
+    pub missing: Vec<Revision>,
+    /// Revisions the database has that may be ancestors of the missing revisions.
+    #[serde(default)]
+    pub possible_ancestors: Vec<Revision>,
+}
+
+impl TryFrom<JsValue> for RevsDiffEntry {
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let revisions = |key| -> Result<Vec<Revision>, Self::Error> {
+            let revs = get(&value, key)?;
+            Ok(if Array::is_array(&revs) {
+                Array::from(&revs)
+                    .iter()
+                    .filter_map(Revision::from_js)
+                    .collect()
+            } else {
+                Vec::new()
+            })
+        };
+        Ok(Self {
+            missing: revisions("missing")?,
+            possible_ancestors: revisions("possible_ancestors")?,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
 pub struct ChangeResponse {
     pub ok: bool,
     pub id: String,
@@ -23,17 +96,312 @@ impl TryFrom<JsValue> for ChangeResponse {
         let ok = Reflect::get(&value, &JsValue::from_str("ok"))?.is_truthy();
         let rev = Reflect::get(&value, &JsValue::from_str("rev"))?;
 
-        if let Some(id) = id.as_string() {
-            if !rev.is_undefined() {
-                return Ok(Self {
-                    ok,
-                    id,
-                    rev: Revision(rev),
-                });
-            }
+        if let (Some(id), Some(rev)) = (id.as_string(), Revision::from_js(rev)) {
+            return Ok(Self { ok, id, rev });
         }
         Err(crate::error::Error::Js(JsValue::from_str(
             "Response did not contain the required elements.",
         )))
     }
 }
+
+fn get(value: &JsValue, key: &str) -> Result<JsValue, crate::error::Error> {
+    Ok(Reflect::get(value, &JsValue::from_str(key))?)
+}
+
+fn get_count(value: &JsValue, key: &str) -> Result<u64, crate::error::Error> {
+    Ok(get(value, key)?.as_f64().unwrap_or(0.0) as u64)
+}
+
+fn get_update_seq(value: &JsValue) -> Result<Option<SequenceID>, crate::error::Error> {
+    Some(get(value, "update_seq")?)
+        .filter(|seq| !seq.is_undefined())
+        .map(SequenceID::from_js)
+        .transpose()
+}
+
+/// A row emitted by a map function.
+#[derive(Debug)]
+pub struct Row<K, V> {
+    /// The id of the document that emitted this row.
+    pub id: String,
+    pub key: K,
+    pub value: V,
+    /// The emitting document, if `include_docs` was set.
+    pub doc: Option<SerializedDocument>,
+}
+
+/// The result of a view query without reduce.
+#[derive(Debug)]
+pub struct QueryResponse<K, V> {
+    /// The total number of rows in the view (before applying `limit`, `skip` and key ranges).
+    pub total_rows: u64,
+    /// The offset of the first returned row in the view.
+    pub offset: u64,
+    /// The sequence id the view reflects, if `update_seq` was set.
+    pub update_seq: Option<SequenceID>,
+    pub rows: Vec<Row<K, V>>,
+}
+
+impl<K, V> TryFrom<JsValue> for QueryResponse<K, V>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let rows: Array = get(&value, "rows")?.dyn_into()?;
+        let rows = rows
+            .iter()
+            .filter(|row| !Reflect::has(row, &JsValue::from_str("error")).unwrap_or(true))
+            .map(|row| {
+                let id = get(&row, "id")?.as_string().ok_or_else(|| {
+                    JsValue::from_str(
+                        "Row does not have an id. Reduced views must be queried with `reduce` set to false.",
+                    )
+                })?;
+                let doc = Some(get(&row, "doc")?)
+                    .filter(|doc| doc.is_object())
+                    .map(SerializedDocument::try_from)
+                    .transpose()?;
+                Ok(Row {
+                    id,
+                    key: get(&row, "key")?.into_serde()?,
+                    value: get(&row, "value")?.into_serde()?,
+                    doc,
+                })
+            })
+            .collect::<Result<_, Self::Error>>()?;
+
+        Ok(Self {
+            total_rows: get_count(&value, "total_rows")?,
+            offset: get_count(&value, "offset")?,
+            update_seq: get_update_seq(&value)?,
+            rows,
+        })
+    }
+}
+
+/// A row returned by a reduce function. When grouping, `key` is the group key;
+/// otherwise it's `null`.
+#[derive(Debug)]
+pub struct ReducedRow<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+/// The result of a view query with reduce, optionally grouped by `group` or `group_level`.
+#[derive(Debug)]
+pub struct ReduceResponse<K, V> {
+    pub update_seq: Option<SequenceID>,
+    pub rows: Vec<ReducedRow<K, V>>,
+}
+
+impl<K, V> TryFrom<JsValue> for ReduceResponse<K, V>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let rows: Array = get(&value, "rows")?.dyn_into()?;
+        let rows = rows
+            .iter()
+            .map(|row| {
+                Ok(ReducedRow {
+                    key: get(&row, "key")?.into_serde()?,
+                    value: get(&row, "value")?.into_serde()?,
+                })
+            })
+            .collect::<Result<_, Self::Error>>()?;
+
+        Ok(Self {
+            update_seq: get_update_seq(&value)?,
+            rows,
+        })
+    }
+}
+
+/// The value of a row returned by [PouchDB::all_docs](crate::PouchDB::all_docs).
+#[derive(Debug)]
+pub enum AllDocsValue<D = SerializedDocument> {
+    /// The document exists. `doc` is only set if `include_docs` was set.
+    Found {
+        id: String,
+        rev: Revision,
+        doc: Option<D>,
+    },
+    /// The document was deleted. Only returned for explicitly requested `keys`.
+    Deleted { id: String, rev: Revision },
+    /// No document with this id exists. Only returned for explicitly requested `keys`.
+    NotFound,
+    /// Any other error reported for this row.
+    Error(String),
+}
+
+#[derive(Debug)]
+pub struct AllDocsRow<D = SerializedDocument> {
+    /// The requested key, i.e. the id of the document.
+    pub key: String,
+    pub value: AllDocsValue<D>,
+}
+
+impl TryFrom<JsValue> for AllDocsRow {
+    type Error = crate::error::Error;
+    fn try_from(row: JsValue) -> Result<Self, Self::Error> {
+        let key = get(&row, "key")?.as_string().unwrap_or_default();
+        let error = get(&row, "error")?;
+        if !error.is_undefined() {
+            let error = error.as_string().unwrap_or_default();
+            return Ok(Self {
+                key,
+                value: if error == "not_found" {
+                    AllDocsValue::NotFound
+                } else {
+                    AllDocsValue::Error(error)
+                },
+            });
+        }
+
+        let id = get(&row, "id")?
+            .as_string()
+            .ok_or_else(|| JsValue::from_str("Row does not have an id."))?;
+        let value = get(&row, "value")?;
+        let rev = Revision::from_js(get(&value, "rev")?)
+            .ok_or_else(|| JsValue::from_str("Row does not have a revision."))?;
+        let value = if get(&value, "deleted")?.is_truthy() {
+            AllDocsValue::Deleted { id, rev }
+        } else {
+            AllDocsValue::Found {
+                id,
+                rev,
+                doc: Some(get(&row, "doc")?)
+                    .filter(|doc| doc.is_object())
+                    .map(SerializedDocument::try_from)
+                    .transpose()?,
+            }
+        };
+        Ok(Self { key, value })
+    }
+}
+
+/// The result of [PouchDB::all_docs](crate::PouchDB::all_docs).
+#[derive(Debug)]
+pub struct AllDocsResponse<D = SerializedDocument> {
+    /// The total number of non-deleted documents in the database.
+    pub total_rows: u64,
+    /// The offset of the first returned row (equal to `skip`).
+    pub offset: u64,
+    /// The current sequence id of the database, if `update_seq` was set.
+    pub update_seq: Option<SequenceID>,
+    pub rows: Vec<AllDocsRow<D>>,
+}
+
+impl<D> AllDocsResponse<D> {
+    /// Convert the included documents.
+    pub(crate) fn try_map_docs<E, F>(
+        self,
+        mut f: F,
+    ) -> Result<AllDocsResponse<E>, crate::error::Error>
+    where
+        F: FnMut(D) -> Result<E, crate::error::Error>,
+    {
+        let rows = self
+            .rows
+            .into_iter()
+            .map(|row| {
+                let value = match row.value {
+                    AllDocsValue::Found { id, rev, doc } => AllDocsValue::Found {
+                        id,
+                        rev,
+                        doc: doc.map(&mut f).transpose()?,
+                    },
+                    AllDocsValue::Deleted { id, rev } => AllDocsValue::Deleted { id, rev },
+                    AllDocsValue::NotFound => AllDocsValue::NotFound,
+                    AllDocsValue::Error(error) => AllDocsValue::Error(error),
+                };
+                Ok(AllDocsRow {
+                    key: row.key,
+                    value,
+                })
+            })
+            .collect::<Result<_, crate::error::Error>>()?;
+        Ok(AllDocsResponse {
+            total_rows: self.total_rows,
+            offset: self.offset,
+            update_seq: self.update_seq,
+            rows,
+        })
+    }
+}
+
+impl TryFrom<JsValue> for AllDocsResponse {
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let rows: Array = get(&value, "rows")?.dyn_into()?;
+
+        Ok(Self {
+            total_rows: get_count(&value, "total_rows")?,
+            offset: get_count(&value, "offset")?,
+            update_seq: get_update_seq(&value)?,
+            rows: rows
+                .iter()
+                .map(AllDocsRow::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// The result of a Mango query.
+#[derive(Debug)]
+pub struct FindResponse<D = SerializedDocument> {
+    pub docs: Vec<D>,
+    /// Set if no matching index was found, or the query is slow for other reasons.
+    pub warning: Option<String>,
+    /// Pass this to [FindOptions::bookmark](crate::options::find::FindOptions::bookmark)
+    /// to get the next page of results (CouchDB only).
+    pub bookmark: Option<String>,
+}
+
+impl TryFrom<JsValue> for FindResponse {
+    type Error = crate::error::Error;
+    fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        let docs: Array = get(&value, "docs")?.dyn_into()?;
+
+        Ok(Self {
+            docs: docs
+                .iter()
+                .map(SerializedDocument::try_from)
+                .collect::<Result<_, _>>()?,
+            warning: get(&value, "warning")?.as_string(),
+            bookmark: get(&value, "bookmark")?.as_string(),
+        })
+    }
+}
+
+/// The result of creating a Mango index.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CreateIndexResponse {
+    /// `"created"`, or `"exists"` if the index already existed.
+    pub result: String,
+    /// The id of the design document containing the index.
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// The user a session belongs to.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UserContext {
+    /// The name of the user, or `None` if the session isn't authenticated.
+    pub name: Option<String>,
+    pub roles: Vec<String>,
+}
+
+/// An authentication session with CouchDB.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Session {
+    #[serde(rename = "userCtx")]
+    pub user_ctx: UserContext,
+}
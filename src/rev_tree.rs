@@ -0,0 +1,561 @@
+//! Revision trees and the document update rules of PouchDB, for the backends implemented
+//! in Rust.
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    collate::js_keys,
+    database::JsonDocument,
+    document::Revision,
+    error::Error,
+    events::changes_event_emitter::ChangeEvent,
+    options::{
+        all_docs::AllDocsOptions,
+        changes::{Changes, Style},
+        fetch::FetchOptions,
+    },
+    responses::{AllDocsValue, RevsDiffEntry},
+};
+
+/// The number of revisions kept in the history of each leaf, like PouchDB's default
+/// `revs_limit`.
+pub(crate) const REVS_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RevNode {
+    pub(crate) parent: Option<Revision>,
+    pub(crate) deleted: bool,
+}
+
+/// All known revisions of a document, each pointing to its parent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct RevTree {
+    nodes: HashMap<Revision, RevNode>,
+}
+
+impl RevTree {
+    pub(crate) fn contains(&self, rev: &Revision) -> bool {
+        self.nodes.contains_key(rev)
+    }
+
+    pub(crate) fn insert(&mut self, rev: Revision, parent: Option<Revision>, deleted: bool) {
+        self.nodes.insert(rev, RevNode { parent, deleted });
+    }
+
+    pub(crate) fn is_deleted(&self, rev: &Revision) -> bool {
+        self.nodes.get(rev).is_some_and(|node| node.deleted)
+    }
+
+    /// Forgets the revisions that are more than `limit` generations older than all leaves
+    /// descending from them. The oldest remaining revisions keep pointing to their
+    /// forgotten parents.
+    pub(crate) fn prune(&mut self, limit: usize) {
+        let kept: HashSet<Revision> = self
+            .leaves()
+            .into_iter()
+            .flat_map(|leaf| self.ancestry(leaf).into_iter().take(limit))
+            .collect();
+        self.nodes.retain(|rev, _| kept.contains(rev));
+    }
+
+    /// The revisions without children, highest generation first.
+    pub(crate) fn leaves(&self) -> Vec<&Revision> {
+        let parents: HashSet<&Revision> = self
+            .nodes
+            .values()
+            .filter_map(|node| node.parent.as_ref())
+            .collect();
+        let mut leaves: Vec<&Revision> = self
+            .nodes
+            .keys()
+            .filter(|rev| !parents.contains(rev))
+            .collect();
+        leaves.sort_by(|a, b| (b.generation(), b.hash()).cmp(&(a.generation(), a.hash())));
+        leaves
+    }
+
+    pub(crate) fn is_leaf(&self, rev: &Revision) -> bool {
+        self.contains(rev)
+            && !self
+                .nodes
+                .values()
+                .any(|node| node.parent.as_ref() == Some(rev))
+    }
+
+    /// The winning revision: non-deleted leaves win over deleted ones, then the highest
+    /// generation wins, then the highest hash.
+    pub(crate) fn winner(&self) -> Option<&Revision> {
+        self.leaves().into_iter().max_by(|a, b| {
+            (!self.is_deleted(a), a.generation(), a.hash()).cmp(&(
+                !self.is_deleted(b),
+                b.generation(),
+                b.hash(),
+            ))
+        })
+    }
+
+    /// The non-deleted leaves that didn't win.
+    pub(crate) fn conflicts(&self) -> Vec<Revision> {
+        let winner = self.winner();
+        self.leaves()
+            .into_iter()
+            .filter(|rev| Some(*rev) != winner && !self.is_deleted(rev))
+            .cloned()
+            .collect()
+    }
+
+    /// `rev` followed by its known ancestors.
+    pub(crate) fn ancestry(&self, rev: &Revision) -> Vec<Revision> {
+        let mut ancestry = Vec::new();
+        let mut next = Some(rev);
+        while let Some(rev) = next.filter(|rev| self.contains(rev)) {
+            ancestry.push(rev.clone());
+            next = self.nodes[rev].parent.as_ref();
+        }
+        ancestry
+    }
+}
+
+/// A document with its revision tree and the bodies of its revisions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct DocRecord {
+    pub(crate) revs: RevTree,
+    pub(crate) bodies: HashMap<Revision, Value>,
+    /// The sequence of the last change.
+    pub(crate) seq: u64,
+}
+
+impl DocRecord {
+    pub(crate) fn winner(&self) -> Option<&Revision> {
+        self.revs.winner()
+    }
+
+    pub(crate) fn is_deleted(&self) -> bool {
+        self.winner()
+            .is_none_or(|winner| self.revs.is_deleted(winner))
+    }
+
+    /// Drops the bodies of revisions that aren't leaves any more, and prunes the history
+    /// to [REVS_LIMIT], like PouchDB's compaction. Call it once the bodies of the parents of
+    /// new revisions aren't needed any more.
+    pub(crate) fn compact(&mut self) {
+        self.revs.prune(REVS_LIMIT);
+        let leaves: HashSet<Revision> = self.revs.leaves().into_iter().cloned().collect();
+        self.bodies.retain(|rev, _| leaves.contains(rev));
+    }
+
+    /// Adds a new revision for `doc`, which must be based on a leaf revision (or on the
+    /// deleted winning revision if `doc` has no revision), and returns it.
+    pub(crate) fn update(&mut self, doc: &JsonDocument) -> Result<Revision, Error> {
+        validate(doc)?;
+        let parent = match &doc.rev {
+            Some(rev) if self.revs.is_leaf(rev) => Some(rev.clone()),
+            Some(_) => return Err(conflict()),
+            None if self.is_deleted() => self.winner().cloned(),
+            None => return Err(conflict()),
+        };
+        let rev = new_revision(parent.as_ref(), &doc.to_json());
+        let body = if doc.deleted {
+            Value::Object(Map::new())
+        } else {
+            doc.data.clone()
+        };
+        self.revs.insert(rev.clone(), parent, doc.deleted);
+        self.bodies.insert(rev.clone(), body);
+        Ok(rev)
+    }
+
+    /// Adds a revision made elsewhere together with its `ancestry` (newest first, starting
+    /// with `doc.rev`), like `_bulk_docs` with `new_edits: false` does. Returns `false` if
+    /// the revision was already known.
+    pub(crate) fn merge(
+        &mut self,
+        doc: &JsonDocument,
+        ancestry: &[Revision],
+    ) -> Result<bool, Error> {
+        validate(doc)?;
+        let rev = doc
+            .rev
+            .as_ref()
+            .filter(|rev| ancestry.first() == Some(rev))
+            .ok_or_else(|| Error::BadRequest("Invalid revision history".to_owned()))?;
+        if self.revs.contains(rev) {
+            return Ok(false);
+        }
+        for (i, ancestor) in ancestry.iter().enumerate().rev() {
+            if !self.revs.contains(ancestor) {
+                let deleted = i == 0 && doc.deleted;
+                self.revs
+                    .insert(ancestor.clone(), ancestry.get(i + 1).cloned(), deleted);
+            }
+        }
+        let body = if doc.deleted {
+            Value::Object(Map::new())
+        } else {
+            doc.data.clone()
+        };
+        self.bodies.insert(rev.clone(), body);
+        Ok(true)
+    }
+
+    /// The revisions of `revs` that this document doesn't know.
+    pub(crate) fn revs_diff(&self, revs: &[Revision]) -> RevsDiffEntry {
+        let missing: Vec<Revision> = revs
+            .iter()
+            .filter(|rev| !self.revs.contains(rev))
+            .cloned()
+            .collect();
+        let max_generation = missing.iter().map(Revision::generation).max().unwrap_or(0);
+        let possible_ancestors = self
+            .revs
+            .leaves()
+            .into_iter()
+            .filter(|leaf| leaf.generation() < max_generation)
+            .cloned()
+            .collect();
+        RevsDiffEntry {
+            missing,
+            possible_ancestors,
+        }
+    }
+
+    /// The value of the row of this document in `all_docs`.
+    pub(crate) fn all_docs_value(
+        &self,
+        id: &str,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsValue<JsonDocument>, Error> {
+        let rev = self.winner().cloned().ok_or_else(missing)?;
+        if self.is_deleted() {
+            return Ok(AllDocsValue::Deleted {
+                id: id.to_owned(),
+                rev,
+            });
+        }
+        let doc = if options.include_docs {
+            let fetch_options = FetchOptions {
+                conflicts: Some(Vec::new()).filter(|_| options.conflicts),
+                ..FetchOptions::default()
+            };
+            Some(self.document(id, &fetch_options)?)
+        } else {
+            None
+        };
+        Ok(AllDocsValue::Found {
+            id: id.to_owned(),
+            rev,
+            doc,
+        })
+    }
+
+    /// The change event for the last change of this document, made at `seq`.
+    pub(crate) fn change_event(
+        &self,
+        id: &str,
+        seq: u64,
+        options: &Changes,
+    ) -> Result<ChangeEvent<JsonDocument>, Error> {
+        let deleted = self.is_deleted();
+        let fetch_options = FetchOptions {
+            conflicts: Some(Vec::new()).filter(|_| options.conflicts),
+            ..FetchOptions::default()
+        };
+        Ok(ChangeEvent {
+            id: id.to_owned(),
+            changes: match options.style {
+                Style::MainOnly => self.winner().cloned().into_iter().collect(),
+                Style::AllDocs => {
+                    let winner = self.winner();
+                    winner
+                        .into_iter()
+                        .chain(
+                            self.revs
+                                .leaves()
+                                .into_iter()
+                                .filter(|rev| Some(*rev) != winner),
+                        )
+                        .cloned()
+                        .collect()
+                }
+            },
+            seq: seq.into(),
+            deleted,
+            doc: if options.include_docs && !deleted {
+                Some(self.document(id, &fetch_options)?)
+            } else {
+                None
+            },
+        })
+    }
+
+    /// The winning revision or the one requested in `options`.
+    pub(crate) fn document(&self, id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        let rev = match (&options.rev, &options.open_revs) {
+            (Some(rev), _) => Revision::from(rev.as_str()),
+            (None, crate::options::fetch::OpenRevs::Revs(revs)) => revs
+                .first()
+                .map(|rev| Revision::from(rev.as_str()))
+                .ok_or_else(|| Error::BadRequest("No revisions requested".to_owned()))?,
+            (None, crate::options::fetch::OpenRevs::All) => {
+                return Err(Error::Unsupported(
+                    "open_revs: all can't be returned as a single document".to_owned(),
+                ))
+            }
+            (None, crate::options::fetch::OpenRevs::Default) => {
+                if self.is_deleted() {
+                    return Err(Error::NotFound("deleted".to_owned()));
+                }
+                self.winner().cloned().ok_or_else(missing)?
+            }
+        };
+        let body = self.bodies.get(&rev).ok_or_else(missing)?;
+        let mut data = body.clone();
+        if options.revs {
+            let ancestry = self.revs.ancestry(&rev);
+            let revisions = serde_json::json!({
+                "start": rev.generation(),
+                "ids": ancestry.iter().map(Revision::hash).collect::<Vec<_>>(),
+            });
+            if let Some(data) = data.as_object_mut() {
+                data.insert("_revisions".to_owned(), revisions);
+            }
+        }
+        Ok(JsonDocument {
+            id: id.to_owned(),
+            deleted: self.revs.is_deleted(&rev),
+            conflicts: if options.conflicts.is_some() && options.rev.is_none() {
+                self.revs.conflicts()
+            } else {
+                Vec::new()
+            },
+            rev: Some(rev),
+            data,
+        })
+    }
+}
+
+pub(crate) fn conflict() -> Error {
+    Error::Conflict("Document update conflict".to_owned())
+}
+
+pub(crate) fn missing() -> Error {
+    Error::NotFound("missing".to_owned())
+}
+
+/// Takes the revision history out of `_revisions` of a replicated document, newest first.
+/// Without it, the history only consists of the revision of the document.
+pub(crate) fn take_revisions(doc: &mut JsonDocument) -> Vec<Revision> {
+    let revisions = doc
+        .data
+        .as_object_mut()
+        .and_then(|data| data.remove("_revisions"));
+    let start = revisions
+        .as_ref()
+        .and_then(|revisions| revisions["start"].as_u64());
+    let ids = revisions
+        .as_ref()
+        .and_then(|revisions| revisions["ids"].as_array());
+    match (start, ids) {
+        (Some(start), Some(ids)) => ids
+            .iter()
+            .zip((1..=start).rev())
+            .filter_map(|(id, generation)| {
+                id.as_str()
+                    .map(|id| Revision(format!("{}-{}", generation, id)))
+            })
+            .collect(),
+        _ => doc.rev.iter().cloned().collect(),
+    }
+}
+
+/// Checks an update of a local document, which keeps no history: its revisions are
+/// `0-N`, and only the current one may be updated. Returns the new revision number, or
+/// `None` if the document is deleted.
+pub(crate) fn update_local(current: Option<u64>, doc: &JsonDocument) -> Result<Option<u64>, Error> {
+    if doc.deleted && current.is_none() {
+        return Err(missing());
+    }
+    if doc.rev != current.map(local_revision) {
+        return Err(conflict());
+    }
+    Ok(Some(current.unwrap_or(0) + 1).filter(|_| !doc.deleted))
+}
+
+pub(crate) fn local_revision(rev: u64) -> Revision {
+    Revision(format!("0-{}", rev))
+}
+
+/// Rejects documents that PouchDB wouldn't accept.
+pub(crate) fn validate(doc: &JsonDocument) -> Result<(), Error> {
+    if doc.id.is_empty() {
+        return Err(Error::BadRequest("Document id is empty".to_owned()));
+    }
+    if doc.id.starts_with('_') && !doc.id.starts_with(crate::design::DESIGN_PREFIX) {
+        return Err(Error::BadRequest(
+            "Only reserved document ids may start with underscore.".to_owned(),
+        ));
+    }
+    match &doc.data {
+        Value::Object(data) => match data
+            .keys()
+            .find(|key| key.starts_with('_') && *key != "_attachments")
+        {
+            Some(key) => Err(Error::BadRequest(format!(
+                "Bad special document member: {}",
+                key
+            ))),
+            None => Ok(()),
+        },
+        _ => Err(Error::BadRequest(
+            "Document must be a JSON object".to_owned(),
+        )),
+    }
+}
+
+/// A deterministic revision for a new edit: the MD5 hash of the document as JSON (with
+/// `_id` and the previous `_rev`). The same edits always result in the same revisions, but
+/// they don't necessarily match the ones PouchDB computes with `deterministic_revs`.
+pub(crate) fn new_revision(parent: Option<&Revision>, doc: &Value) -> Revision {
+    let generation = parent.map_or(0, Revision::generation) + 1;
+    let hash = Md5::digest(js_stringify(doc).as_bytes());
+    let hash: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Revision(format!("{}-{}", generation, hash))
+}
+
+/// Serializes a value like JavaScript's `JSON.stringify`.
+pub(crate) fn js_stringify(value: &Value) -> String {
+    let mut result = String::new();
+    write_json(value, &mut result);
+    result
+}
+
+fn write_json(value: &Value, result: &mut String) {
+    match value {
+        Value::Null => result.push_str("null"),
+        Value::Bool(value) => result.push_str(if *value { "true" } else { "false" }),
+        Value::Number(number) => result.push_str(&js_number(number.as_f64().unwrap_or(0.0))),
+        Value::String(_) => result.push_str(&value.to_string()),
+        Value::Array(values) => {
+            result.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    result.push(',');
+                }
+                write_json(value, result);
+            }
+            result.push(']');
+        }
+        Value::Object(object) => {
+            result.push('{');
+            for (i, key) in js_keys(object).into_iter().enumerate() {
+                if i > 0 {
+                    result.push(',');
+                }
+                result.push_str(&Value::from(key).to_string());
+                result.push(':');
+                write_json(&object[key], result);
+            }
+            result.push('}');
+        }
+    }
+}
+
+/// Formats a number like JavaScript's `Number.prototype.toString`.
+fn js_number(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_owned();
+    }
+    if !number.is_finite() {
+        return "null".to_owned();
+    }
+    // The shortest representation that round-trips, as `d.ddde±x`.
+    let exponential = format!("{:e}", number.abs());
+    let (mantissa, exponent) = exponential.split_at(exponential.find('e').unwrap_or(0));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exponent[1..].parse::<i32>().unwrap_or(0) + 1;
+
+    let mut result = String::new();
+    if number < 0.0 {
+        result.push('-');
+    }
+    if k <= n && n <= 21 {
+        result.push_str(&digits);
+        result.push_str(&"0".repeat((n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        result.push_str(&digits[..n as usize]);
+        result.push('.');
+        result.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        result.push_str("0.");
+        result.push_str(&"0".repeat(-n as usize));
+        result.push_str(&digits);
+    } else {
+        result.push_str(&digits[..1]);
+        if k > 1 {
+            result.push('.');
+            result.push_str(&digits[1..]);
+        }
+        result.push('e');
+        result.push(if n > 0 { '+' } else { '-' });
+        result.push_str(&(n - 1).abs().to_string());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn stringifies_like_javascript() {
+        // Expected values from JSON.stringify in node.
+        let value = json!({"b": [1.0, 0.5, -2.5e-7, 1e21, 123456789012.0], "10": "\u{1}\"", "2": null, "a": {}});
+        assert_eq!(
+            js_stringify(&value),
+            r#"{"2":null,"10":"\u0001\"","b":[1,0.5,-2.5e-7,1e+21,123456789012],"a":{}}"#
+        );
+    }
+
+    #[test]
+    fn winner_prefers_live_leaves_then_generation_then_hash() {
+        let rev = |rev: &str| Revision::from(rev);
+        let mut tree = RevTree::default();
+        tree.insert(rev("1-a"), None, false);
+        tree.insert(rev("2-a"), Some(rev("1-a")), false);
+        tree.insert(rev("2-b"), Some(rev("1-a")), false);
+        assert_eq!(tree.winner(), Some(&rev("2-b")));
+        assert_eq!(tree.conflicts(), vec![rev("2-a")]);
+
+        tree.insert(rev("3-a"), Some(rev("2-b")), true);
+        assert_eq!(tree.winner(), Some(&rev("2-a")));
+        assert!(tree.conflicts().is_empty());
+        assert_eq!(
+            tree.ancestry(&rev("3-a")),
+            vec![rev("3-a"), rev("2-b"), rev("1-a")]
+        );
+    }
+
+    #[test]
+    fn compaction_keeps_leaf_bodies_and_recent_history() {
+        let mut record = DocRecord::default();
+        let mut rev = None;
+        for n in 0..REVS_LIMIT + 10 {
+            let mut doc = JsonDocument::new("a", json!({ "n": n }));
+            doc.rev = rev;
+            rev = Some(record.update(&doc).unwrap());
+            record.compact();
+        }
+        let rev = rev.unwrap();
+        assert_eq!(record.bodies.len(), 1);
+        assert_eq!(record.bodies[&rev], json!({ "n": REVS_LIMIT + 9 }));
+        assert_eq!(record.revs.ancestry(&rev).len(), REVS_LIMIT);
+        // Revisions whose bodies were dropped are still known to replication.
+        let old = record.revs.ancestry(&rev).last().cloned().unwrap();
+        assert!(record.revs_diff(&[old]).missing.is_empty());
+    }
+}
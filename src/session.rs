@@ -0,0 +1,243 @@
+//! Cookie sessions and users of the CouchDB server of a remote database, via the
+//! `_session` endpoint and the `_users` database.
+//!
+//! After [log_in](PouchDB::log_in), the browser sends the session cookie with all
+//! requests to the server, so the credentials don't need to be kept in
+//! [CreateOptions::auth](crate::options::create::CreateOptions::auth).
+
+use js_sys::{Object, Reflect};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, Response};
+
+use crate::{
+    document::Revision,
+    error::Error,
+    responses::{ChangeResponse, Session, UserContext},
+    PouchDB,
+};
+
+/// The prefix of the ids of user documents.
+pub const USER_PREFIX: &str = "org.couchdb.user:";
+
+/// A user in the `_users` database.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct User {
+    pub name: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// The new password. Never returned by CouchDB, which only stores a hash of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(rename = "_rev", default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<Revision>,
+    /// All other fields, e.g. custom metadata and the password hash. Keep them when
+    /// updating the user, or the password is lost.
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl User {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+    pub fn roles<T: Into<String>, I: IntoIterator<Item = T>>(self, roles: I) -> Self {
+        Self {
+            roles: roles.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+    /// Set a custom field, e.g. `email`.
+    pub fn field<T: Into<String>, V: Into<Value>>(mut self, name: T, value: V) -> Self {
+        self.fields.insert(name.into(), value.into());
+        self
+    }
+
+    /// The id of the user document.
+    pub fn id(&self) -> String {
+        user_id(&self.name)
+    }
+
+    /// The user document, as stored in `_users`.
+    pub(crate) fn to_json(&self) -> Result<Value, Error> {
+        let mut doc = serde_json::to_value(self)?;
+        doc["_id"] = json!(self.id());
+        doc["type"] = json!("user");
+        Ok(doc)
+    }
+
+    pub(crate) fn from_json(mut doc: Value) -> Result<Self, Error> {
+        if let Some(doc) = doc.as_object_mut() {
+            doc.remove("_id");
+            doc.remove("type");
+        }
+        Ok(serde_json::from_value(doc)?)
+    }
+}
+
+pub(crate) fn user_id(name: &str) -> String {
+    format!("{}{}", USER_PREFIX, name)
+}
+
+/// The session started by a successful `POST /_session`.
+pub(crate) fn logged_in(response: &Value) -> Result<Session, Error> {
+    Ok(Session {
+        user_ctx: UserContext {
+            name: response["name"].as_str().map(ToOwned::to_owned),
+            roles: serde_json::from_value(response["roles"].clone()).unwrap_or_default(),
+        },
+    })
+}
+
+impl PouchDB {
+    /// Start a cookie session
+    pub async fn log_in(&self, username: &str, password: &str) -> Result<Session, Error> {
+        let body = json!({ "name": username, "password": password });
+        let response = self.server_request("POST", "_session", Some(&body)).await?;
+        logged_in(&response)
+    }
+
+    /// End the cookie session
+    pub async fn log_out(&self) -> Result<(), Error> {
+        self.server_request("DELETE", "_session", None).await?;
+        Ok(())
+    }
+
+    /// Get the current session
+    ///
+    /// [UserContext::name] is `None` if there is no session.
+    pub async fn get_session(&self) -> Result<Session, Error> {
+        let response = self.server_request("GET", "_session", None).await?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Create a user with `password`
+    pub async fn sign_up(&self, user: &User, password: &str) -> Result<ChangeResponse, Error> {
+        let user = User {
+            password: Some(password.to_owned()),
+            ..user.clone()
+        };
+        self.put_user(&user).await
+    }
+
+    /// Set a new password for the user `name`
+    ///
+    /// The session of the user ends if it is the current one, so log in again.
+    pub async fn change_password(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<ChangeResponse, Error> {
+        let user = User {
+            password: Some(password.to_owned()),
+            ..self.get_user(name).await?
+        };
+        self.put_user(&user).await
+    }
+
+    /// Fetch the user `name`
+    pub async fn get_user(&self, name: &str) -> Result<User, Error> {
+        let path = format!("_users/{}", encode(&user_id(name)));
+        User::from_json(self.server_request("GET", &path, None).await?)
+    }
+
+    /// Create or update a user
+    ///
+    /// To update an existing user, [User::rev] must be set, e.g. by fetching it with
+    /// [get_user](Self::get_user) first.
+    pub async fn put_user(&self, user: &User) -> Result<ChangeResponse, Error> {
+        let path = format!("_users/{}", encode(&user.id()));
+        let response = self
+            .server_request("PUT", &path, Some(&user.to_json()?))
+            .await?;
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Send a request to the server of this remote database, including cookies. It goes
+    /// through the `fetch` of the database, so it carries the credentials of
+    /// [CreateOptions::auth](crate::options::create::CreateOptions::auth) and passes the
+    /// hook of [CreateOptions::fetch](crate::options::create::CreateOptions::fetch).
+    async fn server_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, Error> {
+        // Only the HTTP adapter has `fetch`.
+        if !self.has_method("fetch") {
+            let name = Reflect::get(&self.0, &JsValue::from_str("name"))?
+                .as_string()
+                .unwrap_or_default();
+            return Err(Error::Unsupported(format!(
+                "{} is not a remote database.",
+                name
+            )));
+        }
+
+        let headers = Headers::new()?;
+        headers.set("Accept", "application/json")?;
+        headers.set("Content-Type", "application/json")?;
+        let init = Object::new();
+        Reflect::set(&init, &JsValue::from_str("method"), &method.into())?;
+        Reflect::set(&init, &JsValue::from_str("headers"), &headers)?;
+        Reflect::set(&init, &JsValue::from_str("credentials"), &"include".into())?;
+        if let Some(body) = body {
+            Reflect::set(&init, &JsValue::from_str("body"), &body.to_string().into())?;
+        }
+
+        let path = format!("/{}", path);
+        let response: Response = JsFuture::from(self.0.fetch_path(&path, &init)?)
+            .await?
+            .dyn_into()?;
+        let body: Value = JsFuture::from(response.json()?).await?.into_serde()?;
+        if response.ok() {
+            Ok(body)
+        } else {
+            Err(Error::from_response(response.status(), &body))
+        }
+    }
+}
+
+/// Percent-encodes the `:` of user ids, like `encodeURIComponent`.
+fn encode(id: &str) -> String {
+    js_sys::encode_uri_component(id).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_documents() {
+        let user = User::new("bob")
+            .roles(["admin"])
+            .field("email", "bob@example.com");
+        assert_eq!(
+            user.to_json().unwrap(),
+            json!({
+                "name": "bob",
+                "roles": ["admin"],
+                "email": "bob@example.com",
+                "_id": "org.couchdb.user:bob",
+                "type": "user",
+            })
+        );
+        let stored = json!({
+            "_id": "org.couchdb.user:bob",
+            "_rev": "1-a",
+            "type": "user",
+            "name": "bob",
+            "roles": [],
+            "derived_key": "abc",
+        });
+        let user = User::from_json(stored).unwrap();
+        assert_eq!(user.rev, Some(Revision::from("1-a")));
+        assert_eq!(user.fields["derived_key"], "abc");
+        assert!(!user.fields.contains_key("type"));
+    }
+}
@@ -0,0 +1,528 @@
+//! Rewriting documents on every write and read, like transform-pouch.
+//!
+//! Transforms are registered with [PouchDB::add_transform], or with
+//! [Transformed::add_transform] for the other [Database] backends. Documents are passed to
+//! [incoming](DocumentTransform::incoming) before they're written by `put`, `post` and
+//! `bulk_docs`, and to [outgoing](DocumentTransform::outgoing) when they're read by
+//! `fetch`, `all_docs`, `query`, `find` and `changes`.
+//!
+//! The data of attachments, if included, is passed as base64 in `_attachments`, like in
+//! CouchDB, except to the listeners of
+//! [ChangesEventEmitter](crate::events::changes_event_emitter::ChangesEventEmitter), which
+//! get the attachments as they're stored.
+//!
+//! Replication copies the documents as they're stored, so it doesn't apply transforms.
+//! Neither do selectors and views, which see the stored documents too. `_local` documents
+//! and deletions without fields are never transformed, nor are the partial documents
+//! returned by `find` if its `fields` leave out `_id`.
+
+use base64::prelude::*;
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use std::{collections::HashMap, rc::Rc};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag};
+
+use crate::{
+    database::{Database, JsonDocument},
+    document::{Revision, LOCAL_PREFIX},
+    error::Error,
+    events::{changes_event_emitter::ChangeEvent, SequenceID},
+    options::{all_docs::AllDocsOptions, changes::Changes, fetch::FetchOptions},
+    responses::{AllDocsResponse, ChangeResponse, DatabaseInfo, RevsDiffEntry},
+    PouchDB,
+};
+
+/// Rewrites documents when they're written to and read from a database.
+pub trait DocumentTransform {
+    /// Called with each document before it's written. Returning an error fails the write.
+    fn incoming(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        Ok(doc)
+    }
+    /// Called with each document that is read, to undo [incoming](Self::incoming).
+    fn outgoing(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        Ok(doc)
+    }
+}
+
+/// The transforms of a database. Incoming documents pass them in the order they were
+/// added, outgoing documents in reverse order.
+#[derive(Clone, Default)]
+pub(crate) struct Transforms(Vec<Rc<dyn DocumentTransform>>);
+
+impl Transforms {
+    pub(crate) fn push(&mut self, transform: Rc<dyn DocumentTransform>) {
+        self.0.push(transform);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn incoming(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        if !is_transformable(&doc) {
+            return Ok(doc);
+        }
+        self.0
+            .iter()
+            .try_fold(doc, |doc, transform| transform.incoming(doc))
+    }
+
+    pub(crate) fn outgoing(&self, doc: JsonDocument) -> Result<JsonDocument, Error> {
+        if !is_transformable(&doc) {
+            return Ok(doc);
+        }
+        self.0
+            .iter()
+            .rev()
+            .try_fold(doc, |doc, transform| transform.outgoing(doc))
+    }
+
+    /// [incoming](Self::incoming) for a document given to PouchDB.
+    pub(crate) async fn incoming_js(&self, doc: JsValue) -> Result<JsValue, Error> {
+        if self.is_empty() {
+            return Ok(doc);
+        }
+        map_js(doc, |doc| self.incoming(doc)).await
+    }
+
+    /// [outgoing](Self::outgoing) for a document returned by PouchDB.
+    pub(crate) async fn outgoing_js(&self, doc: JsValue) -> Result<JsValue, Error> {
+        if self.is_empty() || !has_id(&doc) {
+            return Ok(doc);
+        }
+        map_js(doc, |doc| self.outgoing(doc)).await
+    }
+
+    /// Like [outgoing_js](Self::outgoing_js), but without reading the data of attachments,
+    /// so they're left out.
+    pub(crate) fn outgoing_js_sync(&self, doc: JsValue) -> Result<JsValue, Error> {
+        if self.is_empty() || !has_id(&doc) {
+            return Ok(doc);
+        }
+        map_js_sync(doc, |doc| self.outgoing(doc))
+    }
+
+    /// Transforms the documents in the array `list` of `response`, either the elements
+    /// themselves or their field `field`, e.g. the `doc` of the `rows` of `all_docs`.
+    pub(crate) async fn outgoing_in(
+        &self,
+        response: &JsValue,
+        list: &str,
+        field: Option<&str>,
+    ) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let list = Reflect::get(response, &JsValue::from_str(list))?;
+        let Some(list) = list.dyn_ref::<Array>() else {
+            return Ok(());
+        };
+        for (index, entry) in list.iter().enumerate() {
+            match field {
+                Some(field) => {
+                    let field = JsValue::from_str(field);
+                    let doc = self.outgoing_js(Reflect::get(&entry, &field)?).await?;
+                    Reflect::set(&entry, &field, &doc)?;
+                }
+                None => list.set(index as u32, self.outgoing_js(entry).await?),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Like transform-pouch, `_local` documents and plain deletions are stored as they are.
+pub(crate) fn is_transformable(doc: &JsonDocument) -> bool {
+    let is_empty = doc.data.as_object().is_none_or(|data| data.is_empty());
+    !(doc.id.starts_with(LOCAL_PREFIX) || doc.deleted && is_empty)
+}
+
+/// Whether `doc` is a document with an id, as opposed to e.g. a projection of `find`.
+fn has_id(doc: &JsValue) -> bool {
+    doc.is_object() && Reflect::get(doc, &JsValue::from_str("_id")).is_ok_and(|id| id.is_string())
+}
+
+/// Passes a PouchDB document through `f` as a [JsonDocument].
+///
+/// The data of attachments is passed as base64, like in CouchDB, and turned back into
+/// `Blob`s afterwards.
+async fn map_js<F>(doc: JsValue, f: F) -> Result<JsValue, Error>
+where
+    F: FnOnce(JsonDocument) -> Result<JsonDocument, Error>,
+{
+    let key = JsValue::from_str("_attachments");
+    let attachments = Reflect::get(&doc, &key)?;
+    if !has_blobs(&attachments)? {
+        return map_js_sync(doc, f);
+    }
+    let object = Object::assign(&Object::new(), doc.unchecked_ref());
+    let attachments = blobs_to_base64(&attachments).await?;
+    Reflect::set(&object, &key, &attachments)?;
+    let doc = map_js_sync(object.into(), f)?;
+    base64_to_blobs(&Reflect::get(&doc, &key)?)?;
+    Ok(doc)
+}
+
+/// Passes a PouchDB document through `f` as a [JsonDocument].
+///
+/// `Blob`s can't be converted to JSON, so attachments with binary data are put back as
+/// they were, as are the conflicts.
+fn map_js_sync<F>(doc: JsValue, f: F) -> Result<JsValue, Error>
+where
+    F: FnOnce(JsonDocument) -> Result<JsonDocument, Error>,
+{
+    let object = Object::assign(&Object::new(), doc.unchecked_ref());
+    let mut kept = vec![JsValue::from_str("_conflicts")];
+    if has_blobs(&Reflect::get(&object, &JsValue::from_str("_attachments"))?)? {
+        kept.push(JsValue::from_str("_attachments"));
+    }
+    let kept = kept
+        .into_iter()
+        .map(|key| {
+            let value = Reflect::get(&object, &key)?;
+            Reflect::delete_property(&object, &key)?;
+            Ok((key, value))
+        })
+        .collect::<Result<Vec<_>, JsValue>>()?;
+
+    let doc = f(JsonDocument::from_json(
+        JsValue::from(object).into_serde()?,
+    )?)?;
+
+    let doc = JsValue::from_serde(&doc.to_json())?;
+    for (key, value) in kept {
+        if !value.is_undefined() {
+            Reflect::set(&doc, &key, &value)?;
+        }
+    }
+    Ok(doc)
+}
+
+fn has_blobs(attachments: &JsValue) -> Result<bool, Error> {
+    if !attachments.is_object() {
+        return Ok(false);
+    }
+    let data = JsValue::from_str("data");
+    for attachment in Object::values(attachments.unchecked_ref()).iter() {
+        if Reflect::get(&attachment, &data)?.is_instance_of::<Blob>() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// A copy of `attachments` with the data of `Blob`s as base64.
+async fn blobs_to_base64(attachments: &JsValue) -> Result<Object, Error> {
+    let data = JsValue::from_str("data");
+    let copy = Object::new();
+    for entry in Object::entries(attachments.unchecked_ref()).iter() {
+        let entry: Array = entry.unchecked_into();
+        let attachment = Object::assign(&Object::new(), entry.get(1).unchecked_ref());
+        if let Ok(blob) = Reflect::get(&attachment, &data)?.dyn_into::<Blob>() {
+            let buffer = JsFuture::from(blob.array_buffer()).await?;
+            let base64 = BASE64_STANDARD.encode(Uint8Array::new(&buffer).to_vec());
+            Reflect::set(&attachment, &data, &JsValue::from_str(&base64))?;
+        }
+        Reflect::set(&copy, &entry.get(0), &attachment)?;
+    }
+    Ok(copy)
+}
+
+/// Turns the base64 data of `attachments` into `Blob`s, in place.
+fn base64_to_blobs(attachments: &JsValue) -> Result<(), Error> {
+    if !attachments.is_object() {
+        return Ok(());
+    }
+    let data = JsValue::from_str("data");
+    for attachment in Object::values(attachments.unchecked_ref()).iter() {
+        let Some(base64) = Reflect::get(&attachment, &data)?.as_string() else {
+            continue;
+        };
+        let bytes = BASE64_STANDARD
+            .decode(base64)
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+        let options = BlobPropertyBag::new();
+        if let Some(content_type) =
+            Reflect::get(&attachment, &JsValue::from_str("content_type"))?.as_string()
+        {
+            options.set_type(&content_type);
+        }
+        let blob = Blob::new_with_u8_array_sequence_and_options(
+            &Array::of1(&Uint8Array::from(bytes.as_slice())),
+            &options,
+        )?;
+        Reflect::set(&attachment, &data, &blob)?;
+    }
+    Ok(())
+}
+
+impl PouchDB {
+    /// Add a transform, which rewrites documents when they're written and read
+    ///
+    /// See the [transform](crate::transform) module for the methods applying it.
+    pub fn add_transform<T: DocumentTransform + 'static>(&mut self, transform: T) {
+        self.2.push(Rc::new(transform));
+    }
+}
+
+/// A [Database] applying [DocumentTransform]s, like [PouchDB::add_transform] does.
+pub struct Transformed<D> {
+    db: D,
+    transforms: Transforms,
+}
+
+impl<D: Database> Transformed<D> {
+    pub fn new(db: D) -> Self {
+        Self {
+            db,
+            transforms: Transforms::default(),
+        }
+    }
+
+    /// Add a transform, which rewrites documents when they're written and read
+    pub fn add_transform<T: DocumentTransform + 'static>(&mut self, transform: T) {
+        self.transforms.push(Rc::new(transform));
+    }
+
+    /// The database, which stores the transformed documents.
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+}
+
+impl<D: Database> Database for Transformed<D> {
+    async fn info(&self) -> Result<DatabaseInfo, Error> {
+        self.db.info().await
+    }
+
+    async fn put(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        self.db.put(&self.transforms.incoming(doc.clone())?).await
+    }
+
+    async fn post(&self, doc: &JsonDocument) -> Result<ChangeResponse, Error> {
+        self.db.post(&self.transforms.incoming(doc.clone())?).await
+    }
+
+    async fn fetch(&self, doc_id: &str, options: &FetchOptions) -> Result<JsonDocument, Error> {
+        self.transforms
+            .outgoing(self.db.fetch(doc_id, options).await?)
+    }
+
+    async fn remove(&self, doc_id: &str, rev: &Revision) -> Result<ChangeResponse, Error> {
+        self.db.remove(doc_id, rev).await
+    }
+
+    async fn bulk_docs(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<ChangeResponse, Error>>, Error> {
+        let docs = docs
+            .iter()
+            .map(|doc| self.transforms.incoming(doc.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.db.bulk_docs(&docs).await
+    }
+
+    async fn all_docs(
+        &self,
+        options: &AllDocsOptions,
+    ) -> Result<AllDocsResponse<JsonDocument>, Error> {
+        self.db
+            .all_docs(options)
+            .await?
+            .try_map_docs(|doc| self.transforms.outgoing(doc))
+    }
+
+    async fn changes(
+        &self,
+        options: &Changes,
+    ) -> Result<(Vec<ChangeEvent<JsonDocument>>, SequenceID), Error> {
+        let (results, last_seq) = self.db.changes(options).await?;
+        let results = results
+            .into_iter()
+            .map(|event| {
+                Ok(ChangeEvent {
+                    doc: event
+                        .doc
+                        .map(|doc| self.transforms.outgoing(doc))
+                        .transpose()?,
+                    ..event
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok((results, last_seq))
+    }
+
+    async fn id(&self) -> Result<String, Error> {
+        self.db.id().await
+    }
+
+    async fn revs_diff(
+        &self,
+        revs: HashMap<String, Vec<Revision>>,
+    ) -> Result<HashMap<String, RevsDiffEntry>, Error> {
+        self.db.revs_diff(revs).await
+    }
+
+    async fn bulk_get(
+        &self,
+        docs: &[(String, Revision)],
+    ) -> Result<Vec<Result<JsonDocument, Error>>, Error> {
+        self.db.bulk_get(docs).await
+    }
+
+    async fn bulk_docs_replicated(
+        &self,
+        docs: &[JsonDocument],
+    ) -> Result<Vec<Result<(), Error>>, Error> {
+        self.db.bulk_docs_replicated(docs).await
+    }
+}
+
+impl<D: std::fmt::Debug> std::fmt::Debug for Transformed<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Transformed {:?}", self.db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::MemoryDatabase, responses::AllDocsValue};
+    use futures::executor::block_on;
+    use serde_json::{json, Value};
+
+    /// Stores `title` upper-cased in `stored`, and adds the computed field `length`.
+    struct Shout;
+
+    impl DocumentTransform for Shout {
+        fn incoming(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+            let title = doc.data["title"]
+                .as_str()
+                .unwrap_or_default()
+                .to_uppercase();
+            doc.data = json!({ "stored": title });
+            Ok(doc)
+        }
+        fn outgoing(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+            let stored = doc.data["stored"].as_str().unwrap_or_default().to_owned();
+            doc.data = json!({ "title": stored.to_lowercase(), "length": stored.len() });
+            Ok(doc)
+        }
+    }
+
+    fn expected() -> Value {
+        json!({ "title": "hello", "length": 5 })
+    }
+
+    #[test]
+    fn every_path_is_transformed() {
+        let mut db = Transformed::new(MemoryDatabase::new("db"));
+        db.add_transform(Shout);
+        let hello = json!({ "title": "Hello" });
+        block_on(async {
+            let put = db
+                .put(&JsonDocument::new("a", hello.clone()))
+                .await
+                .unwrap();
+            let post = db
+                .post(&JsonDocument::new("", hello.clone()))
+                .await
+                .unwrap();
+            let bulk = db
+                .bulk_docs(&[JsonDocument::new("c", hello.clone())])
+                .await
+                .unwrap();
+            assert!(bulk[0].is_ok());
+            db.put(&JsonDocument::new("_local/l", hello.clone()))
+                .await
+                .unwrap();
+
+            let stored = db.inner().fetch("a", &FetchOptions::default()).await;
+            assert_eq!(stored.unwrap().data, json!({ "stored": "HELLO" }));
+            let local = db.fetch("_local/l", &FetchOptions::default()).await;
+            assert_eq!(local.unwrap().data, hello);
+
+            for id in ["a", post.id.as_str(), "c"] {
+                let doc = db.fetch(id, &FetchOptions::default()).await.unwrap();
+                assert_eq!(doc.data, expected());
+            }
+
+            let all_docs = db
+                .all_docs(&AllDocsOptions {
+                    include_docs: true,
+                    ..AllDocsOptions::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(all_docs.rows.len(), 3);
+            for row in all_docs.rows {
+                match row.value {
+                    AllDocsValue::Found { doc, .. } => {
+                        assert_eq!(doc.unwrap().data, expected())
+                    }
+                    value => panic!("unexpected row {:?}", value),
+                }
+            }
+
+            db.remove("a", &put.rev).await.unwrap();
+            let (changes, _) = db
+                .changes(&Changes {
+                    include_docs: true,
+                    ..Changes::default()
+                })
+                .await
+                .unwrap();
+            assert_eq!(changes.len(), 3);
+            for change in changes {
+                if change.deleted {
+                    assert_eq!(change.id, "a");
+                } else {
+                    assert_eq!(change.doc.unwrap().data, expected());
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn transforms_are_chained() {
+        struct Append(&'static str);
+        impl DocumentTransform for Append {
+            fn incoming(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+                doc.data["trace"] =
+                    json!(format!("{}{}", doc.data["trace"].as_str().unwrap(), self.0));
+                Ok(doc)
+            }
+            fn outgoing(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+                let trace = doc.data["trace"].as_str().unwrap();
+                let trace = trace
+                    .strip_suffix(self.0)
+                    .ok_or_else(|| Error::BadRequest(trace.to_owned()))?;
+                doc.data["trace"] = json!(trace);
+                Ok(doc)
+            }
+        }
+        let mut transforms = Transforms::default();
+        transforms.push(Rc::new(Append("1")));
+        transforms.push(Rc::new(Append("2")));
+
+        let doc = JsonDocument::new("a", json!({ "trace": "" }));
+        let stored = transforms.incoming(doc.clone()).unwrap();
+        assert_eq!(stored.data, json!({ "trace": "12" }));
+        assert_eq!(transforms.outgoing(stored).unwrap(), doc);
+
+        let deletion = JsonDocument::deleted("a", "1-a".into());
+        assert_eq!(transforms.incoming(deletion.clone()).unwrap(), deletion);
+
+        // Deletions with fields are transformed, and keep their fields.
+        let deletion = JsonDocument {
+            data: json!({ "trace": "" }),
+            ..deletion
+        };
+        assert_eq!(
+            transforms.incoming(deletion).unwrap().to_json(),
+            json!({ "trace": "12", "_deleted": true, "_id": "a", "_rev": "1-a" })
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+
+use js_sys::Function;
+use serde_json::Value;
+use wasm_bindgen::{closure::Closure, JsValue};
+
+use crate::{collate::collate, responses::Row};
+
+/// Collects the rows emitted by a map function written in Rust.
+///
+/// Since it doesn't depend on PouchDB, map functions can be unit-tested by calling them
+/// with a document and an empty emitter:
+///
+/// ```
+/// use pouchdb::view::Emitter;
+/// use serde_json::{json, Value};
+///
+/// fn by_name(doc: &Value, emitter: &mut Emitter) {
+///     if let Some(name) = doc.get("name") {
+///         emitter.emit(name.clone(), 1);
+///     }
+/// }
+///
+/// let mut emitter = Emitter::default();
+/// by_name(&json!({"_id": "a", "name": "Alice"}), &mut emitter);
+/// assert_eq!(emitter.rows(), &[(json!("Alice"), json!(1))]);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Emitter {
+    rows: Vec<(Value, Value)>,
+}
+
+impl Emitter {
+    /// Emit a row with the given key and value for the current document.
+    pub fn emit<K: Into<Value>, V: Into<Value>>(&mut self, key: K, value: V) {
+        self.rows.push((key.into(), value.into()));
+    }
+    /// The rows emitted so far, as `(key, value)` pairs.
+    pub fn rows(&self) -> &[(Value, Value)] {
+        &self.rows
+    }
+}
+
+pub(crate) type MapClosure = Closure<dyn Fn(JsValue, Function)>;
+
+/// Wraps a Rust map function into a closure callable by PouchDB as `map(doc, emit)`.
+pub(crate) fn map_closure<M>(map: M) -> MapClosure
+where
+    M: Fn(&Value, &mut Emitter) + 'static,
+{
+    Closure::wrap(Box::new(move |doc: JsValue, emit: Function| {
+        // Documents that can't be represented as JSON don't emit anything.
+        if let Ok(doc) = doc.into_serde::<Value>() {
+            let mut emitter = Emitter::default();
+            map(&doc, &mut emitter);
+            for (key, value) in emitter.rows {
+                if let (Ok(key), Ok(value)) =
+                    (JsValue::from_serde(&key), JsValue::from_serde(&value))
+                {
+                    emit.call2(&JsValue::NULL, &key, &value).ok();
+                }
+            }
+        }
+    }) as Box<dyn Fn(JsValue, Function)>)
+}
+
+/// Reduces the rows emitted by a map function with a reduce function written in Rust.
+///
+/// PouchDB evaluates the source code of reduce functions, so Rust closures can't be passed
+/// to it. Instead, the rows are grouped like PouchDB groups them for `group` and
+/// `group_level`, and each group is reduced at once, so `rereduce` is always false.
+pub(crate) fn reduce_rows<R>(
+    rows: Vec<Row<Value, Value>>,
+    group: bool,
+    group_level: Option<usize>,
+    reduce: R,
+) -> Vec<(Value, Value)>
+where
+    R: Fn(&[Value], &[Value], bool) -> Value,
+{
+    let group_key = |key: &Value| match (group_level, key) {
+        (Some(level), Value::Array(key)) => Value::Array(key.iter().take(level).cloned().collect()),
+        (Some(_), key) => key.clone(),
+        (None, key) if group => key.clone(),
+        (None, _) => Value::Null,
+    };
+
+    let mut groups: Vec<(Value, Vec<Value>, Vec<Value>)> = Vec::new();
+    for row in rows {
+        let key = group_key(&row.key);
+        let pair = Value::Array(vec![row.key, Value::String(row.id)]);
+        match groups.last_mut() {
+            Some((last, keys, values)) if collate(last, &key) == Ordering::Equal => {
+                keys.push(pair);
+                values.push(row.value);
+            }
+            _ => groups.push((key, vec![pair], vec![row.value])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(key, keys, values)| (key, reduce(&keys, &values, false)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn row(id: &str, key: Value, value: i64) -> Row<Value, Value> {
+        Row {
+            id: id.to_owned(),
+            key,
+            value: json!(value),
+            doc: None,
+        }
+    }
+
+    #[test]
+    fn rows_are_reduced_per_group() {
+        let rows = || {
+            vec![
+                row("a", json!(["2024", "01"]), 1),
+                row("b", json!(["2024", "01"]), 2),
+                row("c", json!(["2024", "02"]), 3),
+                row("d", json!(["2025", "01"]), 4),
+            ]
+        };
+        let sum = |keys: &[Value], values: &[Value], rereduce: bool| {
+            assert!(!rereduce);
+            assert_eq!(keys.len(), values.len());
+            json!(values.iter().filter_map(Value::as_i64).sum::<i64>())
+        };
+
+        assert_eq!(
+            reduce_rows(rows(), false, None, sum),
+            vec![(Value::Null, json!(10))]
+        );
+        assert_eq!(
+            reduce_rows(rows(), true, Some(1), sum),
+            vec![(json!(["2024"]), json!(6)), (json!(["2025"]), json!(4))]
+        );
+        assert_eq!(reduce_rows(rows(), true, None, sum).len(), 3);
+        assert!(reduce_rows(Vec::new(), false, None, sum).is_empty());
+
+        let keys = reduce_rows(rows(), false, None, |keys, _, _| json!(keys));
+        assert_eq!(keys[0].1[0], json!([["2024", "01"], "a"]));
+    }
+}
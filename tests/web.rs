@@ -0,0 +1,273 @@
+//! Tests of the code paths calling PouchDB, which run in Node.js:
+//!
+//! ```sh
+//! npm install
+//! wasm-pack test --node --features pouchdb-find,pouchdb-adapter-memory
+//! ```
+#![cfg(all(
+    target_arch = "wasm32",
+    feature = "pouchdb-find",
+    feature = "pouchdb-adapter-memory"
+))]
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use base64::prelude::*;
+use futures::{channel::mpsc, StreamExt};
+use pouchdb::{
+    database::JsonDocument,
+    document::{Attachment, SerializedDocument, SerializedDocumentData},
+    error::Error,
+    options::{
+        all_docs::AllDocsOptions, changes::Changes, create::Adapter, create::CreateOptions,
+        fetch::FetchOptions, find::FindOptions, query::QueryOptions, selector::Selector,
+    },
+    responses::AllDocsValue,
+    transform::DocumentTransform,
+    view::Emitter,
+    PouchDB,
+};
+use serde_json::{json, Value};
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::wasm_bindgen_test;
+use web_sys::Blob;
+
+fn memory_db(name: &str) -> PouchDB {
+    PouchDB::plugin_memory_adapter().unwrap();
+    PouchDB::plugin_find().unwrap();
+    PouchDB::new_with_options(CreateOptions::default().name(name).adapter(Adapter::Memory)).unwrap()
+}
+
+fn by_month(doc: &Value, emitter: &mut Emitter) {
+    emitter.emit(doc["month"].clone(), doc["amount"].clone());
+}
+
+fn sum(_keys: &[Value], values: &[Value], _rereduce: bool) -> Value {
+    json!(values.iter().filter_map(Value::as_i64).sum::<i64>())
+}
+
+#[wasm_bindgen_test]
+async fn rust_reduce_functions_run() {
+    let db = memory_db("reduce");
+    let docs = [("a", "01", 1), ("b", "01", 2), ("c", "02", 3)]
+        .iter()
+        .map(|(id, month, amount)| {
+            JsonDocument::new(*id, json!({ "month": month, "amount": amount }))
+        })
+        .collect::<Vec<_>>();
+    db.bulk_docs(docs).await.unwrap();
+
+    let total = db
+        .query_with_reduce::<Value, i64, _, _>(by_month, sum, QueryOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(total.rows.len(), 1);
+    assert_eq!(total.rows[0].key, Value::Null);
+    assert_eq!(total.rows[0].value, 6);
+
+    let grouped = db
+        .query_with_reduce::<String, i64, _, _>(
+            by_month,
+            sum,
+            QueryOptions {
+                group: true,
+                ..QueryOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+    let grouped = grouped
+        .rows
+        .into_iter()
+        .map(|row| (row.key, row.value))
+        .collect::<Vec<_>>();
+    assert_eq!(grouped, [("01".to_owned(), 3), ("02".to_owned(), 3)]);
+
+    db.destroy().await.unwrap();
+}
+
+/// Stores `title` upper-cased in `stored`, and the text of attachments upper-cased.
+struct Shout;
+
+impl DocumentTransform for Shout {
+    fn incoming(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+        let data = doc.data.as_object_mut().unwrap();
+        let title = data.remove("title").unwrap_or_default();
+        data.insert(
+            "stored".to_owned(),
+            json!(title.as_str().unwrap().to_uppercase()),
+        );
+        map_attachments(&mut doc.data, str::to_uppercase);
+        Ok(doc)
+    }
+    fn outgoing(&self, mut doc: JsonDocument) -> Result<JsonDocument, Error> {
+        let data = doc.data.as_object_mut().unwrap();
+        let stored = data.remove("stored").unwrap_or_default();
+        data.insert(
+            "title".to_owned(),
+            json!(stored.as_str().unwrap().to_lowercase()),
+        );
+        map_attachments(&mut doc.data, str::to_lowercase);
+        Ok(doc)
+    }
+}
+
+fn map_attachments(data: &mut Value, f: fn(&str) -> String) {
+    let attachments = data.get_mut("_attachments").and_then(Value::as_object_mut);
+    for attachment in attachments
+        .into_iter()
+        .flat_map(|attachments| attachments.values_mut())
+    {
+        if let Some(base64) = attachment["data"].as_str() {
+            let text = String::from_utf8(BASE64_STANDARD.decode(base64).unwrap()).unwrap();
+            attachment["data"] = json!(BASE64_STANDARD.encode(f(&text)));
+        }
+    }
+}
+
+fn title(doc: SerializedDocument) -> Value {
+    JsonDocument::try_from(doc).unwrap().data["title"].clone()
+}
+
+async fn text(blob: &Blob) -> String {
+    JsFuture::from(blob.text())
+        .await
+        .unwrap()
+        .as_string()
+        .unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn pouchdb_paths_are_transformed() {
+    let mut db = memory_db("transforms");
+    db.add_transform(Shout);
+
+    db.put(&JsonDocument::new("a", json!({ "title": "Hello" })), false)
+        .await
+        .unwrap();
+    let attachments = [(
+        "note.txt".to_owned(),
+        ("text/plain".to_owned(), b"hi".to_vec()),
+    )];
+    let with_attachment = SerializedDocumentData {
+        id: "b".to_owned(),
+        attachments: attachments.iter().cloned().collect::<HashMap<_, _>>(),
+        data: json!({ "title": "Hello" }),
+    };
+    db.put(&with_attachment, false).await.unwrap();
+    let created = db
+        .put(&JsonDocument::new("c", json!({ "title": "Hi" })), false)
+        .await
+        .unwrap();
+    // A deletion keeping its fields.
+    let tombstone = SerializedDocumentData {
+        id: "c".to_owned(),
+        attachments: HashMap::new(),
+        data: json!({ "_rev": created.rev, "_deleted": true, "title": "Bye" }),
+    };
+    let deleted = db.put(&tombstone, false).await.unwrap();
+
+    // Attachments are passed to transforms as base64, and stored as `Blob`s again.
+    let stored = db.get_attachment("b", "note.txt", None).await.unwrap();
+    assert_eq!(text(&stored).await, "HI");
+    let fetched = db
+        .fetch("b", &FetchOptions::default().attachments(true))
+        .await
+        .unwrap();
+    match &fetched.attachments["note.txt"] {
+        Attachment::Data { blob, .. } => assert_eq!(text(blob).await, "hi"),
+        attachment => panic!("unexpected attachment {:?}", attachment),
+    }
+    assert_eq!(title(fetched), "hello");
+    let fetched = db
+        .fetch("c", &FetchOptions::default().rev(deleted.rev.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(title(fetched), "bye");
+
+    let all_docs = db
+        .all_docs(&AllDocsOptions {
+            include_docs: true,
+            ..AllDocsOptions::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(all_docs.rows.len(), 2);
+    for row in all_docs.rows {
+        match row.value {
+            AllDocsValue::Found { doc, .. } => assert_eq!(title(doc.unwrap()), "hello"),
+            value => panic!("unexpected row {:?}", value),
+        }
+    }
+
+    let options = QueryOptions {
+        include_docs: true,
+        ..QueryOptions::default()
+    };
+    let query = db
+        .query::<String, Value>("emit(document._id, null)", options.clone())
+        .await
+        .unwrap();
+    let query_with = db
+        .query_with::<String, Value, _>(
+            |doc, emitter| emitter.emit(doc["_id"].clone(), Value::Null),
+            options,
+        )
+        .await
+        .unwrap();
+    for row in query.rows.into_iter().chain(query_with.rows) {
+        assert_eq!(title(row.doc.unwrap()), "hello");
+    }
+
+    let found = db
+        .find(&FindOptions {
+            selector: Selector::default().field("stored", "HELLO"),
+            ..FindOptions::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(found.docs.len(), 2);
+    for doc in found.docs {
+        assert_eq!(title(doc), "hello");
+    }
+    // Projections without `_id` are returned as they're stored.
+    let projected = db
+        .find(&FindOptions {
+            selector: Selector::default().field("stored", "HELLO"),
+            fields: vec!["stored".to_owned()],
+            ..FindOptions::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(projected.docs[0].id, "");
+    let projected = js_sys::JSON::stringify(&projected.docs[0].data).unwrap();
+    assert_eq!(projected, r#"{"stored":"HELLO"}"#);
+
+    let changes = Changes {
+        include_docs: true,
+        ..Changes::default()
+    };
+    let (results, _) = db.changes_oneshot(&changes).await.unwrap();
+    let titles = results
+        .into_iter()
+        .map(|change| title(change.doc.unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(titles, [json!("hello"), json!("hello"), json!("bye")]);
+
+    let emitter = db.changes(&changes).unwrap();
+    let (sender, receiver) = mpsc::unbounded();
+    let _listener = emitter
+        .add_change_listener(move |change| {
+            sender.unbounded_send(change).ok();
+        })
+        .unwrap();
+    let titles = receiver
+        .take(3)
+        .map(|change| title(change.unwrap().doc.unwrap()))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(titles, [json!("hello"), json!("hello"), json!("bye")]);
+    emitter.cancel();
+
+    db.destroy().await.unwrap();
+}